@@ -0,0 +1,137 @@
+//! Listing the comments of reviewed PDFs.
+//!
+//! Markup annotations are the ones readers add while reviewing, like notes, highlights and
+//! stamps. Other annotations, like links and form widgets, are only counted.
+
+use std::collections::BTreeMap;
+
+use lopdf::{Dictionary, Document, Object};
+use serde::Serialize;
+
+/// Annotation types that are comments, the markup annotations of the PDF specification.
+const MARKUP: &[&[u8]] = &[
+    b"Text",
+    b"FreeText",
+    b"Line",
+    b"Square",
+    b"Circle",
+    b"Polygon",
+    b"PolyLine",
+    b"Highlight",
+    b"Underline",
+    b"Squiggly",
+    b"StrikeOut",
+    b"Stamp",
+    b"Caret",
+    b"Ink",
+    b"FileAttachment",
+    b"Sound",
+    b"Redact",
+];
+
+/// A comment on a page.
+#[derive(Debug, Serialize)]
+pub struct Comment {
+    pub page: u32,
+    #[serde(rename = "type")]
+    pub subtype: String,
+    pub author: Option<String>,
+    pub contents: Option<String>,
+    /// When it was last modified, as ISO 8601 when the date could be read
+    pub date: Option<String>,
+}
+
+/// The comments of `doc` in page order, and how many other annotations there are of every type.
+/// Popups are left out, they only show the comment they belong to.
+pub fn comments(doc: &Document) -> (Vec<Comment>, BTreeMap<String, usize>) {
+    let mut comments = Vec::new();
+    let mut other = BTreeMap::new();
+
+    for (page, page_id) in doc.get_pages() {
+        let annotations = doc
+            .get_dictionary(page_id)
+            .and_then(|page| page.get(b"Annots"))
+            .and_then(|annotations| doc.dereference(annotations))
+            .and_then(|(_, annotations)| annotations.as_array())
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+
+        for annotation in annotations {
+            let Ok((_, Object::Dictionary(annotation))) = doc.dereference(annotation) else {
+                continue;
+            };
+            let subtype = annotation
+                .get(b"Subtype")
+                .and_then(Object::as_name)
+                .unwrap_or(b"Unknown");
+            if subtype == b"Popup" {
+                continue;
+            }
+
+            let subtype_name = String::from_utf8_lossy(subtype).into_owned();
+            if !MARKUP.contains(&subtype) {
+                *other.entry(subtype_name).or_default() += 1;
+                continue;
+            }
+
+            comments.push(Comment {
+                page,
+                subtype: subtype_name,
+                author: text(doc, annotation, b"T"),
+                contents: text(doc, annotation, b"Contents"),
+                date: text(doc, annotation, b"M")
+                    .or_else(|| text(doc, annotation, b"CreationDate"))
+                    .map(|date| iso_date(&date).unwrap_or(date)),
+            });
+        }
+    }
+
+    (comments, other)
+}
+
+/// A text string entry of `dict`, empty strings count as missing.
+fn text(doc: &Document, dict: &Dictionary, key: &[u8]) -> Option<String> {
+    let (_, object) = doc.dereference(dict.get(key).ok()?).ok()?;
+    let text = crate::decode_text_string(object.as_str().ok()?);
+
+    (!text.trim().is_empty()).then_some(text)
+}
+
+/// Turn a PDF date like `D:20240131154500+01'00'` into `2024-01-31T15:45:00+01:00`. Everything
+/// after the year is optional.
+fn iso_date(date: &str) -> Option<String> {
+    let date = date.strip_prefix("D:").unwrap_or(date);
+    let digits = date
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(date.len());
+    let (numbers, zone) = date.split_at(digits);
+    if numbers.len() < 4 {
+        return None;
+    }
+
+    let part = |range: std::ops::Range<usize>, default: &'static str| {
+        numbers.get(range).unwrap_or(default).to_owned()
+    };
+    let mut iso = format!(
+        "{}-{}-{}T{}:{}:{}",
+        part(0..4, ""),
+        part(4..6, "01"),
+        part(6..8, "01"),
+        part(8..10, "00"),
+        part(10..12, "00"),
+        part(12..14, "00"),
+    );
+
+    match zone.chars().next() {
+        Some('Z') => iso.push('Z'),
+        Some(sign @ ('+' | '-')) => {
+            let offset = zone[1..].replace('\'', "");
+            let hours = offset.get(0..2)?;
+            let minutes = offset.get(2..4).unwrap_or("00");
+            iso.push_str(&format!("{sign}{hours}:{minutes}"));
+        }
+        _ => {}
+    }
+
+    Some(iso)
+}
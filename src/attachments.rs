@@ -0,0 +1,104 @@
+//! Files embedded in PDFs, either in the `/EmbeddedFiles` name tree of the catalog or in file
+//! attachment annotations on the pages.
+
+use std::collections::BTreeMap;
+
+use lopdf::{Document, Object, ObjectId};
+
+use crate::names;
+
+/// The streams holding embedded files, with their stored size in bytes.
+fn embedded_file_streams(doc: &Document) -> BTreeMap<ObjectId, u64> {
+    doc.objects
+        .iter()
+        .filter_map(|(id, object)| {
+            let stream = object.as_stream().ok()?;
+            let is_file =
+                stream.dict.get(b"Type").and_then(Object::as_name).ok() == Some(b"EmbeddedFile");
+            is_file.then_some((*id, stream.content.len() as u64))
+        })
+        .collect()
+}
+
+/// Remove every embedded file from `doc`, along with the file attachment annotations that show
+/// them on the pages. Returns the number of removed attachments and the bytes they took up.
+pub fn strip_attachments(doc: &mut Document) -> (usize, u64) {
+    let streams = embedded_file_streams(doc);
+    let mut removed = 0;
+
+    let names_id = doc
+        .catalog()
+        .ok()
+        .and_then(|catalog| catalog.get(b"Names").ok())
+        .and_then(|names| names.as_reference().ok());
+    let names = match names_id {
+        Some(id) => doc.get_dictionary_mut(id).ok(),
+        None => doc
+            .catalog_mut()
+            .ok()
+            .and_then(|catalog| catalog.get_mut(b"Names").ok())
+            .and_then(|names| names.as_dict_mut().ok()),
+    };
+    if let Some(tree) = names.and_then(|names| names.remove(b"EmbeddedFiles")) {
+        if let Ok((_, Object::Dictionary(tree))) = doc.dereference(&tree) {
+            removed += names::tree_entries(doc, tree).len();
+        }
+    }
+
+    if let Ok(catalog) = doc.catalog_mut() {
+        // A portfolio without its files is just the cover page
+        catalog.remove(b"Collection");
+        if catalog.get(b"PageMode").and_then(Object::as_name).ok() == Some(b"UseAttachments") {
+            catalog.remove(b"PageMode");
+        }
+    }
+
+    for (_, page_id) in doc.get_pages() {
+        let Ok(annots) = doc
+            .get_dictionary(page_id)
+            .and_then(|page| page.get(b"Annots"))
+            .and_then(|annots| doc.dereference(annots))
+            .and_then(|(_, annots)| annots.as_array())
+        else {
+            continue;
+        };
+
+        let kept = annots
+            .iter()
+            .filter(|annot| {
+                let Ok((_, Object::Dictionary(annot))) = doc.dereference(annot) else {
+                    return true;
+                };
+                annot.get(b"Subtype").and_then(Object::as_name).ok() != Some(b"FileAttachment")
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+
+        if kept.len() == annots.len() {
+            continue;
+        }
+        removed += annots.len() - kept.len();
+
+        // Set the filtered array on the page itself, the original might be shared
+        if let Ok(page) = doc.get_dictionary_mut(page_id) {
+            if kept.is_empty() {
+                page.remove(b"Annots");
+            } else {
+                page.set("Annots", kept);
+            }
+        }
+    }
+
+    if streams.is_empty() && removed == 0 {
+        return (0, 0);
+    }
+
+    doc.prune_objects();
+    let bytes = streams
+        .iter()
+        .filter(|(id, _)| !doc.objects.contains_key(id))
+        .map(|(_, size)| size)
+        .sum();
+
+    (removed, bytes)
+}
@@ -0,0 +1,302 @@
+//! Helpers to combine entries of the document catalogs of merged PDFs.
+//!
+//! All of these work on the pooled objects of the merge, after every input has been renumbered
+//! into its own range of object ids, so references from different inputs never collide.
+
+use std::collections::BTreeMap;
+
+use lopdf::{Dictionary, Object, ObjectId};
+
+/// Follow a reference into the pooled objects, other objects are returned as they are.
+pub fn resolve<'a>(objects: &'a BTreeMap<ObjectId, Object>, object: &'a Object) -> &'a Object {
+    match object {
+        Object::Reference(id) => objects.get(id).unwrap_or(object),
+        _ => object,
+    }
+}
+
+/// Get an entry of a catalog, following a reference to it.
+pub fn catalog_entry<'a>(
+    objects: &'a BTreeMap<ObjectId, Object>,
+    catalog: ObjectId,
+    key: &[u8],
+) -> Option<&'a Object> {
+    let catalog = objects.get(&catalog)?.as_dict().ok()?;
+
+    Some(resolve(objects, catalog.get(key).ok()?))
+}
+
+/// Get the array stored under `key`, which can be stored indirectly. Missing arrays are empty.
+fn array<'a>(
+    objects: &'a BTreeMap<ObjectId, Object>,
+    dictionary: &'a Dictionary,
+    key: &[u8],
+) -> &'a [Object] {
+    dictionary
+        .get(key)
+        .map(|array| resolve(objects, array))
+        .and_then(Object::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+}
+
+/// Combine the optional content (layer) configuration of all `catalogs`, so the layers of every
+/// merged PDF stay toggleable.
+///
+/// The configuration of the first PDF with layers is the base, the groups of the other PDFs are
+/// added to it together with their default visibility and ordering.
+pub fn merge_oc_properties(
+    objects: &BTreeMap<ObjectId, Object>,
+    catalogs: &[ObjectId],
+) -> Option<Dictionary> {
+    let mut merged: Option<Dictionary> = None;
+
+    for catalog in catalogs {
+        let Some(Ok(properties)) =
+            catalog_entry(objects, *catalog, b"OCProperties").map(Object::as_dict)
+        else {
+            continue;
+        };
+
+        let config = properties
+            .get(b"D")
+            .map(|config| resolve(objects, config))
+            .and_then(Object::as_dict)
+            .cloned()
+            .unwrap_or_default();
+
+        let Some(merged) = merged.as_mut() else {
+            let mut base = properties.clone();
+            base.set("OCGs", array(objects, properties, b"OCGs").to_vec());
+            base.set("D", config);
+            merged = Some(base);
+            continue;
+        };
+
+        let groups = array(objects, properties, b"OCGs");
+        let mut merged_groups = array(objects, merged, b"OCGs").to_vec();
+        merged_groups.extend_from_slice(groups);
+
+        let Ok(Object::Dictionary(merged_config)) = merged.get_mut(b"D") else {
+            continue;
+        };
+
+        let base_state = |config: &Dictionary| {
+            config
+                .get(b"BaseState")
+                .and_then(Object::as_name)
+                .map(<[u8]>::to_vec)
+                .unwrap_or_else(|_| b"ON".to_vec())
+        };
+
+        let mut lists = [&b"ON"[..], b"OFF", b"Order", b"RBGroups", b"Locked"]
+            .map(|key| (key, array(objects, merged_config, key).to_vec()));
+
+        for (key, list) in lists.iter_mut() {
+            list.extend_from_slice(array(objects, &config, key));
+        }
+
+        // Groups that are only hidden (or shown) through their base state have to be listed
+        // explicitly when the base state of the merged configuration is different
+        let (source_base, merged_base) = (base_state(&config), base_state(merged_config));
+        if source_base != merged_base {
+            let (explicit, implicit) = if source_base == b"OFF" {
+                (&b"ON"[..], 1)
+            } else {
+                (&b"OFF"[..], 0)
+            };
+            let listed = array(objects, &config, explicit);
+
+            lists[implicit].1.extend(
+                groups
+                    .iter()
+                    .filter(|group| !listed.contains(group))
+                    .cloned(),
+            );
+        }
+
+        for (key, list) in lists {
+            if !list.is_empty() {
+                merged_config.set(key, list);
+            }
+        }
+
+        merged.set("OCGs", merged_groups);
+    }
+
+    merged
+}
+
+/// Combine the interactive forms of all `catalogs`, so the fields of every merged PDF keep
+/// working. Fields with clashing names have to be renamed before, see [`crate::forms`].
+///
+/// The form of the first PDF with one is the base, the fields and default resources of the
+/// others are added to it. XFA forms are dropped, they would describe only one of the inputs.
+/// The calculation orders are joined in the order of the PDFs, calculated fields only depend on
+/// fields of their own PDF.
+pub fn merge_acro_forms(
+    objects: &BTreeMap<ObjectId, Object>,
+    catalogs: &[ObjectId],
+) -> Option<Dictionary> {
+    let mut merged: Option<Dictionary> = None;
+
+    for catalog in catalogs {
+        let Some(Ok(form)) = catalog_entry(objects, *catalog, b"AcroForm").map(Object::as_dict)
+        else {
+            continue;
+        };
+
+        let resources = form
+            .get(b"DR")
+            .map(|resources| resolve(objects, resources))
+            .and_then(Object::as_dict)
+            .cloned()
+            .unwrap_or_default();
+
+        let Some(merged) = merged.as_mut() else {
+            let mut base = form.clone();
+            base.set("Fields", array(objects, form, b"Fields").to_vec());
+            if form.has(b"CO") {
+                base.set("CO", array(objects, form, b"CO").to_vec());
+            }
+            base.set("DR", resources);
+            base.remove(b"XFA");
+            merged = Some(base);
+            continue;
+        };
+
+        let mut fields = array(objects, merged, b"Fields").to_vec();
+        fields.extend_from_slice(array(objects, form, b"Fields"));
+        merged.set("Fields", fields);
+
+        let mut order = array(objects, merged, b"CO").to_vec();
+        order.extend_from_slice(array(objects, form, b"CO"));
+        if !order.is_empty() {
+            merged.set("CO", order);
+        }
+
+        // Appearances have to be generated when any of the inputs needs it
+        if form
+            .get(b"NeedAppearances")
+            .and_then(Object::as_bool)
+            .unwrap_or(false)
+        {
+            merged.set("NeedAppearances", true);
+        }
+
+        let flags = |form: &Dictionary| form.get(b"SigFlags").and_then(Object::as_i64).unwrap_or(0);
+        let sig_flags = flags(merged) | flags(form);
+        if sig_flags != 0 {
+            merged.set("SigFlags", sig_flags);
+        }
+
+        // Resources under the same name are usually the same standard font, the first one wins
+        let Ok(Object::Dictionary(merged_resources)) = merged.get_mut(b"DR") else {
+            continue;
+        };
+        for (category, entries) in resources.iter() {
+            let Ok(entries) = resolve(objects, entries).as_dict() else {
+                continue;
+            };
+
+            let mut merged_entries = merged_resources
+                .get(category)
+                .map(|entries| resolve(objects, entries))
+                .and_then(Object::as_dict)
+                .cloned()
+                .unwrap_or_default();
+            for (name, entry) in entries.iter() {
+                if !merged_entries.has(name) {
+                    merged_entries.set(name.clone(), entry.clone());
+                }
+            }
+
+            merged_resources.set(category.clone(), merged_entries);
+        }
+    }
+
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lopdf::dictionary;
+
+    /// The base state of a layer configuration, its groups and the groups it lists as ON and OFF,
+    /// all by object number.
+    type Config<'a> = (&'a str, &'a [u32], &'a [u32], &'a [u32]);
+
+    /// Pooled objects with a catalog for every configuration, the first catalog is object 1.
+    fn layered(inputs: &[Config]) -> BTreeMap<ObjectId, Object> {
+        let refs = |ids: &[u32]| -> Vec<Object> { ids.iter().map(|id| (*id, 0).into()).collect() };
+        let mut objects = BTreeMap::new();
+
+        for (n, (base, groups, on, off)) in inputs.iter().enumerate() {
+            for group in *groups {
+                objects.insert(
+                    (*group, 0),
+                    Object::Dictionary(dictionary! { "Type" => "OCG" }),
+                );
+            }
+            let catalog = dictionary! {
+                "Type" => "Catalog",
+                "OCProperties" => dictionary! {
+                    "OCGs" => refs(groups),
+                    "D" => dictionary! {
+                        "BaseState" => *base,
+                        "ON" => refs(on),
+                        "OFF" => refs(off),
+                    },
+                },
+            };
+            objects.insert((n as u32 + 1, 0), Object::Dictionary(catalog));
+        }
+
+        objects
+    }
+
+    fn listed(config: &Dictionary, key: &[u8]) -> Vec<u32> {
+        config
+            .get(key)
+            .and_then(Object::as_array)
+            .unwrap()
+            .iter()
+            .map(|id| id.as_reference().unwrap().0)
+            .collect()
+    }
+
+    #[test]
+    fn layers_hidden_by_base_state_stay_hidden() {
+        // The first PDF shows its layers by default, the second hides all but 201
+        let objects = layered(&[
+            ("ON", &[100, 101], &[], &[101]),
+            ("OFF", &[200, 201, 202], &[201], &[]),
+        ]);
+
+        let merged = merge_oc_properties(&objects, &[(1, 0), (2, 0)]).unwrap();
+        let config = merged.get(b"D").and_then(Object::as_dict).unwrap();
+
+        assert_eq!(listed(&merged, b"OCGs"), [100, 101, 200, 201, 202]);
+        assert_eq!(
+            config.get(b"BaseState").and_then(Object::as_name).unwrap(),
+            b"ON"
+        );
+        assert_eq!(listed(config, b"OFF"), [101, 200, 202]);
+        assert_eq!(listed(config, b"ON"), [201]);
+    }
+
+    #[test]
+    fn layers_shown_by_base_state_stay_shown() {
+        let objects = layered(&[
+            ("OFF", &[100, 101], &[100], &[]),
+            ("ON", &[200, 201], &[], &[200]),
+        ]);
+
+        let merged = merge_oc_properties(&objects, &[(1, 0), (2, 0)]).unwrap();
+        let config = merged.get(b"D").and_then(Object::as_dict).unwrap();
+
+        assert_eq!(listed(config, b"ON"), [100, 201]);
+        assert_eq!(listed(config, b"OFF"), [200]);
+    }
+}
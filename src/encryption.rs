@@ -0,0 +1,196 @@
+//! Encryption with the PDF standard security handler, revision 3 (128 bit RC4).
+//!
+//! lopdf can decrypt documents but not encrypt them. Revision 3 is the most widely supported
+//! scheme that can be written with nothing more than MD5 and RC4, it is meant to keep casual
+//! readers out rather than to withstand a determined attacker.
+
+use lopdf::{dictionary, Document, Object, StringFormat};
+
+/// Padding string from the PDF specification, used to pad passwords to 32 bytes.
+const PAD: [u8; 32] = [
+    0x28, 0xBF, 0x4E, 0x5E, 0x4E, 0x75, 0x8A, 0x41, 0x64, 0x00, 0x4E, 0x56, 0xFF, 0xFA, 0x01, 0x08,
+    0x2E, 0x2E, 0x00, 0xB6, 0xD0, 0x68, 0x3E, 0x80, 0x2F, 0x0C, 0xA9, 0xFE, 0x64, 0x53, 0x69, 0x7A,
+];
+
+/// Length of the file encryption key in bytes.
+const KEY_LEN: usize = 16;
+
+/// Permissions of the encrypted document, everything is allowed.
+const PERMISSIONS: i32 = -4;
+
+fn rc4(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut state: [u8; 256] = std::array::from_fn(|i| i as u8);
+
+    let mut j = 0u8;
+    for i in 0..256 {
+        j = j.wrapping_add(state[i]).wrapping_add(key[i % key.len()]);
+        state.swap(i, j as usize);
+    }
+
+    let (mut i, mut j) = (0u8, 0u8);
+    data.iter()
+        .map(|byte| {
+            i = i.wrapping_add(1);
+            j = j.wrapping_add(state[i as usize]);
+            state.swap(i as usize, j as usize);
+            byte ^ state[state[i as usize].wrapping_add(state[j as usize]) as usize]
+        })
+        .collect()
+}
+
+fn pad_password(password: &[u8]) -> Vec<u8> {
+    password
+        .iter()
+        .chain(PAD.iter())
+        .take(32)
+        .copied()
+        .collect()
+}
+
+/// RC4 encrypt `data` 20 times, with the key xor-ed with the iteration number (revision 3).
+fn rc4_rounds(key: &[u8], data: &[u8]) -> Vec<u8> {
+    (0..20u8).fold(data.to_vec(), |data, round| {
+        let key = key.iter().map(|k| k ^ round).collect::<Vec<_>>();
+        rc4(&key, &data)
+    })
+}
+
+/// Hash `data`, then hash the result 50 more times (revision 3).
+fn md5_rounds(data: &[u8]) -> [u8; 16] {
+    (0..50).fold(md5::compute(data).0, |hash, _| md5::compute(hash).0)
+}
+
+/// Compute the `/O` entry from the owner and user password (algorithm 3).
+fn owner_entry(owner: &[u8], user: &[u8]) -> Vec<u8> {
+    let key = md5_rounds(&pad_password(owner));
+
+    rc4_rounds(&key, &pad_password(user))
+}
+
+/// Compute the file encryption key (algorithm 2).
+fn file_key(user: &[u8], owner_entry: &[u8], id: &[u8]) -> [u8; KEY_LEN] {
+    let mut input = pad_password(user);
+    input.extend_from_slice(owner_entry);
+    input.extend_from_slice(&PERMISSIONS.to_le_bytes());
+    input.extend_from_slice(id);
+
+    md5_rounds(&input)
+}
+
+/// Compute the `/U` entry from the file key (algorithm 5).
+fn user_entry(key: &[u8], id: &[u8]) -> Vec<u8> {
+    let mut input = PAD.to_vec();
+    input.extend_from_slice(id);
+
+    let mut entry = rc4_rounds(key, &md5::compute(input).0);
+    // Only the first 16 bytes are checked, the rest is arbitrary padding
+    entry.resize(32, 0);
+    entry
+}
+
+/// Key for a single object (algorithm 1).
+fn object_key(key: &[u8], (id, generation): (u32, u16)) -> Vec<u8> {
+    let mut input = key.to_vec();
+    input.extend_from_slice(&id.to_le_bytes()[..3]);
+    input.extend_from_slice(&generation.to_le_bytes());
+
+    md5::compute(input).0[..(KEY_LEN + 5).min(16)].to_vec()
+}
+
+fn encrypt_strings(object: &mut Object, key: &[u8]) {
+    match object {
+        Object::String(bytes, _) => *bytes = rc4(key, bytes),
+        Object::Array(array) => array.iter_mut().for_each(|o| encrypt_strings(o, key)),
+        Object::Dictionary(dict) => dict.iter_mut().for_each(|(_, o)| encrypt_strings(o, key)),
+        Object::Stream(stream) => {
+            stream
+                .dict
+                .iter_mut()
+                .for_each(|(_, o)| encrypt_strings(o, key));
+            let content = rc4(key, &stream.content);
+            stream.set_content(content);
+        }
+        _ => {}
+    }
+}
+
+/// Make sure the trailer has a file identifier, which is part of the encryption key.
+/// Returns the first element of the identifier.
+///
+/// A new identifier is random unless `deterministic` is set, then it is the same for every
+/// file. That makes the encrypted output reproducible, which is only meant for testing: every
+/// file encrypted with the same password then also has the same key.
+fn ensure_id(doc: &mut Document, deterministic: bool) -> Vec<u8> {
+    let existing = doc
+        .trailer
+        .get(b"ID")
+        .and_then(Object::as_array)
+        .ok()
+        .and_then(|id| id.first())
+        .and_then(|id| id.as_str().ok())
+        .map(<[u8]>::to_vec);
+
+    if let Some(id) = existing {
+        return id;
+    }
+
+    let seed = if deterministic {
+        "pdft deterministic id".to_string()
+    } else {
+        format!(
+            "{:?}{}{}",
+            std::time::SystemTime::now(),
+            std::process::id(),
+            doc.objects.len()
+        )
+    };
+    let id = md5::compute(seed).0.to_vec();
+
+    doc.trailer.set(
+        "ID",
+        vec![
+            Object::String(id.clone(), StringFormat::Hexadecimal),
+            Object::String(id.clone(), StringFormat::Hexadecimal),
+        ],
+    );
+
+    id
+}
+
+/// Encrypt every string and stream in `doc`, which must not be encrypted already.
+///
+/// Any compression has to happen before this, encrypted streams do not compress. See
+/// [`ensure_id`] for `deterministic_id`.
+pub fn encrypt(
+    doc: &mut Document,
+    user_password: &str,
+    owner_password: &str,
+    deterministic_id: bool,
+) {
+    let id = ensure_id(doc, deterministic_id);
+
+    let owner = owner_entry(owner_password.as_bytes(), user_password.as_bytes());
+    let key = file_key(user_password.as_bytes(), &owner, &id);
+    let user = user_entry(&key, &id);
+
+    for (object_id, object) in doc.objects.iter_mut() {
+        // Cross-reference streams are never encrypted
+        if object.type_name().ok() == Some("XRef") {
+            continue;
+        }
+
+        encrypt_strings(object, &object_key(&key, *object_id));
+    }
+
+    let encrypt_id = doc.add_object(dictionary! {
+        "Filter" => "Standard",
+        "V" => 2,
+        "R" => 3,
+        "Length" => (KEY_LEN * 8) as i64,
+        "O" => Object::String(owner, StringFormat::Hexadecimal),
+        "U" => Object::String(user, StringFormat::Hexadecimal),
+        "P" => PERMISSIONS,
+    });
+
+    doc.trailer.set("Encrypt", encrypt_id);
+}
@@ -0,0 +1,69 @@
+//! Errors that callers can tell apart, for the failures that are worth handling differently
+//! than by showing the message. Everything else stays a plain `anyhow` error with context.
+//!
+//! They are returned wrapped in an `anyhow::Error`, use `downcast_ref::<PdftError>()` to get them
+//! back.
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum PdftError {
+    #[error("File not found: {name}")]
+    FileNotFound {
+        name: String,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("{name} does not look like a PDF ({reason})")]
+    InvalidPdf { name: String, reason: &'static str },
+
+    /// The file looks like a PDF, but lopdf can't parse it.
+    #[error("Failed to read {name}")]
+    Unreadable {
+        name: String,
+        #[source]
+        source: lopdf::Error,
+    },
+
+    #[error("{name} is encrypted, decrypt it first")]
+    Encrypted { name: String },
+
+    #[error("{name} does not have any pages")]
+    NoPages { name: String },
+
+    /// Some of the files of a `--keep-going` run failed.
+    #[error("{failed} of {total} files failed")]
+    SomeFailed { failed: usize, total: usize },
+
+    /// All files of a `--keep-going` run failed.
+    #[error("None of the {total} files could be processed")]
+    NothingProcessed { total: usize },
+}
+
+impl PdftError {
+    /// A short name of the variant, for machine readable output like `--errors-json`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            PdftError::FileNotFound { .. } => "io",
+            PdftError::InvalidPdf { .. } => "invalid_pdf",
+            PdftError::Unreadable { .. } => "unreadable",
+            PdftError::Encrypted { .. } => "encrypted",
+            PdftError::NoPages { .. } => "no_pages",
+            PdftError::SomeFailed { .. } => "some_failed",
+            PdftError::NothingProcessed { .. } => "nothing_processed",
+        }
+    }
+
+    /// The file the error is about, if it is about a single file.
+    pub fn file(&self) -> Option<&str> {
+        match self {
+            PdftError::FileNotFound { name, .. }
+            | PdftError::InvalidPdf { name, .. }
+            | PdftError::Unreadable { name, .. }
+            | PdftError::Encrypted { name }
+            | PdftError::NoPages { name } => Some(name),
+            PdftError::SomeFailed { .. } | PdftError::NothingProcessed { .. } => None,
+        }
+    }
+}
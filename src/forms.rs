@@ -0,0 +1,48 @@
+//! Interactive form fields (AcroForm) of merged PDFs.
+//!
+//! Fields with the same fully qualified name are the same field, sharing one value. When merging,
+//! fields of different inputs that happen to share a name have to be renamed to stay separate.
+
+use lopdf::{Document, Object, ObjectId};
+
+/// The top level fields of the form of `doc`, the names of all other fields are relative to these.
+pub fn top_level_fields(doc: &Document) -> Vec<ObjectId> {
+    doc.catalog()
+        .ok()
+        .and_then(|catalog| catalog.get(b"AcroForm").ok())
+        .and_then(|form| doc.dereference(form).ok())
+        .and_then(|(_, form)| form.as_dict().ok())
+        .and_then(|form| form.get(b"Fields").ok())
+        .and_then(|fields| doc.dereference(fields).ok())
+        .and_then(|(_, fields)| fields.as_array().ok())
+        .map(|fields| {
+            fields
+                .iter()
+                .filter_map(|field| field.as_reference().ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Rename the top level fields of `doc`, `rename` gets the name of every field and returns its new
+/// name, or nothing to keep it. Fields nested in them follow along. Returns the number of renamed
+/// fields.
+pub fn rename_fields(doc: &mut Document, mut rename: impl FnMut(&str) -> Option<String>) -> usize {
+    let mut renamed = 0;
+
+    for id in top_level_fields(doc) {
+        let Ok(field) = doc.get_dictionary_mut(id) else {
+            continue;
+        };
+        let Ok(Object::String(name, _)) = field.get(b"T") else {
+            continue;
+        };
+
+        if let Some(name) = rename(&crate::decode_text_string(name)) {
+            field.set("T", crate::encode_text_string(&name));
+            renamed += 1;
+        }
+    }
+
+    renamed
+}
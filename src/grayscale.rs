@@ -0,0 +1,313 @@
+//! Conversion of color content to grayscale, for cheaper printing.
+//!
+//! Colors set with the device RGB and CMYK operators (and with `cs`/`sc` in an RGB or CMYK color
+//! space) are replaced with their gray value, and 8 bit RGB, CMYK and indexed images are
+//! converted pixel by pixel. Shadings, patterns and images that can't be decoded without an
+//! image codec (like JPEG) keep their colors.
+
+use lopdf::content::{Content, Operation};
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream};
+
+/// What was converted, and what had to be left alone.
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub content_streams: usize,
+    pub images: usize,
+    pub skipped_images: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Space {
+    Rgb,
+    Cmyk,
+}
+
+impl Space {
+    fn components(self) -> usize {
+        match self {
+            Space::Rgb => 3,
+            Space::Cmyk => 4,
+        }
+    }
+
+    /// Gray value (0 is black, 1 is white) of a color in this space.
+    fn gray(self, c: &[f32]) -> f32 {
+        let gray = match self {
+            Space::Rgb => 0.299 * c[0] + 0.587 * c[1] + 0.114 * c[2],
+            Space::Cmyk => {
+                let rgb = [0, 1, 2].map(|i| 1.0 - (c[i] + c[3]).min(1.0));
+                0.299 * rgb[0] + 0.587 * rgb[1] + 0.114 * rgb[2]
+            }
+        };
+
+        gray.clamp(0.0, 1.0)
+    }
+}
+
+/// Find out whether a color space is RGB or CMYK. Named spaces are looked up in `resources`.
+fn color_space(doc: &Document, resources: Option<&Dictionary>, space: &Object) -> Option<Space> {
+    let (_, space) = doc.dereference(space).ok()?;
+
+    match space {
+        Object::Name(name) => match name.as_slice() {
+            b"DeviceRGB" | b"RGB" | b"CalRGB" => Some(Space::Rgb),
+            b"DeviceCMYK" | b"CMYK" => Some(Space::Cmyk),
+            name => {
+                let spaces = resources?.get(b"ColorSpace").ok()?;
+                let (_, spaces) = doc.dereference(spaces).ok()?;
+                color_space(doc, None, spaces.as_dict().ok()?.get(name).ok()?)
+            }
+        },
+        Object::Array(array) => match array.first()?.as_name().ok()? {
+            b"CalRGB" => Some(Space::Rgb),
+            b"ICCBased" => {
+                let (_, profile) = doc.dereference(array.get(1)?).ok()?;
+                match profile
+                    .as_stream()
+                    .ok()?
+                    .dict
+                    .get(b"N")
+                    .and_then(Object::as_i64)
+                {
+                    Ok(3) => Some(Space::Rgb),
+                    Ok(4) => Some(Space::Cmyk),
+                    _ => None,
+                }
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn gray_operation(operator: &str, gray: f32) -> Operation {
+    Operation::new(operator, vec![Object::Real(gray)])
+}
+
+/// Convert the color operators of a content stream, returns whether anything changed.
+fn convert_operations(
+    doc: &Document,
+    resources: Option<&Dictionary>,
+    content: &mut Content,
+) -> bool {
+    // Color space of filling and stroking, saved and restored by q and Q
+    let mut state: (Option<Space>, Option<Space>) = (None, None);
+    let mut stack = Vec::new();
+    let mut changed = false;
+
+    for operation in content.operations.iter_mut() {
+        let numbers = operation
+            .operands
+            .iter()
+            .map(|o| o.as_float().ok())
+            .collect::<Option<Vec<_>>>();
+        let stroke = operation.operator.chars().all(|c| c.is_ascii_uppercase());
+
+        let replacement = match (operation.operator.as_str(), numbers) {
+            ("q", _) => {
+                stack.push(state);
+                None
+            }
+            ("Q", _) => {
+                state = stack.pop().unwrap_or_default();
+                None
+            }
+            ("rg" | "RG", Some(c)) if c.len() == 3 => {
+                let gray = Space::Rgb.gray(&c);
+                Some(gray_operation(if stroke { "G" } else { "g" }, gray))
+            }
+            ("k" | "K", Some(c)) if c.len() == 4 => {
+                let gray = Space::Cmyk.gray(&c);
+                Some(gray_operation(if stroke { "G" } else { "g" }, gray))
+            }
+            ("cs" | "CS", _) => {
+                let space = operation
+                    .operands
+                    .first()
+                    .and_then(|space| color_space(doc, resources, space));
+                if stroke {
+                    state.1 = space;
+                } else {
+                    state.0 = space;
+                }
+
+                space.map(|_| {
+                    Operation::new(
+                        &operation.operator,
+                        vec![Object::Name(b"DeviceGray".to_vec())],
+                    )
+                })
+            }
+            ("sc" | "scn" | "SC" | "SCN", Some(c)) => {
+                let space = if stroke { state.1 } else { state.0 };
+                space
+                    .filter(|space| space.components() == c.len())
+                    .map(|space| gray_operation(&operation.operator, space.gray(&c)))
+            }
+            _ => None,
+        };
+
+        if let Some(replacement) = replacement {
+            *operation = replacement;
+            changed = true;
+        }
+    }
+
+    changed
+}
+
+/// The color space of an image, or the base of its palette for indexed images.
+fn image_space(doc: &Document, space: &Object) -> Option<Space> {
+    match doc.dereference(space).ok()? {
+        (_, Object::Array(indexed))
+            if indexed.first().and_then(|n| n.as_name().ok()) == Some(b"Indexed") =>
+        {
+            color_space(doc, None, indexed.get(1)?)
+        }
+        _ => color_space(doc, None, space),
+    }
+}
+
+/// Convert an image to grayscale if it is an 8 bit RGB or CMYK image, or an indexed one.
+fn convert_image(doc: &Document, stream: &Stream) -> Option<Stream> {
+    let dict = &stream.dict;
+    if dict.has(b"Decode") {
+        return None;
+    }
+
+    let space_object = dict.get(b"ColorSpace").ok()?;
+    let mut converted = dict.clone();
+
+    // Indexed images only need their palette converted
+    if let Ok((_, Object::Array(indexed))) = doc.dereference(space_object) {
+        if indexed.first().and_then(|n| n.as_name().ok()) == Some(b"Indexed") {
+            let base = color_space(doc, None, indexed.get(1)?)?;
+            let palette = match doc.dereference(indexed.get(3)?).ok()?.1 {
+                Object::String(bytes, _) => bytes.clone(),
+                Object::Stream(palette) => crate::images::image_samples(palette)?,
+                _ => return None,
+            };
+            let palette = gray_samples(base, &palette);
+
+            converted.set(
+                "ColorSpace",
+                vec![
+                    Object::Name(b"Indexed".to_vec()),
+                    Object::Name(b"DeviceGray".to_vec()),
+                    indexed.get(2)?.clone(),
+                    Object::String(palette, lopdf::StringFormat::Hexadecimal),
+                ],
+            );
+            return Some(Stream::new(converted, stream.content.clone()));
+        }
+    }
+
+    // Indexed images work with any number of bits, the samples are only converted at 8 bits
+    if dict
+        .get(b"BitsPerComponent")
+        .and_then(Object::as_i64)
+        .ok()?
+        != 8
+    {
+        return None;
+    }
+
+    let space = color_space(doc, None, space_object)?;
+    let samples = gray_samples(space, &crate::images::image_samples(stream)?);
+
+    converted.set("ColorSpace", Object::Name(b"DeviceGray".to_vec()));
+    let mut converted = Stream::new(converted, Vec::new());
+    converted.set_plain_content(samples);
+    let _ = converted.compress();
+
+    Some(converted)
+}
+
+fn gray_samples(space: Space, samples: &[u8]) -> Vec<u8> {
+    samples
+        .chunks_exact(space.components())
+        .map(|pixel| {
+            let color = pixel.iter().map(|c| *c as f32 / 255.0).collect::<Vec<_>>();
+            (space.gray(&color) * 255.0).round() as u8
+        })
+        .collect()
+}
+
+/// Convert the color content of every page, form and image of `doc` to grayscale.
+pub fn convert(doc: &mut Document) -> Summary {
+    let mut summary = Summary::default();
+
+    for (_, page_id) in doc.get_pages() {
+        let Ok(mut content) = doc
+            .get_page_content(page_id)
+            .map_err(|_| ())
+            .and_then(|content| Content::decode(&content).map_err(|_| ()))
+        else {
+            continue;
+        };
+
+        let resources = crate::images::page_resources(doc, page_id);
+        if convert_operations(doc, resources, &mut content) {
+            if let Ok(content) = content.encode() {
+                if doc.change_page_content(page_id, content).is_ok() {
+                    summary.content_streams += 1;
+                }
+            }
+        }
+    }
+
+    let mut replacements: Vec<(ObjectId, Stream)> = Vec::new();
+
+    for (id, object) in doc.objects.iter() {
+        let Ok(stream) = object.as_stream() else {
+            continue;
+        };
+
+        match stream.dict.get(b"Subtype").and_then(Object::as_name) {
+            Ok(b"Form") => {
+                let data = crate::images::image_samples(stream);
+                let Some(mut content) = data.and_then(|data| Content::decode(&data).ok()) else {
+                    continue;
+                };
+
+                let resources = stream
+                    .dict
+                    .get(b"Resources")
+                    .and_then(|resources| doc.dereference(resources))
+                    .and_then(|(_, resources)| resources.as_dict())
+                    .ok();
+
+                if convert_operations(doc, resources, &mut content) {
+                    if let Ok(content) = content.encode() {
+                        let mut stream = stream.clone();
+                        stream.set_plain_content(content);
+                        let _ = stream.compress();
+                        replacements.push((*id, stream));
+                        summary.content_streams += 1;
+                    }
+                }
+            }
+            Ok(b"Image") => {
+                let space = stream.dict.get(b"ColorSpace").ok();
+                if space.and_then(|space| image_space(doc, space)).is_none() {
+                    continue;
+                }
+
+                match convert_image(doc, stream) {
+                    Some(converted) => {
+                        replacements.push((*id, converted));
+                        summary.images += 1;
+                    }
+                    None => summary.skipped_images += 1,
+                }
+            }
+            _ => {}
+        }
+    }
+
+    for (id, stream) in replacements {
+        doc.objects.insert(id, Object::Stream(stream));
+    }
+
+    summary
+}
@@ -0,0 +1,402 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use lopdf::content::Content;
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream};
+
+/// Form XObjects can nest other forms, stop following them at some point to avoid cycles.
+const MAX_FORM_DEPTH: usize = 8;
+
+/// A transformation matrix `[a b c d e f]` as used by the `cm` operator.
+pub type Matrix = [f32; 6];
+
+pub const IDENTITY: Matrix = [1.0, 0.0, 0.0, 1.0, 0.0, 0.0];
+
+/// Multiply two matrices, `m` is applied first and `n` second.
+pub fn multiply(m: &Matrix, n: &Matrix) -> Matrix {
+    [
+        m[0] * n[0] + m[1] * n[2],
+        m[0] * n[1] + m[1] * n[3],
+        m[2] * n[0] + m[3] * n[2],
+        m[2] * n[1] + m[3] * n[3],
+        m[4] * n[0] + m[5] * n[2] + n[4],
+        m[4] * n[1] + m[5] * n[3] + n[5],
+    ]
+}
+
+/// Read a 6 element number array (operands of `cm` or a form `/Matrix`) into a matrix.
+pub fn to_matrix(numbers: &[Object]) -> Option<Matrix> {
+    let numbers = numbers
+        .iter()
+        .map(|n| n.as_float().ok())
+        .collect::<Option<Vec<_>>>()?;
+
+    numbers.try_into().ok()
+}
+
+/// A single use of an image XObject on a page.
+#[derive(Debug, Clone)]
+pub struct ImagePlacement {
+    /// 1-based page number the image is drawn on
+    pub page: u32,
+    /// Resource name the image was drawn with, e.g. `Im1`
+    pub name: String,
+    pub id: ObjectId,
+    /// Size of the image in pixels
+    pub width: i64,
+    pub height: i64,
+    /// Size the image is drawn at on the page, in points
+    pub placed_width: f32,
+    pub placed_height: f32,
+}
+
+impl ImagePlacement {
+    /// Effective resolution of the image in dots per inch, horizontally and vertically.
+    pub fn dpi(&self) -> (f32, f32) {
+        let dpi = |pixels: i64, points: f32| {
+            if points > 0.0 {
+                pixels as f32 * 72.0 / points
+            } else {
+                0.0
+            }
+        };
+
+        (
+            dpi(self.width, self.placed_width),
+            dpi(self.height, self.placed_height),
+        )
+    }
+}
+
+/// Find the resources of a page, which can be inherited from one of its parents.
+pub fn page_resources(doc: &Document, page_id: ObjectId) -> Option<&Dictionary> {
+    crate::pages::inherited_ref(doc, page_id, b"Resources")?
+        .as_dict()
+        .ok()
+}
+
+/// List every image drawn on every page of `doc`, including images drawn from inside form
+/// XObjects. Images used multiple times are reported once per placement.
+pub fn image_placements(doc: &Document) -> Vec<ImagePlacement> {
+    let mut placements = Vec::new();
+
+    for (page, page_id) in doc.get_pages() {
+        let Ok(content) = doc.get_and_decode_page_content(page_id) else {
+            continue;
+        };
+
+        if let Some(resources) = page_resources(doc, page_id) {
+            collect_placements(doc, page, &content, resources, IDENTITY, 0, &mut placements);
+        }
+    }
+
+    placements
+}
+
+fn collect_placements(
+    doc: &Document,
+    page: u32,
+    content: &Content,
+    resources: &Dictionary,
+    ctm: Matrix,
+    depth: usize,
+    placements: &mut Vec<ImagePlacement>,
+) {
+    let xobjects = resources
+        .get(b"XObject")
+        .and_then(|xobjects| doc.dereference(xobjects))
+        .and_then(|(_, xobjects)| xobjects.as_dict())
+        .ok();
+
+    let mut stack = Vec::new();
+    let mut ctm = ctm;
+
+    for operation in &content.operations {
+        match operation.operator.as_str() {
+            "q" => stack.push(ctm),
+            "Q" => ctm = stack.pop().unwrap_or(ctm),
+            "cm" => {
+                if let Some(matrix) = to_matrix(&operation.operands) {
+                    ctm = multiply(&matrix, &ctm);
+                }
+            }
+            "Do" => {
+                let Some(name) = operation.operands.first().and_then(|n| n.as_name().ok()) else {
+                    continue;
+                };
+                let Some((Some(id), Object::Stream(xobject))) = xobjects
+                    .and_then(|xobjects| xobjects.get(name).ok())
+                    .and_then(|xobject| doc.dereference(xobject).ok())
+                else {
+                    continue;
+                };
+
+                match xobject.dict.get(b"Subtype").and_then(Object::as_name) {
+                    Ok(b"Image") => {
+                        let size = |key: &[u8]| {
+                            xobject.dict.get(key).and_then(Object::as_i64).unwrap_or(0)
+                        };
+
+                        // Images are drawn into the unit square, so the CTM scale is the placed size
+                        placements.push(ImagePlacement {
+                            page,
+                            name: String::from_utf8_lossy(name).into_owned(),
+                            id,
+                            width: size(b"Width"),
+                            height: size(b"Height"),
+                            placed_width: ctm[0].hypot(ctm[1]),
+                            placed_height: ctm[2].hypot(ctm[3]),
+                        });
+                    }
+                    Ok(b"Form") if depth < MAX_FORM_DEPTH => {
+                        // Streams without a filter can't be decompressed, but are usable as is
+                        let form = xobject
+                            .decompressed_content()
+                            .unwrap_or_else(|_| xobject.content.clone());
+                        let Ok(form) = Content::decode(&form) else {
+                            continue;
+                        };
+
+                        let matrix = xobject
+                            .dict
+                            .get(b"Matrix")
+                            .and_then(Object::as_array)
+                            .ok()
+                            .and_then(|matrix| to_matrix(matrix))
+                            .unwrap_or(IDENTITY);
+
+                        // Forms without resources of their own use the ones of the page
+                        let form_resources = xobject
+                            .dict
+                            .get(b"Resources")
+                            .and_then(|r| doc.dereference(r))
+                            .and_then(|(_, r)| r.as_dict())
+                            .unwrap_or(resources);
+
+                        collect_placements(
+                            doc,
+                            page,
+                            &form,
+                            form_resources,
+                            multiply(&matrix, &ctm),
+                            depth + 1,
+                            placements,
+                        );
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Decode the samples of an image stream, when that's possible without an image codec.
+pub fn image_samples(stream: &Stream) -> Option<Vec<u8>> {
+    if stream.filters().map(|f| f.is_empty()).unwrap_or(true) {
+        return Some(stream.content.clone());
+    }
+
+    // lopdf refuses to decode images, but Flate and LZW work the same for any stream
+    let mut dict = stream.dict.clone();
+    dict.remove(b"Subtype");
+    Stream::new(dict, stream.content.clone())
+        .decompressed_content()
+        .ok()
+}
+
+/// Number of components of every sample of an image in color space `space`, and whether the
+/// samples are indexes into a palette.
+fn sample_components(doc: &Document, space: &Object) -> Option<(usize, bool)> {
+    match doc.dereference(space).ok()?.1 {
+        Object::Name(name) => match name.as_slice() {
+            b"DeviceGray" | b"CalGray" | b"G" => Some((1, false)),
+            b"DeviceRGB" | b"CalRGB" | b"RGB" => Some((3, false)),
+            b"DeviceCMYK" | b"CMYK" => Some((4, false)),
+            _ => None,
+        },
+        Object::Array(array) => match array.first()?.as_name().ok()? {
+            b"Indexed" | b"I" => Some((1, true)),
+            b"CalGray" | b"Separation" => Some((1, false)),
+            b"CalRGB" | b"Lab" => Some((3, false)),
+            b"DeviceN" => Some((array.get(1)?.as_array().ok()?.len(), false)),
+            b"ICCBased" => {
+                let (_, profile) = doc.dereference(array.get(1)?).ok()?;
+                let n = profile
+                    .as_stream()
+                    .ok()?
+                    .dict
+                    .get(b"N")
+                    .ok()?
+                    .as_i64()
+                    .ok()?;
+                Some((usize::try_from(n).ok()?, false))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The source pixels that scaling `from` pixels to `to` pixels combines into pixel `i`.
+fn span(i: usize, from: usize, to: usize) -> Range<usize> {
+    let start = i * from / to;
+    start..((i + 1) * from / to).max(start + 1)
+}
+
+/// Scale an 8 bit image down to `scale` times its size, averaging the pixels that are combined.
+fn downsample_image(doc: &Document, stream: &Stream, scale: f32) -> Option<Stream> {
+    let dict = &stream.dict;
+    if dict.get(b"BitsPerComponent").ok()?.as_i64().ok()? != 8 {
+        return None;
+    }
+
+    let (components, indexed) = sample_components(doc, dict.get(b"ColorSpace").ok()?)?;
+    let width = usize::try_from(dict.get(b"Width").ok()?.as_i64().ok()?).ok()?;
+    let height = usize::try_from(dict.get(b"Height").ok()?.as_i64().ok()?).ok()?;
+
+    let scaled_width = ((width as f32 * scale).round() as usize).max(1);
+    let scaled_height = ((height as f32 * scale).round() as usize).max(1);
+    if scaled_width >= width && scaled_height >= height {
+        return None;
+    }
+
+    let samples = image_samples(stream)?;
+    if samples.len() < width * height * components {
+        return None;
+    }
+
+    let mut scaled = Vec::with_capacity(scaled_width * scaled_height * components);
+    for y in 0..scaled_height {
+        let rows = span(y, height, scaled_height);
+
+        for x in 0..scaled_width {
+            let columns = span(x, width, scaled_width);
+
+            for c in 0..components {
+                // Palette indexes can't be averaged, those take the first pixel instead
+                if indexed {
+                    scaled.push(samples[(rows.start * width + columns.start) * components + c]);
+                    continue;
+                }
+
+                let mut sum = 0;
+                for row in rows.clone() {
+                    for column in columns.clone() {
+                        sum += samples[(row * width + column) * components + c] as usize;
+                    }
+                }
+                let count = rows.len() * columns.len();
+                scaled.push(((sum + count / 2) / count) as u8);
+            }
+        }
+    }
+
+    let mut dict = dict.clone();
+    dict.set("Width", scaled_width as i64);
+    dict.set("Height", scaled_height as i64);
+
+    let mut scaled_stream = Stream::new(dict, Vec::new());
+    scaled_stream.set_plain_content(scaled);
+    let _ = scaled_stream.compress();
+
+    Some(scaled_stream)
+}
+
+/// Scale the images of `doc` down to `scale` (between 0 and 1) times their size. Only 8 bit
+/// images that can be decoded without an image codec are scaled, so not JPEG images. Returns the
+/// number of images that were scaled.
+pub fn downsample(doc: &mut Document, scale: f32) -> usize {
+    let replacements = doc
+        .objects
+        .iter()
+        .filter_map(|(id, object)| {
+            let stream = object.as_stream().ok()?;
+            if stream.dict.get(b"Subtype").and_then(Object::as_name).ok() != Some(b"Image") {
+                return None;
+            }
+
+            Some((*id, downsample_image(doc, stream, scale)?))
+        })
+        .collect::<Vec<_>>();
+
+    let scaled = replacements.len();
+    for (id, stream) in replacements {
+        doc.objects.insert(id, Object::Stream(stream));
+    }
+
+    scaled
+}
+
+/// An image that is drawn at a higher resolution than `--target-ppi`.
+#[derive(Debug)]
+pub struct Resampled {
+    pub id: ObjectId,
+    /// The first page the image is drawn on
+    pub page: u32,
+    /// Size in pixels before and after, nothing after when the image can't be scaled
+    pub before: (i64, i64),
+    pub after: Option<(i64, i64)>,
+    /// Resolution of the sharpest placement of the image before
+    pub ppi: f32,
+}
+
+impl Resampled {
+    /// Resolution of the sharpest placement of the image after scaling it.
+    pub fn ppi_after(&self) -> f32 {
+        match self.after {
+            Some((width, _)) => self.ppi * width as f32 / self.before.0 as f32,
+            None => self.ppi,
+        }
+    }
+}
+
+/// Scale every image of `doc` drawn at more than `target` pixels per inch down to that
+/// resolution. Images drawn several times are scaled for their sharpest placement, so none of
+/// the placements drop below `target`. Returns the images that were above it, in page order.
+pub fn downsample_to_ppi(doc: &mut Document, target: f32) -> Vec<Resampled> {
+    let mut sharpest: BTreeMap<ObjectId, (u32, i64, i64, f32)> = BTreeMap::new();
+    for placement in image_placements(doc) {
+        let (ppi_x, ppi_y) = placement.dpi();
+        let ppi = ppi_x.max(ppi_y);
+        let entry = sharpest.entry(placement.id).or_insert((
+            placement.page,
+            placement.width,
+            placement.height,
+            ppi,
+        ));
+        entry.3 = entry.3.max(ppi);
+    }
+
+    let mut resampled = sharpest
+        .into_iter()
+        .filter(|(_, (_, _, _, ppi))| *ppi > target)
+        .map(|(id, (page, width, height, ppi))| {
+            let scaled = doc
+                .get_object(id)
+                .and_then(Object::as_stream)
+                .ok()
+                .and_then(|stream| downsample_image(doc, stream, target / ppi));
+            let after = scaled.as_ref().and_then(|stream| {
+                Some((
+                    stream.dict.get(b"Width").ok()?.as_i64().ok()?,
+                    stream.dict.get(b"Height").ok()?.as_i64().ok()?,
+                ))
+            });
+            if let Some(stream) = scaled {
+                doc.objects.insert(id, Object::Stream(stream));
+            }
+
+            Resampled {
+                id,
+                page,
+                before: (width, height),
+                after,
+                ppi,
+            }
+        })
+        .collect::<Vec<_>>();
+    resampled.sort_by_key(|image| (image.page, image.id));
+
+    resampled
+}
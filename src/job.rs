@@ -0,0 +1,62 @@
+//! Merge jobs described in a JSON file, for scripts that would otherwise have to build a long
+//! command line. A job looks like:
+//!
+//! ```json
+//! {
+//!     "output": "packet.pdf",
+//!     "inputs": [
+//!         { "file": "cover.pdf" },
+//!         { "file": "scan.pdf", "pages": "2:-1", "rotate": 90 }
+//!     ]
+//! }
+//! ```
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Job {
+    /// Where to write the merged PDF, `--output` takes precedence
+    pub output: Option<String>,
+    pub inputs: Vec<Input>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Input {
+    pub file: String,
+    /// The pages to take, like `--pages` of extract, all of them by default
+    pub pages: Option<String>,
+    /// Degrees to turn the pages, added to their current rotation
+    pub rotate: Option<i64>,
+}
+
+/// Read and check the job in the JSON file `path`.
+pub fn read_job(path: &str) -> Result<Job> {
+    let json =
+        std::fs::read_to_string(path).with_context(|| format!("Failed to read job {path}"))?;
+    let job: Job = serde_json::from_str(&json).with_context(|| format!("Invalid job {path}"))?;
+
+    if job.inputs.is_empty() {
+        return Err(anyhow::anyhow!("Invalid job {path}: \"inputs\" is empty"));
+    }
+
+    for (i, input) in job.inputs.iter().enumerate() {
+        if Path::new(&input.file).is_dir() {
+            return Err(anyhow::anyhow!(
+                "Invalid job {path}: inputs[{i}].file {:?} is a directory, list its PDFs instead",
+                input.file
+            ));
+        }
+        if let Some(angle) = input.rotate.filter(|angle| angle % 90 != 0) {
+            return Err(anyhow::anyhow!(
+                "Invalid job {path}: inputs[{i}].rotate is {angle}, expected a multiple of 90"
+            ));
+        }
+    }
+
+    Ok(job)
+}
@@ -0,0 +1,342 @@
+//! Removing the content of hidden layers (optional content groups).
+//!
+//! Content is put in a layer by marking it with `/OC /Name BDC ... EMC`, where the name is looked
+//! up in the `/Properties` resources, or by giving a form or image XObject an `/OC` entry. A layer
+//! is hidden when the default configuration of the document turns it off.
+
+use std::collections::BTreeSet;
+
+use lopdf::content::Content;
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream};
+
+/// The layers the default configuration of `doc` turns off.
+fn hidden_groups(doc: &Document) -> BTreeSet<ObjectId> {
+    fn dict<'a>(doc: &'a Document, object: &'a Object) -> Option<&'a Dictionary> {
+        doc.dereference(object)
+            .and_then(|(_, object)| object.as_dict())
+            .ok()
+    }
+    let ids = |dict: &Dictionary, key: &[u8]| {
+        dict.get(key)
+            .and_then(|array| doc.dereference(array))
+            .and_then(|(_, array)| array.as_array())
+            .map(|array| {
+                array
+                    .iter()
+                    .filter_map(|id| id.as_reference().ok())
+                    .collect::<BTreeSet<_>>()
+            })
+            .unwrap_or_default()
+    };
+
+    let Some(properties) = doc
+        .catalog()
+        .ok()
+        .and_then(|catalog| catalog.get(b"OCProperties").ok())
+        .and_then(|properties| dict(doc, properties))
+    else {
+        return BTreeSet::new();
+    };
+    let Some(config) = properties
+        .get(b"D")
+        .ok()
+        .and_then(|config| dict(doc, config))
+    else {
+        return BTreeSet::new();
+    };
+
+    // Layers are on unless the configuration starts out with all of them off
+    match config.get(b"BaseState").and_then(Object::as_name) {
+        Ok(b"OFF") => &ids(properties, b"OCGs") - &ids(config, b"ON"),
+        _ => ids(config, b"OFF"),
+    }
+}
+
+/// Whether the layer or layer membership `object` is hidden.
+fn is_hidden(doc: &Document, object: &Object, hidden: &BTreeSet<ObjectId>) -> bool {
+    if let Ok(id) = object.as_reference() {
+        if hidden.contains(&id) {
+            return true;
+        }
+    }
+    let Ok((_, Object::Dictionary(dict))) = doc.dereference(object) else {
+        return false;
+    };
+    if dict.get(b"Type").and_then(Object::as_name).ok() != Some(b"OCMD") || dict.has(b"VE") {
+        return false;
+    }
+
+    // A membership dictionary combines several layers with a policy
+    let groups = match dict.get(b"OCGs") {
+        Ok(Object::Reference(id)) => vec![*id],
+        Ok(Object::Array(groups)) => groups
+            .iter()
+            .filter_map(|id| id.as_reference().ok())
+            .collect(),
+        _ => return false,
+    };
+    if groups.is_empty() {
+        return false;
+    }
+    let off = groups.iter().filter(|id| hidden.contains(id)).count();
+
+    match dict.get(b"P").and_then(Object::as_name).unwrap_or(b"AnyOn") {
+        b"AllOn" => off > 0,
+        b"AnyOff" => off == 0,
+        b"AllOff" => off < groups.len(),
+        _ => off == groups.len(),
+    }
+}
+
+/// A named resource of the category `key`, like `Properties` or `XObject`.
+fn resource<'a>(
+    doc: &'a Document,
+    resources: Option<&'a Dictionary>,
+    key: &[u8],
+    name: &[u8],
+) -> Option<&'a Object> {
+    let (_, category) = doc.dereference(resources?.get(key).ok()?).ok()?;
+
+    category.as_dict().ok()?.get(name).ok()
+}
+
+/// Whether the XObject `object` is in a hidden layer.
+fn is_hidden_xobject(doc: &Document, object: &Object, hidden: &BTreeSet<ObjectId>) -> bool {
+    doc.dereference(object)
+        .and_then(|(_, object)| object.as_stream())
+        .and_then(|stream| stream.dict.get(b"OC"))
+        .is_ok_and(|layer| is_hidden(doc, layer, hidden))
+}
+
+/// Leave out the marked content sections in hidden layers and the XObjects in hidden layers that
+/// are drawn. Returns how many were left out.
+fn strip_operations(
+    doc: &Document,
+    resources: Option<&Dictionary>,
+    hidden: &BTreeSet<ObjectId>,
+    content: &mut Content,
+) -> usize {
+    let mut removed = 0;
+    // How deep inside a hidden section the operations are, sections can be nested
+    let mut skipping = 0usize;
+    let mut kept = Vec::with_capacity(content.operations.len());
+
+    for operation in content.operations.drain(..) {
+        if skipping > 0 {
+            match operation.operator.as_str() {
+                "BMC" | "BDC" => skipping += 1,
+                "EMC" => skipping -= 1,
+                _ => {}
+            }
+            continue;
+        }
+
+        let hidden_section = match (operation.operator.as_str(), &operation.operands[..]) {
+            ("BDC", [Object::Name(tag), Object::Name(name)]) if tag == b"OC" => {
+                resource(doc, resources, b"Properties", name)
+                    .is_some_and(|layer| is_hidden(doc, layer, hidden))
+            }
+            ("BDC", [Object::Name(tag), layer @ Object::Dictionary(_)]) if tag == b"OC" => {
+                is_hidden(doc, layer, hidden)
+            }
+            _ => false,
+        };
+        if hidden_section {
+            skipping = 1;
+            removed += 1;
+            continue;
+        }
+
+        if let ("Do", [Object::Name(name)]) = (operation.operator.as_str(), &operation.operands[..])
+        {
+            if resource(doc, resources, b"XObject", name)
+                .is_some_and(|xobject| is_hidden_xobject(doc, xobject, hidden))
+            {
+                removed += 1;
+                continue;
+            }
+        }
+
+        kept.push(operation);
+    }
+
+    content.operations = kept;
+    removed
+}
+
+/// Remove the references to `hidden` from the arrays in `object`, without following references.
+fn remove_references(object: &mut Object, hidden: &BTreeSet<ObjectId>) {
+    match object {
+        Object::Array(array) => {
+            array.retain(|o| !o.as_reference().is_ok_and(|id| hidden.contains(&id)));
+            array.iter_mut().for_each(|o| remove_references(o, hidden));
+        }
+        Object::Dictionary(dict) => dict
+            .iter_mut()
+            .for_each(|(_, o)| remove_references(o, hidden)),
+        _ => {}
+    }
+}
+
+/// Remove the layers and XObjects in `unused` from the resource dictionary `resources`.
+fn remove_resources(resources: &mut Dictionary, unused: &BTreeSet<ObjectId>) {
+    for key in [&b"Properties"[..], b"XObject"] {
+        if let Ok(Object::Dictionary(category)) = resources.get_mut(key) {
+            let names = category
+                .iter()
+                .filter(|(_, o)| o.as_reference().is_ok_and(|id| unused.contains(&id)))
+                .map(|(name, _)| name.clone())
+                .collect::<Vec<_>>();
+            for name in names {
+                category.remove(&name);
+            }
+        }
+    }
+}
+
+/// Remove the hidden layers from the optional content properties of the catalog.
+fn remove_groups(doc: &mut Document, hidden: &BTreeSet<ObjectId>) {
+    let Ok(properties) = doc
+        .catalog()
+        .and_then(|catalog| catalog.get(b"OCProperties"))
+        .cloned()
+    else {
+        return;
+    };
+
+    // The configurations can be separate objects, with the properties inline in the catalog
+    let mut ids = properties.as_reference().into_iter().collect::<Vec<_>>();
+    if let Ok((_, Object::Dictionary(dict))) = doc.dereference(&properties) {
+        ids.extend(dict.get(b"D").and_then(Object::as_reference));
+        if let Ok((_, Object::Array(configs))) = dict
+            .get(b"Configs")
+            .and_then(|configs| doc.dereference(configs))
+        {
+            ids.extend(
+                configs
+                    .iter()
+                    .filter_map(|config| config.as_reference().ok()),
+            );
+        }
+    }
+    for id in ids {
+        if let Ok(object) = doc.get_object_mut(id) {
+            remove_references(object, hidden);
+        }
+    }
+
+    if let Ok(properties) = doc
+        .catalog_mut()
+        .and_then(|catalog| catalog.get_mut(b"OCProperties"))
+    {
+        remove_references(properties, hidden);
+    }
+
+    // Without any layers left the properties have nothing to describe
+    let empty = doc
+        .catalog()
+        .and_then(|catalog| catalog.get(b"OCProperties"))
+        .and_then(|properties| doc.dereference(properties))
+        .and_then(|(_, properties)| properties.as_dict())
+        .and_then(|properties| properties.get(b"OCGs"))
+        .and_then(|groups| doc.dereference(groups))
+        .and_then(|(_, groups)| groups.as_array())
+        .is_ok_and(Vec::is_empty);
+    if empty {
+        if let Ok(catalog) = doc.catalog_mut() {
+            catalog.remove(b"OCProperties");
+        }
+    }
+}
+
+/// Remove what `doc` draws in hidden layers, and the hidden layers themselves. Returns how many
+/// sections and XObjects were removed and how many layers.
+pub fn strip_hidden_layers(doc: &mut Document) -> (usize, usize) {
+    let hidden = hidden_groups(doc);
+    if hidden.is_empty() {
+        return (0, 0);
+    }
+    let mut removed = 0;
+
+    for (_, page_id) in doc.get_pages() {
+        let Ok(mut content) = doc
+            .get_page_content(page_id)
+            .map_err(|_| ())
+            .and_then(|content| Content::decode(&content).map_err(|_| ()))
+        else {
+            continue;
+        };
+
+        let resources = crate::images::page_resources(doc, page_id);
+        let stripped = strip_operations(doc, resources, &hidden, &mut content);
+        if stripped > 0 {
+            if let Ok(content) = content.encode() {
+                if doc.change_page_content(page_id, content).is_ok() {
+                    removed += stripped;
+                }
+            }
+        }
+    }
+
+    let mut replacements: Vec<(ObjectId, Stream)> = Vec::new();
+    for (id, object) in doc.objects.iter() {
+        let Ok(stream) = object.as_stream() else {
+            continue;
+        };
+        if stream.dict.get(b"Subtype").and_then(Object::as_name).ok() != Some(b"Form") {
+            continue;
+        }
+
+        let data = crate::images::image_samples(stream);
+        let Some(mut content) = data.and_then(|data| Content::decode(&data).ok()) else {
+            continue;
+        };
+        let resources = stream
+            .dict
+            .get(b"Resources")
+            .and_then(|resources| doc.dereference(resources))
+            .and_then(|(_, resources)| resources.as_dict())
+            .ok();
+
+        let stripped = strip_operations(doc, resources, &hidden, &mut content);
+        if stripped > 0 {
+            if let Ok(content) = content.encode() {
+                let mut stream = stream.clone();
+                stream.set_plain_content(content);
+                let _ = stream.compress();
+                replacements.push((*id, stream));
+                removed += stripped;
+            }
+        }
+    }
+    for (id, stream) in replacements {
+        doc.objects.insert(id, Object::Stream(stream));
+    }
+
+    // Nothing draws the hidden layers anymore, the resources can go. Then only the optional
+    // content properties still point at them
+    let hidden_xobjects = doc
+        .objects
+        .iter()
+        .filter(|(_, object)| is_hidden_xobject(doc, object, &hidden))
+        .map(|(id, _)| *id)
+        .collect::<BTreeSet<_>>();
+    let unused = &hidden | &hidden_xobjects;
+    for object in doc.objects.values_mut() {
+        let dict = match object {
+            Object::Dictionary(dict) => dict,
+            Object::Stream(stream) => &mut stream.dict,
+            _ => continue,
+        };
+        // Either a resource dictionary of its own or a page or form with the resources inline
+        remove_resources(dict, &unused);
+        if let Ok(Object::Dictionary(resources)) = dict.get_mut(b"Resources") {
+            remove_resources(resources, &unused);
+        }
+    }
+
+    remove_groups(doc, &hidden);
+    doc.prune_objects();
+
+    (removed, hidden.len())
+}
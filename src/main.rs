@@ -1,183 +1,4006 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use anyhow::{Context, Result};
+use clap::parser::ValueSource;
 use clap::{Arg, ArgAction, ArgMatches, Command};
-use lopdf::{Bookmark, Document, Object, ObjectId};
+use clap_complete::Shell;
+use error::PdftError;
+use itertools::Itertools;
+use lopdf::content::{Content, Operation};
+use lopdf::xref::XrefType;
+use lopdf::{dictionary, Bookmark, Document, Object, ObjectId, Stream};
+use rayon::prelude::*;
+use regex::Regex;
+use serde::Serialize;
+use serde_json::json;
 
-fn main() -> Result<()> {
-    let matches = Command::new("pdf")
+mod annotations;
+mod attachments;
+mod catalog;
+mod encryption;
+mod error;
+mod forms;
+mod grayscale;
+mod images;
+mod job;
+mod layers;
+mod names;
+mod normalize;
+mod outline;
+mod pages;
+mod portfolio;
+mod recover;
+mod structure;
+mod validate;
+mod xmp;
+
+/// The command line interface, also used to generate the shell completions.
+fn cli() -> Command {
+    Command::new("pdf")
         .subcommand_required(true)
         .arg_required_else_help(true)
+        .after_help(
+            "Exit codes:\n  \
+             0  everything succeeded\n  \
+             1  something failed, with --keep-going: some of the files failed\n  \
+             2  invalid arguments, or nothing could be processed",
+        )
+        .arg(
+            Arg::new("progress-json")
+                .long("progress-json")
+                .global(true)
+                .action(ArgAction::SetTrue)
+                .help("Also print the progress as JSON lines to stderr, for programs wrapping pdft"),
+        )
+        .arg(
+            Arg::new("errors-json")
+                .long("errors-json")
+                .global(true)
+                .action(ArgAction::SetTrue)
+                .help(
+                    "Print errors to stderr as JSON lines with the file, the error and its kind \
+                     (io, invalid_pdf, unreadable, encrypted, no_pages, some_failed, \
+                     nothing_processed, usage or other), for programs wrapping pdft",
+                ),
+        )
+        .arg(
+            Arg::new("no-emoji")
+                .long("no-emoji")
+                .global(true)
+                .action(ArgAction::SetTrue)
+                .help("Print plain text only, for logs and terminals without Unicode support"),
+        )
+        .arg(
+            Arg::new("raw-paths")
+                .long("raw-paths")
+                .global(true)
+                .action(ArgAction::SetTrue)
+                .help("Use file names exactly as given, without adding .pdf to names that don't end in it"),
+        )
+        .arg(
+            Arg::new("recover")
+                .long("recover")
+                .global(true)
+                .action(ArgAction::SetTrue)
+                .help("Try to recover damaged PDFs that fail to load by finding their objects, the result may be incomplete"),
+        )
+        .arg(
+            Arg::new("deterministic")
+                .long("deterministic")
+                .global(true)
+                .action(ArgAction::SetTrue)
+                .help(
+                    "Make the output byte for byte the same every time for the same inputs, for \
+                     reproducible builds. Implies --deterministic-id of merge, encrypt still needs \
+                     --deterministic-id itself because a fixed identifier weakens the encryption",
+                ),
+        )
+        .arg(
+            Arg::new("smoke-test")
+                .long("smoke-test")
+                .value_name("CMD")
+                .global(true)
+                .value_parser(|s: &str| match s.trim() {
+                    "" => Err("the command can't be empty"),
+                    _ => Ok(s.to_owned()),
+                })
+                .help(
+                    "Run CMD on every written file and fail if it fails, like \"qpdf --check {}\". \
+                     {} is replaced by the file, which is added at the end without it. CMD is \
+                     split on spaces and not run by a shell",
+                ),
+        )
+        .arg(
+            Arg::new("chmod")
+                .long("chmod")
+                .value_name("MODE")
+                .global(true)
+                .value_parser(parse_mode)
+                .help("Set the permissions of the written files, in octal like 600 (Unix only)"),
+        )
         .subcommand(
             Command::new("merge")
                 .about("Merge multiple PDFs into a single output PDF.")
+                .after_help(
+                    "Examples:\n  \
+                     pdft merge a.pdf b.pdf -o out.pdf\n  \
+                     pdft merge chapters/ --group-by \"^(chapter\\d+)\" -o book.pdf\n  \
+                     pdft merge new.pdf --append-to binder.pdf\n  \
+                     pdft merge a.pdf b.pdf --portfolio -o package.pdf",
+                )
                 .arg(Arg::new("PDFs").action(ArgAction::Append))
                 .arg(
                     Arg::new("output")
                         .short('o')
                         .long("output")
                         .default_value("output.pdf"),
+                )
+                .arg(
+                    Arg::new("job")
+                        .long("job")
+                        .value_name("JSON")
+                        .conflicts_with_all(["PDFs", "portfolio"])
+                        .help(
+                            "Read the inputs from a JSON file instead, with the pages and rotation \
+                             of every input and optionally the output, like {\"output\": \
+                             \"out.pdf\", \"inputs\": [{\"file\": \"a.pdf\", \"pages\": \"1:3\", \
+                             \"rotate\": 90}]}",
+                        ),
+                )
+                .arg(
+                    Arg::new("append-to")
+                        .long("append-to")
+                        .value_name("PDF")
+                        .help(
+                            "Append the PDFs to the pages of an existing PDF, keeping its metadata, \
+                             bookmarks and catalog. Overwrites it unless --output is given",
+                        ),
+                )
+                .arg(
+                    Arg::new("portfolio")
+                        .long("portfolio")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with_all([
+                            "append-to",
+                            "toc-page",
+                            "interactive",
+                            "keep-bookmarks-from",
+                            "group-by",
+                            "preserve-tags",
+                            "field-prefix",
+                            "dedup-pages",
+                            "pad-to-even",
+                            "strip-attachments",
+                            "separator-page",
+                            "merge-javascript",
+                            "keep-open-action",
+                            "strict-version",
+                            "xmp",
+                        ])
+                        .help("Embed the PDFs unchanged as separate files of a PDF portfolio, instead of merging their pages"),
+                )
+                .arg(
+                    Arg::new("toc-page")
+                        .long("toc-page")
+                        .action(ArgAction::SetTrue)
+                        .help("Prepend a table of contents page linking to each merged PDF"),
+                )
+                .arg(
+                    Arg::new("interactive")
+                        .short('i')
+                        .long("interactive")
+                        .action(ArgAction::SetTrue)
+                        .help("Ask which input wins when the PDFs conflict, instead of using the defaults"),
+                )
+                .arg(stream_filter_arg())
+                .arg(xref_stream_arg())
+                .arg(resolve_references_arg())
+                .arg(strip_thumbnails_arg())
+                .arg(strip_attachments_arg())
+                .arg(remove_links_arg())
+                .arg(output_intent_arg())
+                .arg(title_from_filename_arg())
+                .arg(jobs_arg())
+                .arg(
+                    Arg::new("bookmark-color")
+                        .long("bookmark-color")
+                        .value_name("R,G,B")
+                        .value_parser(parse_color)
+                        .default_value("0,0,1")
+                        .help("Color of the generated bookmarks, each component between 0 and 1"),
+                )
+                .arg(
+                    Arg::new("bookmark-bold")
+                        .long("bookmark-bold")
+                        .action(ArgAction::SetTrue)
+                        .help("Show the generated bookmarks in bold"),
+                )
+                .arg(
+                    Arg::new("bookmark-italic")
+                        .long("bookmark-italic")
+                        .action(ArgAction::SetTrue)
+                        .help("Show the generated bookmarks in italic"),
+                )
+                .arg(
+                    Arg::new("page-offset")
+                        .long("page-offset")
+                        .value_name("N")
+                        .allow_hyphen_values(true)
+                        .value_parser(clap::value_parser!(i64))
+                        .default_value("0")
+                        .help("Add N to the page numbers in the bookmark titles, like -4 when the first 4 pages are front matter"),
+                )
+                .arg(
+                    Arg::new("bookmark-every")
+                        .long("bookmark-every")
+                        .value_name("N")
+                        .value_parser(clap::value_parser!(u32).range(1..))
+                        .help(
+                            "Give every PDF a single bookmark, with bookmarks to every Nth page of \
+                             it (\"Page 10\", \"Page 20\", ...) nested under it",
+                        ),
+                )
+                .arg(
+                    Arg::new("keep-bookmarks-from")
+                        .long("keep-bookmarks-from")
+                        .value_name("N")
+                        .value_parser(clap::value_parser!(u32).range(1..))
+                        .help(
+                            "Keep the bookmarks of the Nth PDF (starting at 1), \
+                             the other PDFs only get a bookmark to their first page",
+                        ),
+                )
+                .arg(
+                    Arg::new("open-fit")
+                        .long("open-fit")
+                        .value_name("VIEW")
+                        .value_parser(parse_open_view)
+                        .help("Open the output at the first page, fit to its \"width\", the whole \"page\" or at a zoom like 150%"),
+                )
+                .arg(
+                    Arg::new("preserve-tags")
+                        .long("preserve-tags")
+                        .action(ArgAction::SetTrue)
+                        .help("Combine the structure trees of tagged PDFs, so the output stays accessible to screen readers"),
+                )
+                .arg(
+                    Arg::new("allow-untagged")
+                        .long("allow-untagged")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("preserve-tags")
+                        .help("Don't warn when tagged PDFs are merged without --preserve-tags"),
+                )
+                .arg(
+                    Arg::new("field-prefix")
+                        .long("field-prefix")
+                        .value_name("TEMPLATE")
+                        .help("Prefix the form fields of every PDF with TEMPLATE, where {n} is the number of the PDF, like \"doc{n}_\". By default only clashing fields are renamed, by adding _{n}"),
+                )
+                .arg(
+                    Arg::new("deterministic-id")
+                        .long("deterministic-id")
+                        .action(ArgAction::SetTrue)
+                        .help("Derive the file identifier from the content instead of making a new one, so the output is reproducible"),
+                )
+                .arg(
+                    Arg::new("dedup-pages")
+                        .long("dedup-pages")
+                        .action(ArgAction::SetTrue)
+                        .help("Remove pages that look exactly like an earlier page"),
+                )
+                .arg(
+                    Arg::new("pad-to-even")
+                        .long("pad-to-even")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("dedup-pages")
+                        .help("Add a blank page after every PDF with an odd number of pages, for double-sided printing"),
+                )
+                .arg(
+                    Arg::new("keep-open-action")
+                        .long("keep-open-action")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("open-fit")
+                        .help("Open at the page and zoom the first PDF opens at, and keep its document actions. By default those of the last PDF are kept"),
+                )
+                .arg(
+                    Arg::new("strict-version")
+                        .long("strict-version")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("interactive")
+                        .help("Refuse to merge PDFs that don't all have the same PDF version, and give the output that version"),
+                )
+                .arg(
+                    Arg::new("metadata")
+                        .long("metadata")
+                        .value_parser(["first", "last", "none", "merge"])
+                        .help(
+                            "Where the title, author and other document information come from: \
+                             the first or last PDF that has it, none, or merged from all of them \
+                             (the longest title and every author). By default there is none, \
+                             unless appending",
+                        ),
+                )
+                .arg(
+                    Arg::new("xmp")
+                        .long("xmp")
+                        .value_parser(["first", "merge", "none"])
+                        .help(
+                            "Where the XMP metadata comes from: the first PDF that has it, none, or \
+                             merged from all of them (the longest title, every creator and the \
+                             earliest creation date). By default it is that of the last PDF",
+                        ),
+                )
+                .arg(
+                    Arg::new("no-final-renumber")
+                        .long("no-final-renumber")
+                        .alias("no-renumber")
+                        .action(ArgAction::SetTrue)
+                        .hide(true)
+                        .help("Keep the object ids the inputs were renumbered to, to debug the merge"),
+                )
+                .arg(
+                    Arg::new("merge-javascript")
+                        .long("merge-javascript")
+                        .action(ArgAction::SetTrue)
+                        .help(
+                            "Keep the document level scripts of every PDF, like form calculations, \
+                             prefixing their names with the number of the PDF. By default only the \
+                             scripts of the first PDF are kept",
+                        ),
+                )
+                .arg(
+                    Arg::new("separator-page")
+                        .long("separator-page")
+                        .value_name("CONTENT")
+                        .value_parser(["blank", "name"])
+                        .num_args(0..=1)
+                        .default_missing_value("name")
+                        .conflicts_with("dedup-pages")
+                        .help("Put a page between the PDFs, blank or showing the name of the next PDF (the default)"),
+                )
+                .arg(
+                    Arg::new("manifest")
+                        .long("manifest")
+                        .value_name("PATH")
+                        .help("Also write the source file and page of every merged page to a JSON file"),
+                )
+                .arg(
+                    Arg::new("group-by")
+                        .long("group-by")
+                        .value_name("REGEX")
+                        .value_parser(|s: &str| Regex::new(s))
+                        .help(
+                            "Put the bookmarks of consecutive PDFs whose file name has the same \
+                             match (or first capture group) of REGEX under one parent bookmark, \
+                             like \"^(chapter\\d+)\"",
+                        ),
+                )
+                .arg(
+                    Arg::new("since")
+                        .long("since")
+                        .value_name("DATE")
+                        .value_parser(parse_date)
+                        .help("Only merge the PDFs of input directories modified since DATE, like 2024-01-31"),
+                )
+                .arg(
+                    Arg::new("replace-page")
+                        .long("replace-page")
+                        .num_args(2)
+                        .value_names(["PAGE", "PDF"])
+                        .action(ArgAction::Append)
+                        .help("Replace page PAGE of the merged PDF with the first page of PDF, can be repeated"),
                 ),
         )
         .subcommand(
             Command::new("compress")
                 .about("Compress a PDF to save disk space or make it easier to attach.")
+                .after_help(
+                    "Examples:\n  \
+                     pdft compress big.pdf\n  \
+                     pdft compress scan.pdf --max-size 10MB\n  \
+                     pdft compress *.pdf --in-place",
+                )
+                .arg(Arg::new("PDFs").action(ArgAction::Append))
+                .arg(
+                    Arg::new("quiet")
+                        .short('q')
+                        .long("quiet")
+                        .action(ArgAction::SetTrue)
+                        .help("Don't print the table with the size of every file at the end"),
+                )
+                .arg(xref_stream_arg())
+                .arg(resolve_references_arg())
+                .arg(strip_thumbnails_arg())
+                .arg(strip_attachments_arg())
+                .arg(remove_links_arg())
+                .arg(output_intent_arg())
+                .arg(title_from_filename_arg())
+                .arg(jobs_arg())
+                .arg(
+                    Arg::new("output-version")
+                        .long("output-version")
+                        .value_parser(["1.0", "1.1", "1.2", "1.3", "1.4", "1.5", "1.6", "1.7", "2.0"])
+                        .help("PDF version to declare in the compressed files, features of newer versions are not removed"),
+                )
+                .arg(mmap_arg())
+                .arg(
+                    Arg::new("max-size")
+                        .long("max-size")
+                        .value_name("SIZE")
+                        .value_parser(parse_size)
+                        .help(
+                            "Scale the images down as little as needed to make every file fit in \
+                             SIZE, like \"10MB\" (in multiples of 1024). JPEG images can't be \
+                             scaled",
+                        ),
+                )
+                .arg(
+                    Arg::new("target-ppi")
+                        .long("target-ppi")
+                        .value_name("PPI")
+                        .value_parser(parse_positive)
+                        .help("Scale images drawn at more than PPI pixels per inch down to PPI. JPEG images can't be scaled"),
+                )
+                .arg(
+                    Arg::new("report")
+                        .long("report")
+                        .action(ArgAction::SetTrue)
+                        .requires("target-ppi")
+                        .help("With --target-ppi, list every image above it with its size and resolution before and after"),
+                )
+                .arg(
+                    Arg::new("strip-icc")
+                        .long("strip-icc")
+                        .action(ArgAction::SetTrue)
+                        .help("Store ICC color profiles that are embedded more than once only once"),
+                )
+                .arg(
+                    Arg::new("aggressive")
+                        .long("aggressive")
+                        .action(ArgAction::SetTrue)
+                        .requires("strip-icc")
+                        .help("With --strip-icc, remove the profiles and use device colors instead. Colors can shift a little"),
+                )
+                .arg(
+                    Arg::new("remove-hidden-ocg")
+                        .long("remove-hidden-ocg")
+                        .action(ArgAction::SetTrue)
+                        .help("Remove the content of layers that are hidden by default, and the layers themselves"),
+                )
+                .arg(
+                    Arg::new("in-place")
+                        .long("in-place")
+                        .action(ArgAction::SetTrue)
+                        .help("Replace the original files, unless the compressed file would be larger"),
+                )
+                .arg(
+                    Arg::new("suffix")
+                        .long("suffix")
+                        .value_name("SUFFIX")
+                        .value_parser(parse_suffix)
+                        .default_value("_compressed")
+                        .conflicts_with("in-place")
+                        .help("Put SUFFIX between the name and the .pdf extension of the compressed files, like .min for report.min.pdf"),
+                )
+                .arg(
+                    Arg::new("only-if-smaller")
+                        .long("only-if-smaller")
+                        .action(ArgAction::SetTrue)
+                        .help("Keep the original when compressing makes a file larger, always on with --in-place"),
+                )
+                .arg(
+                    Arg::new("preserve-dates")
+                        .long("preserve-dates")
+                        .action(ArgAction::SetTrue)
+                        .help("Give the compressed files the modification time of the originals"),
+                )
+                .arg(
+                    Arg::new("dedup")
+                        .long("dedup")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("title-from-filename")
+                        .help("Compress files with the same content only once, and copy the result for the others"),
+                )
+                .arg(
+                    Arg::new("keep-going")
+                        .long("keep-going")
+                        .action(ArgAction::SetTrue)
+                        .help("Compress the other files when one of them fails, and exit with 1 if some failed or 2 if all of them did"),
+                ),
+        )
+        .subcommand(
+            Command::new("extract")
+                .about("Extract a selection of pages from a PDF into a new PDF.")
+                .after_help(
+                    "Examples:\n  \
+                     pdft extract report.pdf -p 1,3,5:7 -o selection.pdf\n  \
+                     pdft extract statement.pdf --contains \"Invoice #4521\"\n  \
+                     pdft extract mixed.pdf -p 1: --fit-to a4\n  \
+                     pdft extract scan.pdf --every 10 -o sample.pdf",
+                )
+                .arg(Arg::new("PDF").required(true))
+                .arg(
+                    Arg::new("pages")
+                        .short('p')
+                        .long("pages")
+                        .allow_hyphen_values(true)
+                        .help(
+                            "Pages to extract, like \"1,3,5:7\". Ranges include both ends, \
+                             negative numbers count from the end (-1 is the last page) and \
+                             open ends run to the first or last page, e.g. \"-3:\" or \"2:\"",
+                        ),
+                )
+                .arg(
+                    Arg::new("contains")
+                        .long("contains")
+                        .value_name("TEXT")
+                        .help("Extract the pages whose text contains TEXT"),
+                )
+                .arg(
+                    Arg::new("regex")
+                        .long("regex")
+                        .action(ArgAction::SetTrue)
+                        .requires("contains")
+                        .help("Match --contains as a regular expression"),
+                )
+                .arg(
+                    Arg::new("every")
+                        .long("every")
+                        .value_name("N")
+                        .value_parser(clap::value_parser!(u32).range(1..))
+                        .help("Extract every Nth page, like pages 1, 11, 21 and so on for 10, to skim a long document"),
+                )
+                .arg(
+                    Arg::new("offset")
+                        .long("offset")
+                        .value_name("PAGE")
+                        .value_parser(clap::value_parser!(u32).range(1..))
+                        .requires("every")
+                        .conflicts_with_all(["pages", "contains"])
+                        .help("The page --every starts at, 1 by default"),
+                )
+                .arg(
+                    Arg::new("fit-to")
+                        .long("fit-to")
+                        .value_name("SIZE")
+                        .value_parser(parse_page_size)
+                        .help(
+                            "Scale every page to the same size: a3, a4, a5, letter, legal, \
+                             tabloid or WIDTHxHEIGHT in points. Landscape pages stay landscape",
+                        ),
+                )
+                .group(
+                    clap::ArgGroup::new("selection")
+                        .args(["pages", "contains", "every"])
+                        .required(true),
+                )
+                .arg(Arg::new("output").short('o').long("output"))
+                .arg(clip_to_crop_box_arg())
+                .arg(box_type_arg())
+                .arg(skip_bad_pages_arg())
+                .args(remove_empty_pages_args())
+                .arg(drop_textless_arg())
+                .arg(stream_filter_arg())
+                .arg(mmap_arg()),
+        )
+        .subcommand(
+            Command::new("split")
+                .about("Split a PDF into multiple PDFs with a fixed number of pages each.")
+                .after_help(
+                    "Examples:\n  \
+                     pdft split book.pdf -n 10 --out parts/\n  \
+                     pdft split scan.pdf --max-size 5MB --out parts/\n  \
+                     pdft split book.pdf --ranges 1-5,6-10,11- --out parts/",
+                )
+                .arg(Arg::new("PDF").required(true))
+                .arg(
+                    Arg::new("pages-per-file")
+                        .short('n')
+                        .long("pages-per-file")
+                        .value_parser(clap::value_parser!(u32).range(1..))
+                        .default_value("1"),
+                )
+                .arg(
+                    Arg::new("max-size")
+                        .long("max-size")
+                        .value_name("SIZE")
+                        .value_parser(parse_size)
+                        .conflicts_with("pages-per-file")
+                        .help(
+                            "Put as many pages in every part as fit in SIZE, like \"5MB\" (in \
+                             multiples of 1024), instead of a fixed number of pages",
+                        ),
+                )
+                .arg(
+                    Arg::new("ranges")
+                        .long("ranges")
+                        .value_name("RANGES")
+                        .allow_hyphen_values(true)
+                        .conflicts_with_all(["pages-per-file", "max-size"])
+                        .help(
+                            "Write a part for every range of pages, like \"1-5,6-10,11-\", instead \
+                             of a fixed number of pages. An open end runs to the last page",
+                        ),
+                )
+                .arg(
+                    Arg::new("name-by-label")
+                        .long("name-by-label")
+                        .action(ArgAction::SetTrue)
+                        .help(
+                            "Name the parts after the page labels of their pages, like book_iv.pdf \
+                             or book_A-1-A-10.pdf, instead of numbering them. Pages without a label \
+                             use their page number",
+                        ),
+                )
+                .arg(
+                    Arg::new("out")
+                        .long("out")
+                        .default_value(".")
+                        .help("Directory to write the parts to"),
+                )
+                .arg(mkdir_arg())
+                .arg(
+                    Arg::new("manifest")
+                        .long("manifest")
+                        .value_name("PATH")
+                        .help("Also write the part to page range mapping to a JSON file"),
+                )
+                .arg(clip_to_crop_box_arg())
+                .arg(box_type_arg())
+                .arg(skip_bad_pages_arg())
+                .args(remove_empty_pages_args())
+                .arg(drop_textless_arg())
+                .arg(stream_filter_arg())
+                .arg(mmap_arg()),
+        )
+        .subcommand(
+            Command::new("split-by-bookmarks")
+                .about("Split a PDF into a PDF per bookmark, like a file per chapter.")
+                .after_help(
+                    "Examples:\n  \
+                     pdft split-by-bookmarks book.pdf --out chapters/\n  \
+                     pdft split-by-bookmarks book.pdf --level 2 --manifest parts.json",
+                )
+                .arg(Arg::new("PDF").required(true))
+                .arg(
+                    Arg::new("level")
+                        .long("level")
+                        .value_parser(clap::value_parser!(u32).range(1..))
+                        .default_value("1")
+                        .help("How deep to split, 1 only splits at the top level bookmarks"),
+                )
+                .arg(
+                    Arg::new("out")
+                        .long("out")
+                        .default_value(".")
+                        .help("Directory to write the parts to"),
+                )
+                .arg(mkdir_arg())
+                .arg(
+                    Arg::new("manifest")
+                        .long("manifest")
+                        .value_name("PATH")
+                        .help("Also write the part to page range mapping to a JSON file"),
+                )
+                .arg(stream_filter_arg())
+                .arg(mmap_arg()),
+        )
+        .subcommand(
+            Command::new("encrypt")
+                .about("Protect a PDF with a password.")
+                .after_help(
+                    "Examples:\n  \
+                     pdft encrypt report.pdf -o protected.pdf\n  \
+                     pdft encrypt report.pdf --password-stdin < password.txt",
+                )
+                .arg(Arg::new("PDF").required(true))
+                .arg(Arg::new("output").short('o').long("output"))
+                .args(password_args())
+                .arg(
+                    Arg::new("owner-password")
+                        .long("owner-password")
+                        .help("Password to change the permissions, defaults to the user password"),
+                )
+                .arg(
+                    Arg::new("deterministic-id")
+                        .long("deterministic-id")
+                        .action(ArgAction::SetTrue)
+                        .help(
+                            "Use a fixed file identifier so the output is reproducible. For testing \
+                             only, it weakens the encryption, don't use it for real files",
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("decrypt")
+                .about("Remove the password protection from a PDF.")
+                .after_help(
+                    "Examples:\n  \
+                     pdft decrypt protected.pdf -o report.pdf",
+                )
+                .arg(Arg::new("PDF").required(true))
+                .arg(Arg::new("output").short('o').long("output"))
+                .args(password_args()),
+        )
+        .subcommand(
+            Command::new("rotate")
+                .about("Rotate the pages of a PDF.")
+                .after_help(
+                    "Examples:\n  \
+                     pdft rotate scan.pdf -a 90\n  \
+                     pdft rotate scan.pdf --auto-orient --target portrait\n  \
+                     pdft rotate ocr.pdf --auto-rotate-text\n  \
+                     pdft rotate scan.pdf --spec \"1-2:90,3:180\"",
+                )
+                .arg(Arg::new("PDF").required(true))
+                .arg(Arg::new("output").short('o').long("output"))
+                .arg(
+                    Arg::new("angle")
+                        .short('a')
+                        .long("angle")
+                        .allow_hyphen_values(true)
+                        .value_parser(parse_angle)
+                        .help("Degrees to turn every page clockwise, a multiple of 90"),
+                )
+                .arg(
+                    Arg::new("auto-orient")
+                        .long("auto-orient")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("angle")
+                        .help("Turn the pages that don't have the --target orientation by 90 degrees"),
+                )
+                .arg(
+                    Arg::new("auto-rotate-text")
+                        .long("auto-rotate-text")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with_all(["angle", "auto-orient"])
+                        .help("Turn every page so most of its text reads left to right, for OCR'd scans. Pages without text stay as they are"),
+                )
+                .arg(
+                    Arg::new("target")
+                        .long("target")
+                        .value_parser(["portrait", "landscape"])
+                        .default_value("portrait")
+                        .requires("auto-orient"),
+                )
+                .arg(
+                    Arg::new("spec")
+                        .long("spec")
+                        .conflicts_with_all(["angle", "auto-orient", "auto-rotate-text"])
+                        .help(
+                            "Angles per page, like \"1-2:90,3:180\". The angles are added to the \
+                             current rotation, pages that are not listed stay as they are",
+                        ),
+                )
+                .group(
+                    clap::ArgGroup::new("rotation")
+                        .args(["angle", "auto-orient", "auto-rotate-text", "spec"])
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("scale")
+                .about("Scale the pages of a PDF, both their content and their size.")
+                .after_help(
+                    "Examples:\n  \
+                     pdft scale drawing.pdf --factor 0.5 -o drawing_a3.pdf\n  \
+                     pdft scale slides.pdf --factor 2 --pages 1,3:5",
+                )
+                .arg(Arg::new("PDF").required(true))
+                .arg(Arg::new("output").short('o').long("output"))
+                .arg(
+                    Arg::new("factor")
+                        .long("factor")
+                        .required(true)
+                        .value_parser(parse_positive)
+                        .help("How much to scale the pages, like 0.5 for half the width and height"),
+                )
+                .arg(
+                    Arg::new("pages")
+                        .short('p')
+                        .long("pages")
+                        .allow_hyphen_values(true)
+                        .help("Pages to scale, like \"1,3,5:7\", all of them by default"),
+                )
+                .arg(box_type_arg()),
+        )
+        .subcommand(
+            Command::new("crop")
+                .about("Crop the pages of a PDF, to their content or to a fixed area.")
+                .after_help(
+                    "Examples:\n  \
+                     pdft crop scan.pdf --auto --margin 10 -o scan_cropped.pdf\n  \
+                     pdft crop poster.pdf --box 36,36,576,756 --box-type trim\n  \
+                     pdft crop sheet.pdf --box 0,421,595,842 --remove-empty-pages",
+                )
+                .arg(Arg::new("PDF").required(true))
+                .arg(Arg::new("output").short('o').long("output"))
+                .arg(
+                    Arg::new("auto")
+                        .long("auto")
+                        .action(ArgAction::SetTrue)
+                        .help("Crop every page to the area its content is drawn on, pages without content are left as they are"),
+                )
+                .arg(
+                    Arg::new("margin")
+                        .long("margin")
+                        .value_name("POINTS")
+                        .value_parser(clap::value_parser!(f32))
+                        .default_value("0")
+                        .conflicts_with("box")
+                        .help("With --auto, keep this much space around the content, in points"),
+                )
+                .arg(
+                    Arg::new("box")
+                        .long("box")
+                        .value_name("LLX,LLY,URX,URY")
+                        .value_parser(parse_rect)
+                        .help("Crop every page to this rectangle, in points from the bottom left corner of the page"),
+                )
+                .group(
+                    clap::ArgGroup::new("area")
+                        .args(["auto", "box"])
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("pages")
+                        .short('p')
+                        .long("pages")
+                        .allow_hyphen_values(true)
+                        .help("Pages to crop, like \"1,3,5:7\", all of them by default"),
+                )
+                .arg(box_type_arg().help(
+                    "The page box to set: the crop box that viewers show, the media box, or for \
+                     print the bleed, trim or art box",
+                ))
+                .args(remove_empty_pages_args()),
+        )
+        .subcommand(
+            Command::new("grayscale")
+                .about("Convert the colors of a PDF to grayscale, for cheaper printing.")
+                .after_help(
+                    "Examples:\n  \
+                     pdft grayscale flyer.pdf -o flyer_gray.pdf",
+                )
+                .arg(Arg::new("PDF").required(true))
+                .arg(Arg::new("output").short('o').long("output")),
+        )
+        .subcommand(
+            Command::new("info")
+                .about("Show information about PDFs.")
+                .after_help(
+                    "Examples:\n  \
+                     pdft info a.pdf b.pdf\n  \
+                     pdft info scan.pdf --images --hash",
+                )
+                .arg(Arg::new("PDFs").action(ArgAction::Append))
+                .arg(
+                    Arg::new("images")
+                        .long("images")
+                        .action(ArgAction::SetTrue)
+                        .help("List every placed image with its size and effective DPI"),
+                )
+                .arg(
+                    Arg::new("hash")
+                        .long("hash")
+                        .action(ArgAction::SetTrue)
+                        .help(
+                            "Show a fingerprint of the page contents, resources and sizes that \
+                             ignores metadata, bookmarks, annotations and the file structure",
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("annotations")
+                .about("List the comments of a PDF, like notes and highlights.")
+                .after_help(
+                    "Examples:\n  \
+                     pdft annotations reviewed.pdf\n  \
+                     pdft annotations reviewed.pdf --json > comments.json",
+                )
+                .arg(Arg::new("PDF").required(true))
+                .arg(
+                    Arg::new("json")
+                        .long("json")
+                        .action(ArgAction::SetTrue)
+                        .help("Print the comments as JSON, for scripts"),
+                ),
+        )
+        .subcommand(
+            Command::new("dump")
+                .about("Print the drawing operators of a page, for debugging.")
+                .after_help(
+                    "Examples:\n  \
+                     pdft dump stamped.pdf --page 3\n  \
+                     pdft dump stamped.pdf --page 3 --raw > page3.txt",
+                )
+                .arg(Arg::new("PDF").required(true))
+                .arg(
+                    Arg::new("page")
+                        .long("page")
+                        .value_parser(clap::value_parser!(u32).range(1..))
+                        .default_value("1")
+                        .help("The page to dump"),
+                )
+                .arg(
+                    Arg::new("raw")
+                        .long("raw")
+                        .action(ArgAction::SetTrue)
+                        .help("Print the content as it is stored, only with the compression undone, instead of one operator per line"),
+                ),
+        )
+        .subcommand(
+            Command::new("validate")
+                .about("Check PDFs for the most common reasons they are not PDF/A.")
+                .after_help(
+                    "Examples:\n  \
+                     pdft validate archive/*.pdf",
+                )
                 .arg(Arg::new("PDFs").action(ArgAction::Append)),
         )
-        .get_matches();
+        .subcommand(
+            Command::new("completions")
+                .about("Print a shell completion script to stdout.")
+                .hide(true)
+                .arg(
+                    Arg::new("shell")
+                        .required(true)
+                        .value_parser(clap::value_parser!(Shell)),
+                ),
+        )
+}
+
+fn main() -> ExitCode {
+    let matches = cli().get_matches();
+    let errors_json = matches
+        .subcommand()
+        .is_some_and(|(_, data)| data.get_flag("errors-json"));
+
+    if let Some((command, data)) = matches.subcommand() {
+        if let Err(error) = validate_args(command, data) {
+            if errors_json {
+                eprintln!("{}", error_json(None, &error, Some("usage")));
+            } else {
+                eprintln!("Error: {error:?}");
+            }
+            // The same code clap exits with for the arguments it checks itself
+            return ExitCode::from(2);
+        }
+    }
+
+    match run(&matches) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            if errors_json {
+                eprintln!("{}", error_json(None, &error, None));
+            } else {
+                eprintln!("Error: {error:?}");
+            }
+            match error.chain().find_map(|e| e.downcast_ref::<PdftError>()) {
+                Some(PdftError::NothingProcessed { .. }) => ExitCode::from(2),
+                _ => ExitCode::FAILURE,
+            }
+        }
+    }
+}
 
+fn run(matches: &ArgMatches) -> Result<()> {
     match matches.subcommand() {
         Some(("merge", data)) => merge_pdfs(data).with_context(|| "Failed to merge pdfs")?,
         Some(("compress", data)) => {
             compress_pdfs(data).with_context(|| "Failed to compress pdfs")?
         }
+        Some(("extract", data)) => extract_pdf(data).with_context(|| "Failed to extract pages")?,
+        Some(("split", data)) => split_pdf(data).with_context(|| "Failed to split pdf")?,
+        Some(("split-by-bookmarks", data)) => {
+            split_by_bookmarks_pdf(data).with_context(|| "Failed to split pdf")?
+        }
+        Some(("encrypt", data)) => encrypt_pdf(data).with_context(|| "Failed to encrypt pdf")?,
+        Some(("decrypt", data)) => decrypt_pdf(data).with_context(|| "Failed to decrypt pdf")?,
+        Some(("rotate", data)) => rotate_pdf(data).with_context(|| "Failed to rotate pdf")?,
+        Some(("scale", data)) => scale_pdf(data).with_context(|| "Failed to scale pdf")?,
+        Some(("crop", data)) => crop_pdf(data).with_context(|| "Failed to crop pdf")?,
+        Some(("grayscale", data)) => {
+            grayscale_pdf(data).with_context(|| "Failed to convert pdf to grayscale")?
+        }
+        Some(("info", data)) => info_pdfs(data).with_context(|| "Failed to inspect pdfs")?,
+        Some(("dump", data)) => dump_pdf(data).with_context(|| "Failed to dump page")?,
+        Some(("annotations", data)) => {
+            list_annotations(data).with_context(|| "Failed to list annotations")?
+        }
+        Some(("validate", data)) => validate_pdfs(data)?,
+        Some(("completions", data)) => {
+            let shell = *data.get_one::<Shell>("shell").unwrap();
+            clap_complete::generate(shell, &mut cli(), "pdf", &mut io::stdout());
+        }
         _ => Err(anyhow::anyhow!("This command does not exist"))?,
     }
 
     Ok(())
 }
 
-fn compress_pdfs(data: &ArgMatches) -> Result<()> {
-    println!("Checking input validity...");
-
-    let pdfs = data
-        .get_many::<String>("PDFs")
-        .with_context(|| "No PDFs found to merge")?
-        .peekable();
-
-    println!("Loading PDFs into memory...");
+/// Check the combinations of arguments clap can't check by itself, before any PDF is loaded.
+fn validate_args(command: &str, data: &ArgMatches) -> Result<()> {
+    if command == "merge" {
+        let inputs = data
+            .get_many::<String>("PDFs")
+            .map(|pdfs| pdfs.len())
+            .unwrap_or(0);
+        // The number of PDFs in a directory or a job is only known once it is read
+        let directories = data
+            .get_many::<String>("PDFs")
+            .into_iter()
+            .flatten()
+            .any(|f| Path::new(f).is_dir())
+            || data.contains_id("job");
 
-    let documents = pdfs
-        .map(|f| {
-            if f.ends_with(".pdf") {
-                (
-                    f.clone(),
-                    Document::load(f)
-                        .with_context(|| format!("File not found: {}", f))
-                        .unwrap(),
-                )
-            } else {
-                let mut s = String::new();
-                s.push_str(f);
-                s.push_str(".pdf");
-                (
-                    s.clone(),
-                    Document::load(s.as_str())
-                        .with_context(|| format!("File not found: {}", f))
-                        .unwrap(),
-                )
+        for mut replacement in data
+            .get_occurrences::<String>("replace-page")
+            .into_iter()
+            .flatten()
+        {
+            let page = replacement.next().unwrap();
+            if !page.parse::<u32>().is_ok_and(|page| page >= 1) {
+                return Err(anyhow::anyhow!(
+                    "--replace-page needs a page number starting at 1, not {page:?}"
+                ));
             }
-        })
-        .collect::<Vec<_>>();
-
-    println!("Compressing PDFs...");
+        }
 
-    for (name, mut doc) in documents {
-        let mut compressed_name: String = name[0..(name.len() - 4)].into();
-        compressed_name.push_str("_compressed.pdf");
+        if let Some(n) = data
+            .get_one::<u32>("keep-bookmarks-from")
+            .filter(|n| **n as usize > inputs && !directories)
+        {
+            return Err(anyhow::anyhow!(
+                "--keep-bookmarks-from {} can't be combined with only {} input PDFs",
+                n,
+                inputs
+            ));
+        }
+    }
 
-        println!("Compressing {name:?} to {compressed_name:?}");
+    if command == "compress" {
+        if let (Some(version), Some("on")) = (
+            data.get_one::<String>("output-version")
+                .filter(|version| version.as_str() < "1.5"),
+            data.get_one::<String>("xref-stream").map(String::as_str),
+        ) {
+            return Err(anyhow::anyhow!(
+                "--xref-stream on needs PDF 1.5 or newer, not --output-version {version}"
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_angle(s: &str) -> Result<i64, String> {
+    match s.trim().parse::<i64>() {
+        Ok(angle) if angle % 90 == 0 => Ok(angle),
+        _ => Err(format!(
+            "Invalid angle {s:?}, expected a multiple of 90 like 90 or -90"
+        )),
+    }
+}
+
+/// Parse a positive number, like a `--factor` of `0.5`.
+fn parse_positive(s: &str) -> Result<f32, String> {
+    match s.trim().parse::<f32>() {
+        Ok(number) if number > 0.0 && number.is_finite() => Ok(number),
+        _ => Err(format!(
+            "Invalid number {s:?}, expected a positive number like 0.5 or 150"
+        )),
+    }
+}
+
+/// Parse a `--suffix`, which has to keep the compressed file next to the original and apart
+/// from it.
+fn parse_suffix(s: &str) -> Result<String, String> {
+    if s.is_empty() {
+        return Err("The suffix can't be empty, use --in-place to replace the originals".into());
+    }
+    if s.contains(['/', std::path::MAIN_SEPARATOR]) {
+        return Err(format!(
+            "Invalid suffix {s:?}, it can't contain a path separator"
+        ));
+    }
+
+    Ok(s.to_owned())
+}
+
+/// Parse a `llx,lly,urx,ury` rectangle in points.
+fn parse_rect(s: &str) -> Result<[f32; 4], String> {
+    let numbers = s
+        .split(',')
+        .map(|n| n.trim().parse::<f32>().map_err(|e| format!("{n:?}: {e}")))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let rect: [f32; 4] = numbers
+        .try_into()
+        .map_err(|_| "expected four numbers, like 36,36,576,756".to_string())?;
+    if rect[0] >= rect[2] || rect[1] >= rect[3] {
+        return Err("the lower left corner has to be below and left of the upper right one".into());
+    }
+
+    Ok(rect)
+}
+
+/// Parse an `r,g,b` color with components between 0 and 1.
+fn parse_color(s: &str) -> Result<[f32; 3], String> {
+    let components = s
+        .split(',')
+        .map(|c| c.trim().parse::<f32>().map_err(|e| format!("{c:?}: {e}")))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let color: [f32; 3] = components
+        .try_into()
+        .map_err(|_| "expected three components, like 0,0,1".to_string())?;
+
+    if color.iter().any(|c| !(0.0..=1.0).contains(c)) {
+        return Err("color components must be between 0 and 1".into());
+    }
+
+    Ok(color)
+}
+
+/// Print a `--progress-json` event to stderr.
+fn progress(data: &ArgMatches, event: serde_json::Value) {
+    if data.get_flag("progress-json") {
+        eprintln!("{event}");
+    }
+}
+
+/// The JSON line `--errors-json` prints for `error` about `file`. The file and the kind are taken
+/// from the error itself when it knows them.
+fn error_json(
+    file: Option<&str>,
+    error: &anyhow::Error,
+    kind: Option<&'static str>,
+) -> serde_json::Value {
+    let known = error.chain().find_map(|e| e.downcast_ref::<PdftError>());
+    let io = error.chain().any(|e| e.is::<io::Error>());
+    let kind = kind
+        .or(known.map(PdftError::kind))
+        .unwrap_or(if io { "io" } else { "other" });
+
+    json!({
+        "file": file.or(known.and_then(PdftError::file)),
+        "error": format!("{error:#}"),
+        "kind": kind,
+    })
+}
+
+/// Print that `file` failed with `error` to stderr and keep going, after `what` was tried with it.
+fn print_failure(data: &ArgMatches, what: &str, file: &str, error: &anyhow::Error) {
+    if data.get_flag("errors-json") {
+        eprintln!("{}", error_json(Some(file), error, None));
+    } else {
+        eprintln!("Failed to {what} {file}: {error:#}");
+    }
+}
+
+/// Print the message that ends every successful command, which wrote the files `outputs`.
+fn print_done<S: AsRef<str>>(data: &ArgMatches, outputs: &[S]) {
+    match &outputs.iter().map(AsRef::as_ref).collect::<Vec<_>>()[..] {
+        [output] => progress(data, json!({"phase": "done", "output": output})),
+        outputs => progress(data, json!({"phase": "done", "outputs": outputs})),
+    }
+
+    if data.get_flag("no-emoji") {
+        println!("All done!");
+    } else {
+        println!("🦀 All done! 🦀");
+    }
+}
+
+/// Finish the file `output` right after it is written, by applying `--chmod` and running
+/// `--smoke-test` on it. Every command calls this for every file it writes.
+fn finish_output(data: &ArgMatches, output: &str) -> Result<()> {
+    if let Some(mode) = data.get_one::<u32>("chmod") {
+        set_mode(output, *mode)?;
+    }
+
+    smoke_test(data, output)
+}
+
+/// Apply `--smoke-test` to the written file `output`.
+fn smoke_test(data: &ArgMatches, output: &str) -> Result<()> {
+    let Some(command) = data.get_one::<String>("smoke-test") else {
+        return Ok(());
+    };
+
+    let mut words = command.split_whitespace().collect::<Vec<_>>();
+    if !words.iter().any(|word| word.contains("{}")) {
+        words.push("{}");
+    }
+    let words = words
+        .iter()
+        .map(|word| word.replace("{}", output))
+        .collect::<Vec<_>>();
+    let Some((program, args)) = words.split_first() else {
+        return Err(anyhow::anyhow!("--smoke-test is empty"));
+    };
+
+    println!("Checking {output} with {program}...");
+
+    let status = std::process::Command::new(program)
+        .args(args)
+        .status()
+        .with_context(|| format!("Failed to run the smoke test {program}"))?;
+    if !status.success() {
+        return Err(anyhow::anyhow!(
+            "The smoke test {:?} failed on {output} ({status})",
+            words.join(" ")
+        ));
+    }
+
+    Ok(())
+}
+
+/// Parse a `--chmod` mode, given in octal like `600`.
+fn parse_mode(mode: &str) -> Result<u32, String> {
+    u32::from_str_radix(mode, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o7777)
+        .ok_or_else(|| format!("{mode} is not an octal file mode like 600 or 0644"))
+}
+
+#[cfg(unix)]
+fn set_mode(file: &str, mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    std::fs::set_permissions(file, std::fs::Permissions::from_mode(mode))
+        .with_context(|| format!("Failed to set the permissions of {file}"))
+}
+
+#[cfg(not(unix))]
+fn set_mode(_file: &str, _mode: u32) -> Result<()> {
+    Err(anyhow::anyhow!("--chmod is only supported on Unix"))
+}
+
+fn xref_stream_arg() -> Arg {
+    Arg::new("xref-stream")
+        .long("xref-stream")
+        .value_parser(["on", "off"])
+        .help("Write the cross-reference as a compressed stream (on, PDF 1.5) or as a classic table (off), by default as in the input, and always a table for PDFs older than 1.5")
+}
+
+fn stream_filter_arg() -> Arg {
+    Arg::new("stream-filter")
+        .long("stream-filter")
+        .value_parser(["none", "flate", "ascii85"])
+        .default_value("flate")
+        .help("How to encode the streams: compressed (flate), uncompressed to read and diff the output (none), or compressed as printable text (ascii85)")
+}
+
+/// Encode the streams of a document that is about to be saved as `--stream-filter` asks.
+fn encode_streams(doc: &mut Document, data: &ArgMatches) {
+    match data.get_one::<String>("stream-filter").map(String::as_str) {
+        // Images stay compressed, their samples are no easier to read without it
+        Some("none") => doc.decompress(),
+        Some("ascii85") => {
+            doc.compress();
+            normalize::ascii85_streams(doc);
+        }
+        _ => doc.compress(),
+    }
+}
+
+fn resolve_references_arg() -> Arg {
+    Arg::new("resolve-references")
+        .long("resolve-references")
+        .action(ArgAction::SetTrue)
+        .help("Remove references to references and store numbers and names directly, for strict readers")
+}
+
+/// Apply `--resolve-references` to a document that is about to be saved.
+fn resolve_references(doc: &mut Document, data: &ArgMatches) {
+    if data.get_flag("resolve-references") {
+        let replaced = normalize::resolve_references(doc);
+        println!("Resolved {replaced} indirect references");
+    }
+}
+
+/// Apply `--target-ppi` and `--report` to a document that is about to be saved.
+fn target_ppi(doc: &mut Document, data: &ArgMatches) {
+    let Some(target) = data.get_one::<f32>("target-ppi") else {
+        return;
+    };
+
+    let resampled = images::downsample_to_ppi(doc, *target);
+    let scaled = resampled
+        .iter()
+        .filter(|image| image.after.is_some())
+        .count();
+    println!(
+        "Scaled {scaled} of {} images above {target} ppi down",
+        resampled.len()
+    );
+
+    if !data.get_flag("report") {
+        return;
+    }
+    for image in &resampled {
+        let size = |(width, height): (i64, i64)| format!("{width}x{height}");
+        println!(
+            "    page {:<4} obj {:<6} {:>11} px -> {:>11} px {:>6.0} ppi -> {:>4.0} ppi{}",
+            image.page,
+            image.id.0,
+            size(image.before),
+            image.after.map(size).unwrap_or_else(|| size(image.before)),
+            image.ppi,
+            image.ppi_after(),
+            if image.after.is_none() {
+                "  (kept, can't be decoded)"
+            } else {
+                ""
+            },
+        );
+    }
+}
+
+/// Apply `--strip-icc` and `--aggressive` to a document that is about to be saved.
+fn strip_icc(doc: &mut Document, data: &ArgMatches) {
+    if !data.get_flag("strip-icc") {
+        return;
+    }
+
+    if data.get_flag("aggressive") {
+        let removed = normalize::strip_icc_profiles(doc);
+        println!("Replaced {removed} ICC profiles by device colors");
+    } else {
+        let removed = normalize::dedup_icc_profiles(doc);
+        println!("Removed {removed} duplicate ICC profiles");
+    }
+}
+
+/// Apply `--remove-hidden-ocg` to a document that is about to be saved.
+fn remove_hidden_layers(doc: &mut Document, data: &ArgMatches) {
+    if data.get_flag("remove-hidden-ocg") {
+        let (sections, layers) = layers::strip_hidden_layers(doc);
+        println!("Removed {layers} hidden layers ({sections} marked content sections)");
+    }
+}
+
+fn clip_to_crop_box_arg() -> Arg {
+    Arg::new("clip-to-cropbox")
+        .long("clip-to-cropbox")
+        .action(ArgAction::SetTrue)
+        .help("Make the crop box (or --box-type) of every page its media box, so only the visible area is kept")
+}
+
+/// Apply `--clip-to-cropbox` to the pages of a document.
+fn clip_to_crop_box(doc: &mut Document, data: &ArgMatches) {
+    if data.get_flag("clip-to-cropbox") {
+        let clipped = pages::clip_to_box(doc, box_type(data));
+        println!(
+            "Clipped {clipped} pages to their {} box",
+            data.get_one::<String>("box-type").unwrap()
+        );
+    }
+}
+
+fn box_type_arg() -> Arg {
+    Arg::new("box-type")
+        .long("box-type")
+        .value_parser(pages::PAGE_BOXES.map(|(name, _)| name))
+        .default_value("crop")
+        .help(
+            "The page box to clip to or scale: the media box, the crop box that viewers show, or \
+             for print the bleed, trim or art box. Missing boxes are the crop box",
+        )
+}
+
+/// The page box `--box-type` picks, like `b"TrimBox"`.
+fn box_type(data: &ArgMatches) -> &'static [u8] {
+    let name = data.get_one::<String>("box-type").unwrap();
+
+    pages::PAGE_BOXES
+        .iter()
+        .find(|(box_name, _)| box_name == name)
+        .map(|(_, key)| *key)
+        .unwrap()
+}
+
+fn strip_thumbnails_arg() -> Arg {
+    Arg::new("strip-thumbnails")
+        .long("strip-thumbnails")
+        .action(ArgAction::SetTrue)
+        .help("Remove embedded page thumbnails, viewers generate their own")
+}
+
+/// Apply `--strip-thumbnails` to a document that is about to be saved.
+fn strip_thumbnails(doc: &mut Document, data: &ArgMatches) {
+    if data.get_flag("strip-thumbnails") {
+        let (thumbnails, objects) = pages::strip_thumbnails(doc);
+        println!("Removed {thumbnails} page thumbnails ({objects} objects)");
+    }
+}
+
+fn strip_attachments_arg() -> Arg {
+    Arg::new("strip-attachments")
+        .long("strip-attachments")
+        .action(ArgAction::SetTrue)
+        .help("Remove embedded files and file attachment annotations, before passing a PDF on")
+}
+
+/// Apply `--strip-attachments` to a document that is about to be saved.
+fn strip_attachments(doc: &mut Document, data: &ArgMatches) {
+    if data.get_flag("strip-attachments") {
+        let (attachments, bytes) = attachments::strip_attachments(doc);
+        println!("Removed {attachments} attachments ({})", format_size(bytes));
+    }
+}
+
+fn remove_links_arg() -> Arg {
+    Arg::new("remove-links")
+        .long("remove-links")
+        .value_name("LINKS")
+        .value_parser(["external", "all"])
+        .num_args(0..=1)
+        .default_missing_value("external")
+        .help(
+            "Remove link annotations. Only the ones leading out of the document (to URLs, other \
+             files or applications) unless \"all\" is given",
+        )
+}
+
+/// Apply `--remove-links` to a document that is about to be saved.
+fn remove_links(doc: &mut Document, data: &ArgMatches) {
+    if let Some(links) = data.get_one::<String>("remove-links") {
+        let removed = pages::remove_links(doc, links == "external");
+        println!("Removed {removed} links");
+    }
+}
+
+fn skip_bad_pages_arg() -> Arg {
+    Arg::new("skip-bad-pages")
+        .long("skip-bad-pages")
+        .action(ArgAction::SetTrue)
+        .help("Leave out pages with a missing or damaged content stream, size or resources")
+}
+
+/// The pages `--skip-bad-pages` leaves out, after telling which ones and why.
+fn skipped_pages(doc: &Document, data: &ArgMatches) -> BTreeSet<u32> {
+    if !data.get_flag("skip-bad-pages") {
+        return BTreeSet::new();
+    }
+
+    let bad = pages::bad_pages(doc);
+    if !bad.is_empty() {
+        println!("Warning: skipping {} damaged pages:", bad.len());
+        for (page, problem) in &bad {
+            println!("  page {page}: {problem}");
+        }
+    }
+
+    bad.into_keys().collect()
+}
+
+fn output_intent_arg() -> Arg {
+    Arg::new("output-intent")
+        .long("output-intent")
+        .value_name("ICC")
+        .help("Embed an ICC profile as the output intent, the color space the PDF is meant to be printed in")
+}
+
+/// Apply `--output-intent` to a document that is about to be saved, replacing the output intents
+/// it already had.
+fn add_output_intent(doc: &mut Document, data: &ArgMatches) -> Result<()> {
+    let Some(path) = data.get_one::<String>("output-intent") else {
+        return Ok(());
+    };
+
+    let profile =
+        std::fs::read(path).with_context(|| format!("Failed to read ICC profile {path}"))?;
+
+    // The color space of the profile is in its header, readers need to know the components
+    let components = match profile.get(16..20) {
+        Some(b"GRAY") => 1,
+        Some(b"RGB ") => 3,
+        Some(b"CMYK") => 4,
+        _ => {
+            return Err(anyhow::anyhow!(
+                "{path} is not an ICC profile for a gray, RGB or CMYK color space"
+            ))
+        }
+    };
+
+    let condition = Path::new(path)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "Custom".into());
+
+    let mut stream = Stream::new(dictionary! { "N" => components }, profile);
+    let _ = stream.compress();
+    let profile_id = doc.add_object(stream);
+
+    let intent_id = doc.add_object(dictionary! {
+        "Type" => "OutputIntent",
+        "S" => "GTS_PDFX",
+        "OutputConditionIdentifier" => encode_text_string(&condition),
+        "Info" => encode_text_string(&condition),
+        "DestOutputProfile" => profile_id,
+    });
+
+    doc.catalog_mut()
+        .with_context(|| "Catalog root not found")?
+        .set("OutputIntents", vec![Object::Reference(intent_id)]);
+
+    println!("Embedded output intent {condition}");
+
+    Ok(())
+}
+
+fn remove_empty_pages_args() -> [Arg; 2] {
+    [
+        Arg::new("remove-empty-pages")
+            .long("remove-empty-pages")
+            .action(ArgAction::SetTrue)
+            .help("Leave out pages with (almost) nothing inside their crop box"),
+        Arg::new("empty-threshold")
+            .long("empty-threshold")
+            .value_name("PERCENT")
+            .value_parser(clap::value_parser!(f32))
+            .default_value("0.01")
+            .requires("remove-empty-pages")
+            .help("Pages with content on less than this part of the visible area are empty"),
+    ]
+}
+
+/// The pages `--remove-empty-pages` leaves out, after telling which ones and why.
+fn empty_pages(doc: &Document, data: &ArgMatches) -> BTreeSet<u32> {
+    if !data.get_flag("remove-empty-pages") {
+        return BTreeSet::new();
+    }
+
+    let threshold = *data.get_one::<f32>("empty-threshold").unwrap();
+    let empty = pages::empty_pages(doc, threshold / 100.0);
+    if !empty.is_empty() {
+        println!("Removing {} empty pages:", empty.len());
+        for (page, covered) in &empty {
+            println!(
+                "  page {page}: content covers {:.1}% of the page",
+                covered * 100.0
+            );
+        }
+    }
+
+    empty.into_keys().collect()
+}
+
+fn drop_textless_arg() -> Arg {
+    Arg::new("drop-textless")
+        .long("drop-textless")
+        .action(ArgAction::SetTrue)
+        .help("Leave out pages without any extractable text, like scans that weren't OCR'd")
+}
+
+/// The pages `--drop-textless` leaves out, after telling which ones.
+fn textless_pages(doc: &Document, data: &ArgMatches) -> BTreeSet<u32> {
+    if !data.get_flag("drop-textless") {
+        return BTreeSet::new();
+    }
+
+    // Text that can't be extracted doesn't count either, as with `--contains`
+    let textless = doc
+        .get_pages()
+        .into_keys()
+        .filter(|page| {
+            doc.extract_text(&[*page])
+                .map_or(true, |text| text.trim().is_empty())
+        })
+        .collect::<BTreeSet<_>>();
+
+    if !textless.is_empty() {
+        println!(
+            "Removing {} pages without text: {}",
+            textless.len(),
+            textless.iter().join(", ")
+        );
+    }
+
+    textless
+}
+
+fn title_from_filename_arg() -> Arg {
+    Arg::new("title-from-filename")
+        .long("title-from-filename")
+        .action(ArgAction::SetTrue)
+        .help("Use the name of the output file as the title, when there is no title yet")
+}
+
+fn jobs_arg() -> Arg {
+    Arg::new("jobs")
+        .short('j')
+        .long("jobs")
+        .value_parser(clap::value_parser!(u32).range(1..))
+        .help("How many PDFs to load at the same time, defaults to the number of CPUs")
+}
+
+fn mkdir_arg() -> Arg {
+    Arg::new("mkdir")
+        .long("mkdir")
+        .action(ArgAction::SetTrue)
+        .help(
+            "Create the --out directory and its parents when they don't exist, instead of failing",
+        )
+}
+
+/// The `--out` directory to write the parts to, created with `--mkdir` when it doesn't exist.
+fn output_dir(data: &ArgMatches) -> Result<&Path> {
+    let out = Path::new(data.get_one::<String>("out").unwrap());
+
+    if !out.is_dir() {
+        if out.exists() {
+            return Err(anyhow::anyhow!(
+                "Output directory {} is not a directory",
+                out.display()
+            ));
+        }
+        if !data.get_flag("mkdir") {
+            return Err(anyhow::anyhow!(
+                "Output directory {} does not exist, use --mkdir to create it",
+                out.display()
+            ));
+        }
+
+        println!("Creating output directory {}...", out.display());
+        std::fs::create_dir_all(out)
+            .with_context(|| format!("Failed to create output directory {}", out.display()))?;
+    }
+
+    Ok(out)
+}
+
+fn mmap_arg() -> Arg {
+    Arg::new("mmap")
+        .long("mmap")
+        .action(ArgAction::SetTrue)
+        .help("Memory map the input instead of reading it into memory, the file must not change while it is used")
+}
+
+/// Load a single PDF, checking it actually looks like one first. Files that only have a `.pdf`
+/// name (like a saved error page) can otherwise partially load and fail much later.
+///
+/// With `mmap` the file is paged in by the OS as it is parsed, instead of being read into a
+/// buffer first, which keeps the peak memory use down for huge files. With `recover` a file that
+/// fails to load gets a new cross-reference table for the objects found in it, see
+/// [`recover::rebuild_xref`].
+fn load_pdf(name: &str, mmap: bool, recover: bool) -> Result<Document> {
+    let (mapped, read, downloaded);
+    let bytes: &[u8] = if is_url(name) {
+        downloaded = download(name)?;
+        &downloaded
+    } else if mmap {
+        let file = std::fs::File::open(name).map_err(|error| open_error(name, error))?;
+        // Safety: the map is only read while parsing, changes to the file by other processes
+        // at the same time are the caveat documented on --mmap
+        mapped = unsafe { memmap2::Mmap::map(&file) }
+            .with_context(|| format!("Failed to memory map {name}"))?;
+        &mapped
+    } else {
+        read = std::fs::read(name).map_err(|error| open_error(name, error))?;
+        &read
+    };
+
+    // Gzipped PDFs are recognized by their content, whatever they are called
+    let inflated;
+    let bytes = if is_gzip(bytes) {
+        inflated = gunzip(name, bytes)?;
+        &inflated
+    } else {
+        bytes
+    };
+
+    let loaded = check_pdf(name, bytes).and_then(|_| {
+        Document::load_mem(bytes).map_err(|source| {
+            PdftError::Unreadable {
+                name: name.into(),
+                source,
+            }
+            .into()
+        })
+    });
+    let error = match loaded {
+        Err(error) if recover => error,
+        loaded => return loaded,
+    };
+
+    let recovered = recover::rebuild_xref(bytes)
+        .and_then(|bytes| Document::load_mem(&bytes).ok())
+        .filter(|doc| !doc.get_pages().is_empty());
+    match recovered {
+        Some(doc) => {
+            println!("Warning: recovered {name}, it may be incomplete ({error:#})");
+            Ok(doc)
+        }
+        None => Err(error.context(format!("Failed to recover {name}"))),
+    }
+}
+
+/// Load a PDF with [`load_pdf`] for a command that rewrites it. lopdf doesn't decrypt, so an
+/// encrypted file would be written back as ciphertext under a dropped /Encrypt, it is
+/// [`PdftError::Encrypted`] instead. Only decrypt, info and validate read encrypted files.
+fn load_unencrypted(name: &str, mmap: bool, recover: bool) -> Result<Document> {
+    let doc = load_pdf(name, mmap, recover)?;
+    if doc.is_encrypted() {
+        return Err(PdftError::Encrypted { name: name.into() }.into());
+    }
+    Ok(doc)
+}
+
+/// The error for failing to open or read the input `name`. Only a missing file is
+/// [`PdftError::FileNotFound`], other failures like missing permissions keep their own message.
+fn open_error(name: &str, error: io::Error) -> anyhow::Error {
+    match error.kind() {
+        io::ErrorKind::NotFound => PdftError::FileNotFound {
+            name: name.into(),
+            source: error,
+        }
+        .into(),
+        _ => anyhow::Error::new(error).context(format!("Failed to read {name}")),
+    }
+}
+
+/// Read the PDF `name` as it is, without parsing it, e.g. to embed it.
+fn read_pdf(name: &str) -> Result<Vec<u8>> {
+    let bytes = if is_url(name) {
+        download(name)?
+    } else {
+        std::fs::read(name).map_err(|error| open_error(name, error))?
+    };
+    let bytes = if is_gzip(&bytes) {
+        gunzip(name, &bytes)?
+    } else {
+        bytes
+    };
+
+    check_pdf(name, &bytes)?;
+
+    Ok(bytes)
+}
+
+fn is_gzip(bytes: &[u8]) -> bool {
+    bytes.starts_with(&[0x1f, 0x8b])
+}
+
+fn gunzip(name: &str, bytes: &[u8]) -> Result<Vec<u8>> {
+    let mut decoder = flate2::read::GzDecoder::new(bytes);
+    let mut inflated = Vec::new();
+    io::Read::read_to_end(&mut decoder, &mut inflated)
+        .with_context(|| format!("Failed to decompress {name}"))?;
+
+    Ok(inflated)
+}
+
+/// Quick checks that `bytes` are a whole PDF, to give a clearer error than the parser would.
+fn check_pdf(name: &str, bytes: &[u8]) -> Result<()> {
+    // Readers accept the header anywhere in the first 1024 bytes, and the end marker in the last
+    let contains = |haystack: &[u8], needle: &[u8]| {
+        haystack
+            .windows(needle.len())
+            .any(|window| window == needle)
+    };
+    if !contains(&bytes[..bytes.len().min(1024)], b"%PDF-") {
+        return Err(PdftError::InvalidPdf {
+            name: name.into(),
+            reason: "missing %PDF header",
+        }
+        .into());
+    }
+    if !contains(&bytes[bytes.len().saturating_sub(1024)..], b"%%EOF") {
+        return Err(PdftError::InvalidPdf {
+            name: name.into(),
+            reason: "missing %%EOF, is it truncated?",
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+/// The path of the input PDF `f`, which gets `.pdf` added when it has no PDF extension, unless
+/// `--raw-paths` is given.
+fn input_path(f: &str, data: &ArgMatches) -> String {
+    if data.get_flag("raw-paths") || f.ends_with(".pdf") || f.ends_with(".pdf.gz") {
+        f.to_owned()
+    } else {
+        format!("{f}.pdf")
+    }
+}
+
+/// The path of the output PDF `s`, like [`input_path`] but gzipped outputs aren't written.
+fn output_path(s: &str, data: &ArgMatches) -> String {
+    if data.get_flag("raw-paths") || s.ends_with(".pdf") {
+        s.to_owned()
+    } else {
+        format!("{s}.pdf")
+    }
+}
+
+/// The name of the PDF `name` without its extension, `.pdf` or `.pdf.gz`, to derive the names of
+/// outputs from.
+fn name_stem(name: &str) -> &str {
+    let name = name.strip_suffix(".gz").unwrap_or(name);
+    name.strip_suffix(".pdf").unwrap_or(name)
+}
+
+fn is_url(name: &str) -> bool {
+    name.starts_with("http://") || name.starts_with("https://")
+}
+
+/// Download a PDF into memory.
+#[cfg(feature = "net")]
+fn download(url: &str) -> Result<Vec<u8>> {
+    println!("Downloading {url}...");
+
+    reqwest::blocking::get(url)
+        .and_then(|response| response.error_for_status())
+        .and_then(|response| response.bytes())
+        .map(|bytes| bytes.to_vec())
+        .with_context(|| format!("Failed to download {url}"))
+}
+
+#[cfg(not(feature = "net"))]
+fn download(url: &str) -> Result<Vec<u8>> {
+    Err(anyhow::anyhow!(
+        "Can't download {url}, this build of pdft doesn't have the net feature"
+    ))
+}
+
+/// Turn the inputs into a list of files: directories are replaced by the PDFs in them, sorted
+/// by name, and the `.pdf` extension is added where it was left out. URLs are used as they are.
+///
+/// With `--since` only the files from directories modified at or after that time are kept,
+/// files that were named explicitly are always used.
+fn expand_inputs<'a>(
+    pdfs: impl Iterator<Item = &'a String>,
+    data: &ArgMatches,
+) -> Result<Vec<String>> {
+    let since = data
+        .try_get_one::<std::time::SystemTime>("since")
+        .ok()
+        .flatten();
+    let mut names = Vec::new();
+
+    for f in pdfs {
+        if is_url(f) {
+            names.push(f.clone());
+            continue;
+        }
+
+        if !Path::new(f).is_dir() {
+            names.push(input_path(f, data));
+            continue;
+        }
+
+        let mut files = std::fs::read_dir(f)
+            .with_context(|| format!("Failed to read directory {f}"))?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| {
+                let name = entry.file_name().to_string_lossy().to_ascii_lowercase();
+                name.ends_with(".pdf") || name.ends_with(".pdf.gz")
+            })
+            .filter(|entry| {
+                since.is_none_or(|since| {
+                    entry
+                        .metadata()
+                        .and_then(|metadata| metadata.modified())
+                        .is_ok_and(|modified| modified >= *since)
+                })
+            })
+            .map(|entry| entry.path().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        files.sort();
+
+        if files.is_empty() {
+            println!("No PDFs found in {f}, skipping it");
+        }
+        names.extend(files);
+    }
+
+    if names.is_empty() {
+        return Err(anyhow::anyhow!("No PDFs found"));
+    }
+
+    Ok(names)
+}
+
+/// Parse a date like `2024-01-01`, `2024/01/01` or `2024-01-01 12:30:00` (UTC).
+fn parse_date(s: &str) -> Result<std::time::SystemTime, String> {
+    let invalid =
+        || format!("Invalid date {s:?}, expected something like 2024-01-31 or 2024-01-31T12:30");
+
+    let (date, time) = match s.trim().split_once(['T', ' ']) {
+        Some((date, time)) => (date, time),
+        None => (s.trim(), ""),
+    };
+
+    let date = date
+        .split(['-', '/', '.'])
+        .map(|n| n.parse::<i64>().ok())
+        .collect::<Option<Vec<_>>>()
+        .filter(|date| date.len() == 3)
+        .ok_or_else(invalid)?;
+    let time = time
+        .trim_end_matches('Z')
+        .split(':')
+        .filter(|n| !n.is_empty())
+        .map(|n| n.parse::<i64>().ok())
+        .collect::<Option<Vec<_>>>()
+        .filter(|time| time.len() <= 3)
+        .ok_or_else(invalid)?;
+
+    let (year, month, day) = (date[0], date[1], date[2]);
+    let [hour, minute, second] = std::array::from_fn(|i| time.get(i).copied().unwrap_or(0));
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 59
+    {
+        return Err(invalid());
+    }
+
+    // Days since 1970-01-01 of a date in the proleptic Gregorian calendar
+    let (y, m) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = y.div_euclid(400);
+    let year_of_era = y - era * 400;
+    let day_of_year = (153 * m + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    let seconds = days * 86_400 + hour * 3600 + minute * 60 + second;
+    let offset = std::time::Duration::from_secs(seconds.unsigned_abs());
+
+    Ok(if seconds >= 0 {
+        std::time::UNIX_EPOCH + offset
+    } else {
+        std::time::UNIX_EPOCH - offset
+    })
+}
+
+/// Files that failed with `--keep-going`, with their errors.
+type Failures = Vec<(String, anyhow::Error)>;
+
+/// Load all `pdfs`, see [`expand_inputs`]. With `--jobs` other than
+/// 1 the files are loaded in parallel, the order of the returned documents is always the same.
+fn load_documents<'a>(
+    pdfs: impl Iterator<Item = &'a String>,
+    data: &ArgMatches,
+) -> Result<Vec<(String, Document)>> {
+    let (documents, failures) = try_load_documents(pdfs, data)?;
+
+    match failures.into_iter().next() {
+        Some((_, error)) => Err(error),
+        None => Ok(documents),
+    }
+}
+
+/// Load all `pdfs` like [`load_documents`], but keep going when some of them fail to load.
+/// Returns the loaded documents and the files that failed with their errors, both in order.
+fn try_load_documents<'a>(
+    pdfs: impl Iterator<Item = &'a String>,
+    data: &ArgMatches,
+) -> Result<(Vec<(String, Document)>, Failures)> {
+    let names = expand_inputs(pdfs, data)?;
+
+    // Only the subcommands working on huge single files have --mmap
+    let mmap = matches!(data.try_get_one::<bool>("mmap"), Ok(Some(true)));
+    let loaded = AtomicUsize::new(0);
+    let load = |name: &String| {
+        let doc = load_unencrypted(name, mmap, data.get_flag("recover"));
+        let current = loaded.fetch_add(1, Ordering::Relaxed) + 1;
+        progress(
+            data,
+            json!({"phase": "loading", "current": current, "total": names.len(), "file": name}),
+        );
+        (name.clone(), doc)
+    };
+
+    let jobs = data
+        .get_one::<u32>("jobs")
+        .map(|jobs| *jobs as usize)
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, usize::from));
+
+    let results: Vec<_> = if jobs == 1 {
+        names.iter().map(load).collect()
+    } else {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs)
+            .build()
+            .with_context(|| "Failed to start the loading threads")?
+            .install(|| names.par_iter().map(load).collect())
+    };
+
+    let mut documents = Vec::new();
+    let mut failures = Vec::new();
+    for (name, doc) in results {
+        match doc {
+            Ok(doc) => documents.push((name, doc)),
+            Err(error) => failures.push((name, error)),
+        }
+    }
+
+    Ok((documents, failures))
+}
+
+/// Apply `--xref-stream` to a document that is about to be saved.
+///
+/// lopdf can't write object streams, objects are always written out individually. What can be
+/// chosen is whether the cross-reference is written as a (PDF 1.5) stream or as the classic table
+/// older tools expect. Objects are written in the order of their numbers, so saving the same
+/// document twice gives the same bytes as long as the file identifier is the same too, see
+/// `--deterministic`.
+fn set_xref_type(doc: &mut Document, data: &ArgMatches) {
+    // Cross-reference streams were only added in PDF 1.5
+    let default = (doc.version.as_str() < "1.5").then_some("off");
+
+    match data
+        .get_one::<String>("xref-stream")
+        .map(String::as_str)
+        .or(default)
+    {
+        Some("on") => doc.reference_table.cross_reference_type = XrefType::CrossReferenceStream,
+        Some("off") => {
+            doc.reference_table.cross_reference_type = XrefType::CrossReferenceTable;
+
+            // A trailer read from a cross-reference stream still carries the stream entries
+            for key in [
+                &b"Type"[..],
+                b"W",
+                b"Index",
+                b"Length",
+                b"Filter",
+                b"DecodeParms",
+                b"XRefStm",
+            ] {
+                doc.trailer.remove(key);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn compress_pdfs(data: &ArgMatches) -> Result<()> {
+    println!("Checking input validity...");
+
+    let pdfs = data
+        .get_many::<String>("PDFs")
+        .with_context(|| "No PDFs found to merge")?
+        .peekable();
+
+    println!("Loading PDFs into memory...");
+
+    let keep_going = data.get_flag("keep-going");
+    let (documents, mut failures) = if keep_going {
+        try_load_documents(pdfs, data)?
+    } else {
+        (load_documents(pdfs, data)?, Vec::new())
+    };
+    for (name, error) in &failures {
+        print_failure(data, "load", name, error);
+    }
+
+    println!("Compressing PDFs...");
+
+    // File name with its size before and after
+    let mut stats = Vec::new();
+    let total = documents.len() + failures.len();
+    let mut outputs = Vec::new();
+
+    // The input and output of every compressed file by the hash of its content, for --dedup
+    let mut compressed: BTreeMap<[u8; 16], (String, String)> = BTreeMap::new();
+    let mut deduplicated = Vec::new();
+
+    for (current, (name, doc)) in documents.into_iter().enumerate() {
+        progress(
+            data,
+            json!({"phase": "compressing", "current": current + 1, "total": total, "file": name}),
+        );
+
+        let hash = (data.get_flag("dedup") && !is_url(&name))
+            .then(|| std::fs::read(&name).ok())
+            .flatten()
+            .map(|bytes| md5::compute(bytes).0);
+        let duplicate_of = hash.and_then(|hash| compressed.get(&hash)).cloned();
+
+        let result = match &duplicate_of {
+            Some((original, output)) => {
+                println!("{name:?} is the same file as {original:?}, copying {output:?}");
+                copy_compressed(&name, output, data)
+            }
+            None => compress_pdf(&name, doc, data),
+        };
+
+        match result {
+            Ok((output, original_size, compressed_size)) => {
+                match (hash, &duplicate_of) {
+                    (_, Some((original, _))) => deduplicated.push(format!("{name} ({original})")),
+                    (Some(hash), None) => {
+                        compressed.insert(hash, (name.clone(), output.clone()));
+                    }
+                    (None, None) => {}
+                }
+                outputs.push(output);
+                stats.push((name, original_size, compressed_size));
+            }
+            Err(error) if keep_going => {
+                print_failure(data, "compress", &name, &error);
+                failures.push((name, error));
+            }
+            Err(error) => return Err(error),
+        }
+    }
+
+    if !deduplicated.is_empty() {
+        println!(
+            "Copied {} files that are the same as an earlier one: {}",
+            deduplicated.len(),
+            deduplicated.join(", ")
+        );
+    }
+
+    if !data.get_flag("quiet") && (!stats.is_empty() || failures.is_empty()) {
+        print_compression_stats(&stats);
+    }
+
+    if failures.is_empty() {
+        print_done(data, &outputs);
+        return Ok(());
+    }
+
+    print_failure_summary(stats.len(), "compressed", &failures);
+
+    Err(if stats.is_empty() {
+        PdftError::NothingProcessed { total }
+    } else {
+        PdftError::SomeFailed {
+            failed: failures.len(),
+            total,
+        }
+    }
+    .into())
+}
+
+/// Compress `doc`, loaded from `name`, into its output file. Returns the name of the output with
+/// the size of the original and the compressed file.
+fn compress_pdf(name: &str, mut doc: Document, data: &ArgMatches) -> Result<(String, u64, u64)> {
+    let in_place = data.get_flag("in-place");
+    // Some files that are already optimized grow a little when they are saved again
+    let only_if_smaller = in_place || data.get_flag("only-if-smaller");
+
+    if in_place && is_url(name) {
+        return Err(anyhow::anyhow!(
+            "Can't compress {name} in place, it is a URL"
+        ));
+    }
+    if in_place && name.ends_with(".gz") {
+        return Err(anyhow::anyhow!(
+            "Can't compress {name} in place, the output would not be gzipped"
+        ));
+    }
+
+    let compressed_name = compressed_name(name, data);
+
+    println!("Compressing {name:?} to {compressed_name:?}");
+
+    if let Some(version) = data.get_one::<String>("output-version") {
+        doc.version = version.clone();
+    }
+
+    strip_thumbnails(&mut doc, data);
+    strip_attachments(&mut doc, data);
+    remove_links(&mut doc, data);
+    add_output_intent(&mut doc, data)?;
+    set_default_title(&mut doc, data, &compressed_name);
+    remove_hidden_layers(&mut doc, data);
+    strip_icc(&mut doc, data);
+    target_ppi(&mut doc, data);
+    resolve_references(&mut doc, data);
+    doc.compress();
+    set_xref_type(&mut doc, data);
+    if let Some(max_size) = data.get_one::<u64>("max-size") {
+        fit_size(&mut doc, *max_size).with_context(|| format!("Failed to compress {name}"))?;
+    }
+    // Written next to the target first, so a failure never leaves half a file behind
+    let temporary = format!("{compressed_name}.tmp");
+    doc.save(&temporary)
+        .with_context(|| "Failed to save file")?;
+
+    if data.get_flag("preserve-dates") {
+        preserve_modified(name, &temporary)?;
+    }
+
+    let size = |file: &str| std::fs::metadata(file).map(|m| m.len()).unwrap_or(0);
+    let original_size = size(name);
+
+    if only_if_smaller && !is_url(name) && size(&temporary) >= original_size {
+        println!("Kept the original {name:?}, compressing did not make it smaller");
+
+        std::fs::remove_file(&temporary)
+            .with_context(|| format!("Failed to remove {temporary}"))?;
+        if !in_place {
+            std::fs::copy(name, &compressed_name)
+                .with_context(|| format!("Failed to write output file {compressed_name}"))?;
+            if data.get_flag("preserve-dates") {
+                preserve_modified(name, &compressed_name)?;
+            }
+        }
+    } else {
+        std::fs::rename(&temporary, &compressed_name)
+            .with_context(|| format!("Failed to write output file {compressed_name}"))?;
+    }
+
+    finish_output(data, &compressed_name)?;
+
+    let compressed_size = size(&compressed_name);
+    Ok((compressed_name, original_size, compressed_size))
+}
+
+/// The file the compressed `name` is written to.
+fn compressed_name(name: &str, data: &ArgMatches) -> String {
+    if data.get_flag("in-place") {
+        name.to_owned()
+    } else {
+        let suffix = data.get_one::<String>("suffix").unwrap();
+        format!("{}{suffix}.pdf", name_stem(name))
+    }
+}
+
+/// Copy the already `compressed` output of a file with the same content as `name` to the output
+/// of `name`, for `--dedup`. Returns the same as [`compress_pdf`].
+fn copy_compressed(name: &str, compressed: &str, data: &ArgMatches) -> Result<(String, u64, u64)> {
+    let compressed_name = compressed_name(name, data);
+    let size = |file: &str| std::fs::metadata(file).map(|m| m.len()).unwrap_or(0);
+    let original_size = size(name);
+
+    std::fs::copy(compressed, &compressed_name)
+        .with_context(|| format!("Failed to write output file {compressed_name}"))?;
+    if data.get_flag("preserve-dates") {
+        preserve_modified(name, &compressed_name)?;
+    }
+
+    finish_output(data, &compressed_name)?;
+
+    let compressed_size = size(&compressed_name);
+    Ok((compressed_name, original_size, compressed_size))
+}
+
+/// Print how many files of a `--keep-going` run were `done` and how many failed, by the kind of
+/// failure.
+fn print_failure_summary(succeeded: usize, done: &str, failures: &[(String, anyhow::Error)]) {
+    let kinds = failures
+        .iter()
+        .map(|(_, error)| failure_kind(error))
+        .counts_by(|kind| kind);
+    let kinds = kinds
+        .iter()
+        .sorted()
+        .map(|(kind, count)| format!("{count} {kind}"))
+        .join(", ");
+
+    println!(
+        "Summary: {succeeded} {done}, {} failed ({kinds})",
+        failures.len()
+    );
+}
+
+/// What kind of failure `error` is, for the summary of `--keep-going`.
+fn failure_kind(error: &anyhow::Error) -> &'static str {
+    match error.chain().find_map(|e| e.downcast_ref::<PdftError>()) {
+        Some(PdftError::FileNotFound { .. }) => "not found",
+        Some(PdftError::InvalidPdf { .. }) => "not a PDF",
+        Some(PdftError::Unreadable { .. }) => "unreadable",
+        Some(PdftError::Encrypted { .. }) => "encrypted",
+        Some(PdftError::NoPages { .. }) => "without pages",
+        _ => "other errors",
+    }
+}
+
+/// The smallest images `--max-size` scales to, as a fraction of their original size.
+const MIN_IMAGE_SCALE: f32 = 0.1;
+
+/// How many image scales `--max-size` tries between the smallest one and the original size.
+const IMAGE_SCALE_STEPS: usize = 6;
+
+/// Apply `--max-size`: scale the images of `doc` down as little as possible to make the saved
+/// file fit in `max_size` bytes. Fails when even the smallest scale doesn't fit.
+fn fit_size(doc: &mut Document, max_size: u64) -> Result<()> {
+    let size = |doc: &mut Document| -> Result<u64> {
+        let mut buffer = Vec::new();
+        doc.save_to(&mut buffer)
+            .with_context(|| "Failed to write the compressed file")?;
+        Ok(buffer.len() as u64)
+    };
+
+    if size(doc)? <= max_size {
+        return Ok(());
+    }
+
+    println!("Scaling images down to fit in {}...", format_size(max_size));
+
+    let scaled = |scale: f32| -> Result<(Document, usize, u64)> {
+        let mut scaled = doc.clone();
+        let images = images::downsample(&mut scaled, scale);
+        let size = size(&mut scaled)?;
+        Ok((scaled, images, size))
+    };
+
+    let (smallest, scaled_images, smallest_size) = scaled(MIN_IMAGE_SCALE)?;
+    if scaled_images == 0 {
+        return Err(anyhow::anyhow!(
+            "The file is larger than {} and has no images that can be scaled down",
+            format_size(max_size)
+        ));
+    }
+    if smallest_size > max_size {
+        return Err(anyhow::anyhow!(
+            "The file is larger than {}, the smallest it got is {} with the images scaled to {:.0}%",
+            format_size(max_size),
+            format_size(smallest_size),
+            MIN_IMAGE_SCALE * 100.0
+        ));
+    }
+
+    // Binary search for the largest scale that still fits, the smallest one always does
+    let mut best = (smallest, MIN_IMAGE_SCALE);
+    let (mut low, mut high) = (MIN_IMAGE_SCALE, 1.0);
+    for _ in 0..IMAGE_SCALE_STEPS {
+        let scale = (low + high) / 2.0;
+        let (candidate, _, candidate_size) = scaled(scale)?;
+
+        if candidate_size <= max_size {
+            best = (candidate, scale);
+            low = scale;
+        } else {
+            high = scale;
+        }
+    }
+
+    println!("Scaled the images to {:.0}% of their size", best.1 * 100.0);
+    *doc = best.0;
+
+    Ok(())
+}
+
+/// How `--open-fit` shows the first page when the PDF is opened.
+#[derive(Debug, Clone, Copy)]
+enum OpenView {
+    Width,
+    Page,
+    /// The zoom factor, 1 is 100%
+    Zoom(f32),
+}
+
+fn parse_open_view(view: &str) -> Result<OpenView> {
+    match view {
+        "width" => Ok(OpenView::Width),
+        "page" => Ok(OpenView::Page),
+        zoom => zoom
+            .trim_end_matches('%')
+            .parse::<f32>()
+            .ok()
+            .filter(|zoom| *zoom > 0.0)
+            .map(|zoom| OpenView::Zoom(zoom / 100.0))
+            .with_context(|| "Expected width, page or a zoom percentage like 150%"),
+    }
+}
+
+/// Parse a page size for `--fit-to`, a name like `a4` or `WIDTHxHEIGHT` in points.
+fn parse_page_size(size: &str) -> Result<(f32, f32)> {
+    let size = size.to_ascii_lowercase();
+    if let Some((_, size)) = pages::PAGE_SIZES.iter().find(|(name, _)| *name == size) {
+        return Ok(*size);
+    }
+
+    let (width, height) = size.split_once('x').with_context(|| {
+        format!("Unknown page size {size:?}, use a name like a4 or WIDTHxHEIGHT")
+    })?;
+    let dimension = |n: &str| {
+        n.trim()
+            .parse::<f32>()
+            .ok()
+            .filter(|n| *n > 0.0)
+            .with_context(|| format!("Invalid page size {size:?}"))
+    };
+
+    Ok((dimension(width)?, dimension(height)?))
+}
+
+/// Parse a size like `10MB`, `500k` or `1.5 GiB` into bytes. The units are multiples of 1024,
+/// same as the sizes that are printed.
+fn parse_size(size: &str) -> Result<u64> {
+    let size = size.trim();
+    let split = size
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(split);
+
+    let number = number
+        .parse::<f64>()
+        .with_context(|| format!("Invalid size {size:?}"))?;
+    let multiplier = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => 1 << 10,
+        "m" | "mb" | "mib" => 1 << 20,
+        "g" | "gb" | "gib" => 1 << 30,
+        unit => return Err(anyhow::anyhow!("Unknown size unit {unit:?}")),
+    };
+
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// Copy the modification time of `source` to `target`.
+fn preserve_modified(source: &str, target: &str) -> Result<()> {
+    let modified = std::fs::metadata(source)
+        .and_then(|metadata| metadata.modified())
+        .with_context(|| format!("Failed to read the modification time of {source}"))?;
+
+    std::fs::File::options()
+        .write(true)
+        .open(target)
+        .and_then(|file| file.set_modified(modified))
+        .with_context(|| format!("Failed to set the modification time of {target}"))
+}
+
+/// Print a table with the size of every file before and after compressing, and the total.
+fn print_compression_stats(stats: &[(String, u64, u64)]) {
+    let total = (
+        "Total".to_string(),
+        stats.iter().map(|(_, original, _)| original).sum(),
+        stats.iter().map(|(_, _, compressed)| compressed).sum(),
+    );
+
+    let rows = stats
+        .iter()
+        .chain(std::iter::once(&total))
+        .map(|(name, original, compressed)| {
+            let saved = if *original == 0 {
+                0.0
+            } else {
+                100.0 * (*original as f64 - *compressed as f64) / *original as f64
+            };
+
+            [
+                name.clone(),
+                format_size(*original),
+                format_size(*compressed),
+                format!("{saved:.1}%"),
+            ]
+        })
+        .collect::<Vec<_>>();
+
+    let header = ["File", "Original", "Compressed", "Saved"].map(String::from);
+    let widths: [usize; 4] = std::array::from_fn(|i| {
+        rows.iter()
+            .chain(std::iter::once(&header))
+            .map(|row| row[i].chars().count())
+            .max()
+            .unwrap_or(0)
+    });
+
+    println!();
+    for (i, row) in std::iter::once(&header).chain(rows.iter()).enumerate() {
+        // The total is separated from the files, same as the header
+        if i == 1 || i == rows.len() {
+            println!("{}", "-".repeat(widths.iter().sum::<usize>() + 6));
+        }
+        println!(
+            "{:<w0$}  {:>w1$}  {:>w2$}  {:>w3$}",
+            row[0],
+            row[1],
+            row[2],
+            row[3],
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2],
+            w3 = widths[3],
+        );
+    }
+    println!();
+}
+
+/// Format a number of bytes with a binary unit, like `1.5 MiB`.
+fn format_size(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.1} {}", units[unit])
+    }
+}
+
+fn extract_pdf(data: &ArgMatches) -> Result<()> {
+    let f = data
+        .get_one::<String>("PDF")
+        .with_context(|| "No PDF found to extract from")?;
+    let name = input_path(f, data);
+
+    let output = match data.get_one::<String>("output") {
+        Some(s) => output_path(s, data),
+        None => format!("{}_extracted.pdf", name_stem(&name)),
+    };
+
+    println!("Loading PDF into memory...");
+    progress(
+        data,
+        json!({"phase": "loading", "current": 1, "total": 1, "file": name}),
+    );
+
+    let mut doc = load_unencrypted(&name, data.get_flag("mmap"), data.get_flag("recover"))?;
+
+    let page_count = doc.get_pages().len() as u32;
+    if page_count == 0 {
+        return Err(PdftError::NoPages { name }.into());
+    }
+    let keep = match (
+        data.get_one::<String>("pages"),
+        data.get_one::<u32>("every"),
+    ) {
+        (Some(spec), _) => pages::parse_page_spec(spec, page_count)?
+            .into_iter()
+            .collect::<BTreeSet<_>>(),
+        (None, Some(every)) => {
+            let offset = data.get_one::<u32>("offset").copied().unwrap_or(1);
+            if offset > page_count {
+                return Err(anyhow::anyhow!(
+                    "--offset {offset} is past the last page, {name} has {page_count} pages"
+                ));
+            }
+            (offset..=page_count).step_by(*every as usize).collect()
+        }
+        (None, None) => pages_containing(&doc, data)?,
+    };
+
+    if keep.is_empty() {
+        return Err(anyhow::anyhow!(
+            "No pages contain {:?}",
+            data.get_one::<String>("contains").unwrap()
+        ));
+    }
+
+    let keep = &keep - &skipped_pages(&doc, data);
+    if keep.is_empty() {
+        return Err(anyhow::anyhow!("All selected pages are damaged"));
+    }
+    let keep = &keep - &empty_pages(&doc, data);
+    if keep.is_empty() {
+        return Err(anyhow::anyhow!("All selected pages are empty"));
+    }
+    let keep = &keep - &textless_pages(&doc, data);
+    if keep.is_empty() {
+        return Err(anyhow::anyhow!("None of the selected pages have text"));
+    }
+
+    println!(
+        "Extracting {} of {} pages into {}...",
+        keep.len(),
+        page_count,
+        output
+    );
+
+    pages::keep_pages(&mut doc, &keep);
+    clip_to_crop_box(&mut doc, data);
+    if let Some((width, height)) = data.get_one::<(f32, f32)>("fit-to") {
+        let fitted = pages::fit_pages(&mut doc, box_type(data), *width, *height);
+        println!("Scaled {fitted} pages to {width}x{height} points");
+    }
+    encode_streams(&mut doc, data);
+
+    println!("Writing output file...");
+    progress(data, json!({"phase": "writing"}));
+
+    doc.save(&output)
+        .with_context(|| format!("Failed to write output file {}", output))?;
+    finish_output(data, &output)?;
+
+    print_done(data, &[&output]);
+
+    Ok(())
+}
+
+/// The pages whose text matches `--contains`, as a plain substring or with `--regex` as a
+/// regular expression.
+fn pages_containing(doc: &Document, data: &ArgMatches) -> Result<BTreeSet<u32>> {
+    let pattern = data.get_one::<String>("contains").unwrap();
+    let pattern = if data.get_flag("regex") {
+        Regex::new(pattern).with_context(|| format!("Invalid regular expression {pattern:?}"))?
+    } else {
+        Regex::new(&regex::escape(pattern)).unwrap()
+    };
+
+    println!("Searching the text of the pages...");
+
+    Ok(doc
+        .get_pages()
+        .into_keys()
+        .filter(|page| {
+            // Pages without extractable text (like scans) simply don't match
+            doc.extract_text(&[*page])
+                .is_ok_and(|text| pattern.is_match(&text))
+        })
+        .collect())
+}
+
+/// The `--group-by` group of the input `name`: the first capture group of `regex` in its file
+/// name, or the whole match when there is no group.
+fn file_group(regex: &Regex, name: &str) -> Option<String> {
+    let stem = Path::new(name_stem(name)).file_name()?.to_string_lossy();
+    let captures = regex.captures(&stem)?;
+    let matched = captures.get(1).or_else(|| captures.get(0))?;
+
+    Some(matched.as_str().to_owned())
+}
+
+/// A part written by `split`, with the pages it contains numbered as in the original PDF.
+#[derive(Serialize)]
+struct SplitPart {
+    file: String,
+    first_page: u32,
+    last_page: u32,
+}
+
+fn split_pdf(data: &ArgMatches) -> Result<()> {
+    let f = data
+        .get_one::<String>("PDF")
+        .with_context(|| "No PDF found to split")?;
+    let name = input_path(f, data);
+
+    let out = output_dir(data)?;
+
+    println!("Loading PDF into memory...");
+    progress(
+        data,
+        json!({"phase": "loading", "current": 1, "total": 1, "file": name}),
+    );
+
+    let mut doc = load_unencrypted(&name, data.get_flag("mmap"), data.get_flag("recover"))?;
+
+    let page_count = doc.get_pages().len() as u32;
+    if page_count == 0 {
+        return Err(PdftError::NoPages { name }.into());
+    }
+    let pages_per_file = *data.get_one::<u32>("pages-per-file").unwrap();
+    let stem = Path::new(name_stem(&name))
+        .file_name()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "part".into());
+
+    let mut parts = Vec::new();
+    let skipped =
+        &(&skipped_pages(&doc, data) | &empty_pages(&doc, data)) | &textless_pages(&doc, data);
+
+    clip_to_crop_box(&mut doc, data);
+
+    let ranges = if let Some(max_size) = data.get_one::<u64>("max-size") {
+        println!("Splitting {page_count} pages into parts of at most {max_size} bytes...");
+
+        ranges_by_size(&doc, page_count, &skipped, *max_size, data)?
+    } else if let Some(spec) = data.get_one::<String>("ranges") {
+        let ranges = pages::parse_page_ranges(spec, page_count)?;
+        println!(
+            "Splitting {page_count} pages into {} parts...",
+            ranges.len()
+        );
+
+        ranges
+    } else {
+        println!("Splitting {page_count} pages into parts of {pages_per_file} pages...");
+
+        (1..=page_count)
+            .step_by(pages_per_file as usize)
+            .map(|first_page| {
+                (
+                    first_page,
+                    (first_page + pages_per_file - 1).min(page_count),
+                )
+            })
+            .collect()
+    };
+
+    let labels = data
+        .get_flag("name-by-label")
+        .then(|| pages::page_labels(&doc));
+    if labels.as_ref().is_some_and(BTreeMap::is_empty) {
+        println!("{name} has no page labels, naming the parts by page number");
+    }
+    let mut files = BTreeSet::new();
+
+    for (i, (first_page, last_page)) in ranges.into_iter().enumerate() {
+        let part = match &labels {
+            Some(labels) => label_part_name(labels, first_page, last_page),
+            None => (i + 1).to_string(),
+        };
+        // Page labels don't have to be unique
+        let mut file_name = format!("{stem}_{part}.pdf");
+        for n in 2.. {
+            if files.insert(file_name.clone()) {
+                break;
+            }
+            file_name = format!("{stem}_{part}_{n}.pdf");
+        }
+        let file = out.join(file_name).to_string_lossy().into_owned();
+
+        let Some(mut part) = split_part(&doc, first_page, last_page, &skipped, data) else {
+            println!("{file}: skipped, pages {first_page}-{last_page} are all left out");
+            continue;
+        };
+
+        part.save(&file)
+            .with_context(|| format!("Failed to write output file {}", file))?;
+        finish_output(data, &file)?;
+
+        println!("{file}: pages {first_page}-{last_page}");
+
+        parts.push(SplitPart {
+            file,
+            first_page,
+            last_page,
+        });
+    }
+
+    if let Some(manifest) = data.get_one::<String>("manifest") {
+        println!("Writing manifest {manifest}...");
+
+        std::fs::write(manifest, serde_json::to_string_pretty(&parts)?)
+            .with_context(|| format!("Failed to write manifest {}", manifest))?;
+    }
+
+    print_done(
+        data,
+        &parts.iter().map(|part| &part.file).collect::<Vec<_>>(),
+    );
+
+    Ok(())
+}
+
+/// The name of a part with the pages `first_page` to `last_page` for `--name-by-label`, the label
+/// of its first page or the labels of its first and last page. Characters that can't be in file
+/// names are replaced.
+fn label_part_name(labels: &BTreeMap<u32, String>, first_page: u32, last_page: u32) -> String {
+    let label = |page: u32| match labels.get(&page).filter(|label| !label.trim().is_empty()) {
+        Some(label) => label
+            .trim()
+            .chars()
+            .map(|c| match c {
+                '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+                c if c.is_control() => '_',
+                c => c,
+            })
+            .collect(),
+        None => page.to_string(),
+    };
+
+    if first_page == last_page {
+        label(first_page)
+    } else {
+        format!("{}-{}", label(first_page), label(last_page))
+    }
+}
+
+/// A copy of `doc`, ready to be saved, with the pages `first_page` to `last_page` that aren't `skipped`,
+/// or nothing when all of them are.
+fn split_part(
+    doc: &Document,
+    first_page: u32,
+    last_page: u32,
+    skipped: &BTreeSet<u32>,
+    data: &ArgMatches,
+) -> Option<Document> {
+    let keep = (first_page..=last_page)
+        .filter(|page| !skipped.contains(page))
+        .collect::<BTreeSet<_>>();
+    if keep.is_empty() {
+        return None;
+    }
+
+    let mut part = doc.clone();
+    pages::keep_pages(&mut part, &keep);
+    encode_streams(&mut part, data);
+
+    Some(part)
+}
+
+/// Split the pages of `doc` into ranges that each fit in `max_size` bytes when saved. A page that
+/// doesn't fit on its own gets a part of its own.
+fn ranges_by_size(
+    doc: &Document,
+    page_count: u32,
+    skipped: &BTreeSet<u32>,
+    max_size: u64,
+    data: &ArgMatches,
+) -> Result<Vec<(u32, u32)>> {
+    let size = |first_page, last_page| -> Result<u64> {
+        let Some(mut part) = split_part(doc, first_page, last_page, skipped, data) else {
+            return Ok(0);
+        };
+
+        let mut buffer = Vec::new();
+        part.save_to(&mut buffer)
+            .with_context(|| "Failed to measure the size of a part")?;
+        Ok(buffer.len() as u64)
+    };
+
+    let mut ranges = Vec::new();
+    let mut first_page = 1;
+
+    while first_page <= page_count {
+        // Adding pages only makes a part larger, so search for the last page that still fits
+        let (mut low, mut high) = (first_page, page_count);
+        while low < high {
+            let middle = (low + high).div_ceil(2);
+            if size(first_page, middle)? <= max_size {
+                low = middle;
+            } else {
+                high = middle - 1;
+            }
+        }
+
+        if low == first_page && size(first_page, first_page)? > max_size {
+            println!("Page {first_page} alone is larger than {max_size} bytes");
+        }
+
+        ranges.push((first_page, low));
+        first_page = low + 1;
+    }
+
+    Ok(ranges)
+}
+
+/// Arguments to pass a password on the command line or stdin, without either of them the password
+/// is prompted for.
+fn password_args() -> [Arg; 2] {
+    [
+        Arg::new("password")
+            .long("password")
+            .conflicts_with("password-stdin")
+            .help("Password in plain text, this ends up in your shell history, meant for scripts"),
+        Arg::new("password-stdin")
+            .long("password-stdin")
+            .action(ArgAction::SetTrue)
+            .help("Read the password from the first line of stdin"),
+    ]
+}
+
+/// Get the password from `password_args`, prompting for it without echo when neither was given.
+/// When `confirm` is set a prompted password has to be entered twice.
+fn read_password(data: &ArgMatches, confirm: bool) -> Result<String> {
+    if let Some(password) = data.get_one::<String>("password") {
+        return Ok(password.clone());
+    }
+
+    if data.get_flag("password-stdin") {
+        let mut line = String::new();
+        io::stdin()
+            .read_line(&mut line)
+            .with_context(|| "Failed to read password from stdin")?;
+
+        return Ok(line.trim_end_matches(['\r', '\n']).to_string());
+    }
+
+    let password =
+        rpassword::prompt_password("Password: ").with_context(|| "Failed to read password")?;
+
+    if confirm
+        && rpassword::prompt_password("Repeat password: ")
+            .with_context(|| "Failed to read password")?
+            != password
+    {
+        return Err(anyhow::anyhow!("Passwords do not match"));
+    }
+
+    Ok(password)
+}
+
+fn split_by_bookmarks_pdf(data: &ArgMatches) -> Result<()> {
+    let f = data
+        .get_one::<String>("PDF")
+        .with_context(|| "No PDF found to split")?;
+    let name = input_path(f, data);
+
+    let out = output_dir(data)?;
+
+    println!("Loading PDF into memory...");
+    progress(
+        data,
+        json!({"phase": "loading", "current": 1, "total": 1, "file": name}),
+    );
+
+    let doc = load_unencrypted(&name, data.get_flag("mmap"), data.get_flag("recover"))?;
+
+    let page_numbers = doc
+        .get_pages()
+        .into_iter()
+        .map(|(page, id)| (id, page))
+        .collect::<BTreeMap<_, _>>();
+    let page_count = page_numbers.len() as u32;
+    if page_count == 0 {
+        return Err(PdftError::NoPages { name }.into());
+    }
+
+    let level = *data.get_one::<u32>("level").unwrap() as usize;
+    let outline = outline::read_outline(&doc);
+
+    // Where every part starts, bookmarks to the same page as an earlier one don't start a part
+    let mut starts = outline::entries_to_level(&outline, level)
+        .into_iter()
+        .map(|item| (page_numbers[&item.page], item.title.clone()))
+        .collect::<Vec<_>>();
+    starts.sort_by_key(|(page, _)| *page);
+    starts.dedup_by_key(|(page, _)| *page);
+
+    if starts.is_empty() {
+        return Err(anyhow::anyhow!("{name} has no bookmarks to split at"));
+    }
+
+    // The pages before the first bookmark, like a cover, are a part of their own
+    let stem = Path::new(name_stem(&name))
+        .file_name()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "part".into());
+    if starts[0].0 > 1 {
+        starts.insert(0, (1, stem));
+    }
+
+    println!(
+        "Splitting {page_count} pages at {} bookmarks...",
+        starts.len()
+    );
+
+    let mut parts = Vec::new();
+
+    for (i, (first_page, title)) in starts.iter().enumerate() {
+        let last_page = starts.get(i + 1).map_or(page_count, |(next, _)| next - 1);
+        let file = out
+            .join(format!("{:02}_{}.pdf", i + 1, file_name_safe(title)))
+            .to_string_lossy()
+            .into_owned();
+
+        let mut part = doc.clone();
+        pages::keep_pages(&mut part, &(*first_page..=last_page).collect());
+        encode_streams(&mut part, data);
+        part.save(&file)
+            .with_context(|| format!("Failed to write output file {}", file))?;
+        finish_output(data, &file)?;
+
+        println!("{file}: pages {first_page}-{last_page}");
+
+        parts.push(SplitPart {
+            file,
+            first_page: *first_page,
+            last_page,
+        });
+    }
+
+    if let Some(manifest) = data.get_one::<String>("manifest") {
+        println!("Writing manifest {manifest}...");
+
+        std::fs::write(manifest, serde_json::to_string_pretty(&parts)?)
+            .with_context(|| format!("Failed to write manifest {}", manifest))?;
+    }
+
+    print_done(
+        data,
+        &parts.iter().map(|part| &part.file).collect::<Vec<_>>(),
+    );
+
+    Ok(())
+}
+
+/// Turn a bookmark title into something that can be used in a file name on every platform.
+fn file_name_safe(title: &str) -> String {
+    let name = title
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || matches!(c, ' ' | '-' | '_' | '.') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    let name = name.trim().trim_matches('.');
+
+    if name.is_empty() {
+        "part".into()
+    } else {
+        name.chars().take(100).collect()
+    }
+}
+
+fn encrypt_pdf(data: &ArgMatches) -> Result<()> {
+    let f = data
+        .get_one::<String>("PDF")
+        .with_context(|| "No PDF found to encrypt")?;
+    let name = input_path(f, data);
 
-        doc.compress();
-        doc.save(compressed_name)
-            .with_context(|| "Failed to save file")?;
+    let output = match data.get_one::<String>("output") {
+        Some(s) => output_path(s, data),
+        None => format!("{}_encrypted.pdf", name_stem(&name)),
+    };
+
+    println!("Loading PDF into memory...");
+    progress(
+        data,
+        json!({"phase": "loading", "current": 1, "total": 1, "file": name}),
+    );
+
+    let mut doc = load_unencrypted(&name, false, data.get_flag("recover"))?;
+
+    let password = read_password(data, true)?;
+    let owner_password = data
+        .get_one::<String>("owner-password")
+        .cloned()
+        .unwrap_or_else(|| password.clone());
+
+    println!("Encrypting {name:?} to {output:?}");
+
+    doc.compress();
+    encryption::encrypt(
+        &mut doc,
+        &password,
+        &owner_password,
+        data.get_flag("deterministic-id"),
+    );
+
+    doc.save(&output)
+        .with_context(|| format!("Failed to write output file {}", output))?;
+    finish_output(data, &output)?;
+
+    print_done(data, &[&output]);
+
+    Ok(())
+}
+
+fn decrypt_pdf(data: &ArgMatches) -> Result<()> {
+    let f = data
+        .get_one::<String>("PDF")
+        .with_context(|| "No PDF found to decrypt")?;
+    let name = input_path(f, data);
+
+    let output = match data.get_one::<String>("output") {
+        Some(s) => output_path(s, data),
+        None => format!("{}_decrypted.pdf", name_stem(&name)),
+    };
+
+    println!("Loading PDF into memory...");
+    progress(
+        data,
+        json!({"phase": "loading", "current": 1, "total": 1, "file": name}),
+    );
+
+    let mut doc = load_pdf(&name, false, data.get_flag("recover"))?;
+
+    let encrypt_id = doc
+        .trailer
+        .get(b"Encrypt")
+        .and_then(Object::as_reference)
+        .map_err(|_| anyhow::anyhow!("{} is not encrypted", name))?;
+
+    let password = read_password(data, false)?;
+
+    println!("Decrypting {name:?} to {output:?}");
+
+    doc.decrypt(&password)
+        .with_context(|| "Failed to decrypt, is the password correct?")?;
+    doc.objects.remove(&encrypt_id);
+    doc.compress();
+
+    doc.save(&output)
+        .with_context(|| format!("Failed to write output file {}", output))?;
+    finish_output(data, &output)?;
+
+    print_done(data, &[&output]);
+
+    Ok(())
+}
+
+fn validate_pdfs(data: &ArgMatches) -> Result<()> {
+    let pdfs = data
+        .get_many::<String>("PDFs")
+        .with_context(|| "No PDFs found to validate")?;
+
+    let mut failed = 0;
+
+    for f in pdfs {
+        let name = input_path(f, data);
+
+        let doc = load_pdf(&name, false, data.get_flag("recover"))?;
+        let checks = validate::check(&doc);
+
+        println!("{name}");
+        for check in &checks {
+            println!(
+                "  {}  {}",
+                if check.passed() { "PASS" } else { "FAIL" },
+                check.rule
+            );
+            for problem in &check.problems {
+                println!("          {problem}");
+            }
+        }
+
+        if checks.iter().any(|check| !check.passed()) {
+            failed += 1;
+        }
+    }
+
+    if failed > 0 {
+        return Err(anyhow::anyhow!("{failed} PDFs failed validation"));
+    }
+
+    print_done::<&str>(data, &[]);
+
+    Ok(())
+}
+
+fn rotate_pdf(data: &ArgMatches) -> Result<()> {
+    let f = data
+        .get_one::<String>("PDF")
+        .with_context(|| "No PDF found to rotate")?;
+    let name = input_path(f, data);
+
+    let output = match data.get_one::<String>("output") {
+        Some(s) => output_path(s, data),
+        None => format!("{}_rotated.pdf", name_stem(&name)),
+    };
+
+    println!("Loading PDF into memory...");
+    progress(
+        data,
+        json!({"phase": "loading", "current": 1, "total": 1, "file": name}),
+    );
+
+    let mut doc = load_unencrypted(&name, false, data.get_flag("recover"))?;
+
+    println!("Rotating {name:?} to {output:?}");
+
+    let mut rotated = 0;
+
+    if data.get_flag("auto-orient") {
+        let landscape = data.get_one::<String>("target").unwrap() == "landscape";
+
+        for (_, page_id) in doc.get_pages() {
+            if pages::is_landscape(&doc, page_id).is_some_and(|l| l != landscape) {
+                pages::rotate_page(&mut doc, page_id, 90);
+                rotated += 1;
+            }
+        }
+    } else if data.get_flag("auto-rotate-text") {
+        let mut without_text = Vec::new();
+
+        for (page, page_id) in doc.get_pages() {
+            let Some(direction) = pages::text_direction(&doc, page_id) else {
+                without_text.push(page);
+                continue;
+            };
+
+            // Rotate turns clockwise, as much as the text runs counterclockwise makes it level
+            let turn = (direction - pages::rotation(&doc, page_id)).rem_euclid(360);
+            if turn != 0 {
+                pages::rotate_page(&mut doc, page_id, turn);
+                rotated += 1;
+            }
+        }
+
+        if !without_text.is_empty() {
+            println!(
+                "Skipped {} pages without text: {}",
+                without_text.len(),
+                without_text.iter().join(", ")
+            );
+        }
+    } else if let Some(spec) = data.get_one::<String>("spec") {
+        let pages = doc.get_pages();
+
+        let rotations = pages::parse_rotation_spec(spec, pages.len() as u32)?;
+
+        for (page, angle) in &rotations {
+            pages::rotate_page(&mut doc, pages[page], *angle);
+        }
+        rotated = rotations
+            .iter()
+            .map(|(page, _)| page)
+            .collect::<BTreeSet<_>>()
+            .len();
+    } else {
+        let angle = *data.get_one::<i64>("angle").unwrap();
+
+        for (_, page_id) in doc.get_pages() {
+            pages::rotate_page(&mut doc, page_id, angle);
+            rotated += 1;
+        }
+    }
+
+    println!("Rotated {rotated} pages");
+
+    doc.compress();
+    doc.save(&output)
+        .with_context(|| format!("Failed to write output file {}", output))?;
+    finish_output(data, &output)?;
+
+    print_done(data, &[&output]);
+
+    Ok(())
+}
+
+fn scale_pdf(data: &ArgMatches) -> Result<()> {
+    let f = data
+        .get_one::<String>("PDF")
+        .with_context(|| "No PDF found to scale")?;
+    let name = input_path(f, data);
+
+    let output = match data.get_one::<String>("output") {
+        Some(s) => output_path(s, data),
+        None => format!("{}_scaled.pdf", name_stem(&name)),
+    };
+
+    println!("Loading PDF into memory...");
+    progress(
+        data,
+        json!({"phase": "loading", "current": 1, "total": 1, "file": name}),
+    );
+
+    let mut doc = load_unencrypted(&name, false, data.get_flag("recover"))?;
+
+    let page_count = doc.get_pages().len() as u32;
+    if page_count == 0 {
+        return Err(PdftError::NoPages { name }.into());
+    }
+    let selected = match data.get_one::<String>("pages") {
+        Some(spec) => pages::parse_page_spec(spec, page_count)?
+            .into_iter()
+            .collect::<BTreeSet<_>>(),
+        None => (1..=page_count).collect(),
+    };
+    let factor = *data.get_one::<f32>("factor").unwrap();
+
+    println!("Scaling {name:?} to {output:?}");
+
+    let scaled = pages::scale_pages(&mut doc, &selected, box_type(data), factor);
+
+    println!("Scaled {scaled} pages by {factor}");
+
+    doc.compress();
+    doc.save(&output)
+        .with_context(|| format!("Failed to write output file {}", output))?;
+    finish_output(data, &output)?;
+
+    print_done(data, &[&output]);
+
+    Ok(())
+}
+
+fn crop_pdf(data: &ArgMatches) -> Result<()> {
+    let f = data
+        .get_one::<String>("PDF")
+        .with_context(|| "No PDF found to crop")?;
+    let name = input_path(f, data);
+
+    let output = match data.get_one::<String>("output") {
+        Some(s) => output_path(s, data),
+        None => format!("{}_cropped.pdf", name_stem(&name)),
+    };
+
+    println!("Loading PDF into memory...");
+    progress(
+        data,
+        json!({"phase": "loading", "current": 1, "total": 1, "file": name}),
+    );
+
+    let mut doc = load_unencrypted(&name, false, data.get_flag("recover"))?;
+
+    let page_count = doc.get_pages().len() as u32;
+    if page_count == 0 {
+        return Err(PdftError::NoPages { name }.into());
+    }
+    let selected = match data.get_one::<String>("pages") {
+        Some(spec) => pages::parse_page_spec(spec, page_count)?
+            .into_iter()
+            .collect::<BTreeSet<_>>(),
+        None => (1..=page_count).collect(),
+    };
+    let margin = *data.get_one::<f32>("margin").unwrap();
+    let key = box_type(data);
+
+    println!("Cropping {name:?} to {output:?}");
+
+    let mut cropped = 0;
+    let mut without_content = 0;
+    for (page, page_id) in doc.get_pages() {
+        if !selected.contains(&page) {
+            continue;
+        }
+        let rect = match data.get_one::<[f32; 4]>("box") {
+            Some(rect) => *rect,
+            None => match pages::content_bounds(&doc, page_id) {
+                Some([x1, y1, x2, y2]) => [x1 - margin, y1 - margin, x2 + margin, y2 + margin],
+                None => {
+                    without_content += 1;
+                    continue;
+                }
+            },
+        };
+
+        if pages::set_page_box(&mut doc, page_id, key, rect) {
+            cropped += 1;
+        }
+    }
+
+    println!(
+        "Cropped {cropped} pages{}",
+        match without_content {
+            0 => String::new(),
+            n => format!(", left {n} pages without content as they are"),
+        }
+    );
+
+    // Cropping can leave pages with nothing in view, those go after the crop
+    let empty = empty_pages(&doc, data);
+    if !empty.is_empty() {
+        let keep = &(1..=page_count).collect::<BTreeSet<_>>() - &empty;
+        if keep.is_empty() {
+            return Err(anyhow::anyhow!("All pages are empty after cropping"));
+        }
+        pages::keep_pages(&mut doc, &keep);
+    }
+
+    doc.compress();
+    doc.save(&output)
+        .with_context(|| format!("Failed to write output file {}", output))?;
+    finish_output(data, &output)?;
+
+    print_done(data, &[&output]);
+
+    Ok(())
+}
+
+fn grayscale_pdf(data: &ArgMatches) -> Result<()> {
+    let f = data
+        .get_one::<String>("PDF")
+        .with_context(|| "No PDF found to convert")?;
+    let name = input_path(f, data);
+
+    let output = match data.get_one::<String>("output") {
+        Some(s) => output_path(s, data),
+        None => format!("{}_grayscale.pdf", name_stem(&name)),
+    };
+
+    println!("Loading PDF into memory...");
+    progress(
+        data,
+        json!({"phase": "loading", "current": 1, "total": 1, "file": name}),
+    );
+
+    let mut doc = load_unencrypted(&name, false, data.get_flag("recover"))?;
+
+    println!("Converting {name:?} to {output:?}");
+
+    let summary = grayscale::convert(&mut doc);
+
+    println!(
+        "Converted {} content streams and {} images",
+        summary.content_streams, summary.images
+    );
+    if summary.skipped_images > 0 {
+        println!(
+            "Kept the colors of {} images that can't be decoded (like JPEG)",
+            summary.skipped_images
+        );
+    }
+
+    doc.compress();
+    doc.save(&output)
+        .with_context(|| format!("Failed to write output file {}", output))?;
+    finish_output(data, &output)?;
+
+    print_done(data, &[&output]);
+
+    Ok(())
+}
+
+fn info_pdfs(data: &ArgMatches) -> Result<()> {
+    let pdfs = data
+        .get_many::<String>("PDFs")
+        .with_context(|| "No PDFs found to inspect")?;
+
+    for f in pdfs {
+        let name = input_path(f, data);
+
+        let doc = load_pdf(&name, false, data.get_flag("recover"))?;
+
+        println!("{name}");
+        println!("  Version:   {}", doc.version);
+        println!("  Pages:     {}", doc.get_pages().len());
+        println!(
+            "  Title:     {}",
+            document_title(&doc).unwrap_or_else(|| "-".into())
+        );
+        println!(
+            "  Encrypted: {}",
+            if doc.is_encrypted() { "yes" } else { "no" }
+        );
+
+        if data.get_flag("hash") {
+            println!("  Hash:      {}", pages::content_hash(&doc));
+        }
+
+        if data.get_flag("images") {
+            let placements = images::image_placements(&doc);
+
+            println!("  Images:    {}", placements.len());
+
+            for image in placements {
+                let (dpi_x, dpi_y) = image.dpi();
+
+                println!(
+                    "    page {:<4} {:<8} obj {:<6} {:>11} px {:>15} pt {:>11} dpi",
+                    image.page,
+                    image.name,
+                    image.id.0,
+                    format!("{}x{}", image.width, image.height),
+                    format!("{:.1}x{:.1}", image.placed_width, image.placed_height),
+                    format!("{dpi_x:.0}x{dpi_y:.0}"),
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn dump_pdf(data: &ArgMatches) -> Result<()> {
+    let f = data
+        .get_one::<String>("PDF")
+        .with_context(|| "No PDF found to dump")?;
+    let name = input_path(f, data);
+    let page = *data.get_one::<u32>("page").unwrap();
+
+    let doc = load_pdf(&name, false, data.get_flag("recover"))?;
+    let pages = doc.get_pages();
+    let page_id = *pages.get(&page).with_context(|| {
+        format!(
+            "Page {page} is out of range, the document has {} pages",
+            pages.len()
+        )
+    })?;
+
+    let content = doc
+        .get_page_content(page_id)
+        .with_context(|| format!("Failed to read the content of page {page}"))?;
+
+    if data.get_flag("raw") {
+        io::stdout().write_all(&content)?;
+        return Ok(());
+    }
+
+    let content = Content::decode(&content)
+        .with_context(|| format!("Failed to parse the content of page {page}"))?;
+
+    // Indent what is inside saved graphics states, text objects and marked content
+    let mut depth = 0usize;
+    for operation in content.operations {
+        if matches!(operation.operator.as_str(), "Q" | "ET" | "EMC") {
+            depth = depth.saturating_sub(1);
+        }
+        let opens = matches!(operation.operator.as_str(), "q" | "BT" | "BMC" | "BDC");
+
+        let encoded = Content {
+            operations: vec![operation],
+        }
+        .encode()
+        .unwrap_or_default();
+        println!(
+            "{}{}",
+            "  ".repeat(depth),
+            String::from_utf8_lossy(&encoded).trim_end()
+        );
+
+        if opens {
+            depth += 1;
+        }
+    }
+
+    Ok(())
+}
+
+fn list_annotations(data: &ArgMatches) -> Result<()> {
+    let f = data
+        .get_one::<String>("PDF")
+        .with_context(|| "No PDF found to list the annotations of")?;
+    let name = input_path(f, data);
+
+    let doc = load_pdf(&name, false, data.get_flag("recover"))?;
+    let (comments, other) = annotations::comments(&doc);
+
+    if data.get_flag("json") {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&json!({
+                "file": name,
+                "comments": comments,
+                "other": other,
+            }))?
+        );
+
+        return Ok(());
+    }
+
+    println!("{name}");
+    println!("  Comments: {}", comments.len());
+
+    for comment in &comments {
+        println!(
+            "    page {:<4} {:<10} {:<16} {:<25} {}",
+            comment.page,
+            comment.subtype,
+            comment.author.as_deref().unwrap_or("-"),
+            comment.date.as_deref().unwrap_or("-"),
+            comment
+                .contents
+                .as_deref()
+                .map(|contents| format!("{:?}", contents))
+                .unwrap_or_else(|| "-".into()),
+        );
     }
 
-    println!("🦀 All done! 🦀");
+    if !other.is_empty() {
+        println!(
+            "  Other:    {}",
+            other
+                .iter()
+                .map(|(subtype, count)| format!("{count} {subtype}"))
+                .join(", ")
+        );
+    }
 
     Ok(())
 }
 
+/// A page of a merged PDF, with the file and page number it came from. Pages that were added by
+/// the merge itself, like the table of contents, have no source.
+#[derive(Serialize)]
+struct MergedPage {
+    page: u32,
+    source: Option<String>,
+    source_page: Option<u32>,
+}
+
+/// Fail unless all `documents` have the same PDF version, for `--strict-version`.
+fn check_versions(documents: &[(String, Document)]) -> Result<()> {
+    let versions = documents
+        .iter()
+        .map(|(_, doc)| doc.version.as_str())
+        .collect::<BTreeSet<_>>();
+    if versions.len() <= 1 {
+        return Ok(());
+    }
+
+    let files = documents
+        .iter()
+        .map(|(name, doc)| format!("  {name}: PDF {}", doc.version))
+        .join("\n");
+    Err(anyhow::anyhow!(
+        "The PDFs have different versions ({}), which --strict-version doesn't allow:\n{files}",
+        versions.iter().join(", ")
+    ))
+}
+
+/// Warn about the tagged `documents`, their structure trees are lost without `--preserve-tags`.
+fn warn_untagged(documents: &[(String, Document)]) {
+    let tagged = documents
+        .iter()
+        .filter(|(_, doc)| {
+            doc.catalog()
+                .is_ok_and(|catalog| catalog.has(b"StructTreeRoot"))
+        })
+        .map(|(name, _)| name.as_str())
+        .collect::<Vec<_>>();
+    if tagged.is_empty() {
+        return;
+    }
+
+    eprintln!(
+        "Warning: {} tagged, but structure trees are only kept with --preserve-tags, so the \
+         output won't be accessible to screen readers (--allow-untagged hides this warning)",
+        match &tagged[..] {
+            [name] => format!("{name} is"),
+            names => format!("{} are", names.join(", ")),
+        }
+    );
+}
+
 fn merge_pdfs(data: &ArgMatches) -> Result<()> {
+    let append_to = data
+        .get_one::<String>("append-to")
+        .map(|f| output_path(f, data));
+    let job = data
+        .get_one::<String>("job")
+        .map(|path| job::read_job(path))
+        .transpose()?;
+    let default_output = data.value_source("output") == Some(ValueSource::DefaultValue);
+
     let output = match data.get_one::<String>("output") {
-        Some(s) => {
-            if s.ends_with(".pdf") {
-                s.clone()
-            } else {
-                let mut s = s.clone();
-                s.push_str(".pdf");
-                s
+        // Appending writes back to the base PDF, unless an output was given
+        Some(_) if append_to.is_some() && default_output => append_to.clone().unwrap(),
+        Some(_) if default_output && job.as_ref().is_some_and(|job| job.output.is_some()) => {
+            output_path(job.as_ref().unwrap().output.as_ref().unwrap(), data)
+        }
+        Some(s) => output_path(s, data),
+        None => "output.pdf".into(),
+    };
+
+    let inputs = match &job {
+        Some(job) => job.inputs.iter().map(|input| input.file.clone()).collect(),
+        None => data
+            .get_many::<String>("PDFs")
+            .with_context(|| "No PDFs found to merge")?
+            .cloned()
+            .collect::<Vec<_>>(),
+    };
+    let mut pdfs = inputs.iter().peekable();
+
+    println!("Checking input validity...");
+
+    if pdfs.peek().is_none() {
+        Err(anyhow::anyhow!("No pdfs found"))?;
+    }
+
+    if data.get_flag("portfolio") {
+        return merge_into_portfolio(pdfs, data, &output);
+    }
+
+    println!("Loading PDFs into memory...");
+
+    let mut documents = load_documents(pdfs, data)?;
+
+    if let Some(job) = &job {
+        for ((name, doc), input) in documents.iter_mut().zip(&job.inputs) {
+            apply_job_input(doc, name, input)?;
+        }
+    }
+
+    // The PDF that is appended to is merged first, and keeps more of itself than the others
+    if let Some(base) = &append_to {
+        documents.insert(
+            0,
+            (
+                base.clone(),
+                load_unencrypted(base, false, data.get_flag("recover"))?,
+            ),
+        );
+    }
+    let base_index = append_to.as_ref().map(|_| 0);
+
+    if data.get_flag("strict-version") {
+        check_versions(&documents)?;
+    }
+
+    if !data.get_flag("preserve-tags") && !data.get_flag("allow-untagged") {
+        warn_untagged(&documents);
+    }
+
+    println!("Merging {} PDFs into {}...", documents.len(), output);
+    progress(data, json!({"phase": "merging"}));
+
+    let toc_page = data.get_flag("toc-page");
+    let bookmark_color = *data.get_one::<[f32; 3]>("bookmark-color").unwrap();
+    // Outline item flags, bit 1 is italic and bit 2 is bold
+    let bookmark_format =
+        data.get_flag("bookmark-italic") as u32 | (data.get_flag("bookmark-bold") as u32) << 1;
+    let interactive = data.get_flag("interactive");
+    let pad_to_even = data.get_flag("pad-to-even");
+    let separator = data.get_one::<String>("separator-page").map(String::as_str);
+
+    let versions = documents
+        .iter()
+        .map(|(_, doc)| doc.version.clone())
+        .collect::<BTreeSet<_>>();
+
+    let mut max_id = 1;
+    // The table of contents page, when requested, is page 1 of the merged document
+    let mut pagenum = if toc_page { 2 } else { 1 };
+    let mut toc_entries = Vec::new();
+
+    let mut documents_pages = BTreeMap::new();
+    let mut documents_objects = BTreeMap::new();
+
+    let mut document = Document::with_version("1.5");
+    if let Some(index) = base_index {
+        document.version = documents[index].1.version.clone();
+    }
+    // The PDFs were checked to have just one version, keep it instead of the default
+    if data.get_flag("strict-version") {
+        document.version = documents[0].1.version.clone();
+    }
+    // The document information dictionary and file identifier of the PDF that is appended to
+    let mut base_info = None;
+    // The document information dictionary of every input, for --metadata
+    let mut infos = Vec::new();
+    // The objects of the information dictionaries that aren't kept as they are, the merged Info
+    // is written as a new dictionary
+    let mut dropped_infos = BTreeSet::new();
+    let mut base_id = None;
+
+    if interactive && versions.len() > 1 {
+        let options = std::iter::once(document.version.clone())
+            .chain(versions.into_iter().filter(|v| *v != document.version))
+            .collect::<Vec<_>>();
+
+        let choice = prompt_choice(
+            "The PDFs have different versions, which version should the output use?",
+            &options,
+            0,
+        )?;
+        document.version = options[choice].clone();
+    }
+
+    // Catalog of every input, used to resolve conflicts between them in interactive mode
+    let mut catalogs = Vec::new();
+
+    // Counted in the inputs that were given, not including the PDF that is appended to
+    let keep_bookmarks_from = data
+        .get_one::<u32>("keep-bookmarks-from")
+        .map(|n| *n as usize + base_index.iter().count());
+    let mut named_dests = Vec::new();
+    let merge_javascript = data.get_flag("merge-javascript");
+    let keep_open_action = data.get_flag("keep-open-action");
+    // Document level scripts of all merged PDFs, for --merge-javascript
+    let mut scripts = Vec::new();
+    let bookmark_every = data.get_one::<u32>("bookmark-every").copied();
+    let page_offset = *data.get_one::<i64>("page-offset").unwrap();
+    let field_prefix = data.get_one::<String>("field-prefix");
+    let mut field_names = BTreeSet::new();
+    // Source file and page number of every page, for --manifest
+    let mut provenance = BTreeMap::new();
+    let group_by = data.get_one::<Regex>("group-by");
+    // The name and parent bookmark of the --group-by group the previous PDF was in
+    let mut group: Option<(String, u32)> = None;
+    let preserve_tags = data.get_flag("preserve-tags");
+    let mut structure_tree = structure::StructureTree::default();
+
+    for (index, (name, mut doc)) in documents.into_iter().enumerate() {
+        let mut first = true;
+        let title = document_title(&doc).unwrap_or_else(|| name.clone());
+        let is_base = base_index == Some(index);
+
+        // Reserved before the PDF is renumbered, so the separator ends up right before its pages.
+        // With --pad-to-even it gets a blank back, every PDF still starts on a front side
+        let separator_id = (separator.is_some() && index > 0).then(|| {
+            let id = (max_id, 0);
+            max_id += if pad_to_even { 2 } else { 1 };
+            id
+        });
+
+        doc.renumber_objects_with(max_id);
+        max_id = doc.max_id + 1;
+
+        if let Some(id) = separator_id {
+            let media_box = doc
+                .page_iter()
+                .next()
+                .and_then(|page| pages::inherited(&doc, page, b"MediaBox"));
+            let label = (separator == Some("name")).then_some(title.as_str());
+
+            let page = separator_page(
+                &mut documents_objects,
+                &mut max_id,
+                media_box.clone(),
+                label,
+            );
+            documents_pages.insert(id, page);
+            pagenum += 1;
+            if pad_to_even {
+                documents_pages.insert((id.0 + 1, 0), pages::blank_page(media_box));
+                pagenum += 1;
             }
         }
-        None => "output.pdf".into(),
-    };
 
-    let mut pdfs = data
-        .get_many::<String>("PDFs")
-        .with_context(|| "No PDFs found to merge")?
-        .peekable();
+        // With the values resolved, the dictionary doesn't depend on the ids of the objects
+        infos.push(
+            doc.trailer
+                .get(b"Info")
+                .and_then(|info| doc.dereference(info))
+                .and_then(|(_, info)| info.as_dict())
+                .ok()
+                .map(|info| {
+                    info.iter()
+                        .filter_map(|(key, value)| {
+                            Some((key.clone(), doc.dereference(value).ok()?.1.clone()))
+                        })
+                        .collect::<lopdf::Dictionary>()
+                })
+                .filter(|info| !info.is_empty()),
+        );
 
-    println!("Checking input validity...");
+        // Without --metadata the Info of the PDF that is appended to is the one that stays
+        if !is_base || data.get_one::<String>("metadata").is_some() {
+            if let Ok(id) = doc.trailer.get(b"Info").and_then(Object::as_reference) {
+                dropped_infos.insert(id);
+                if let Ok(info) = doc.get_dictionary(id) {
+                    dropped_infos.extend(info.iter().filter_map(|(_, v)| v.as_reference().ok()));
+                }
+            }
+        }
 
-    if pdfs.peek().is_none() {
-        Err(anyhow::anyhow!("No pdfs found"))?;
-    }
+        if is_base {
+            base_info = doc.trailer.get(b"Info").ok().cloned();
+            base_id = doc
+                .trailer
+                .get(b"ID")
+                .and_then(Object::as_array)
+                .ok()
+                .and_then(|id| id.first())
+                .and_then(|id| id.as_str().ok())
+                .map(<[u8]>::to_vec);
+        }
 
-    println!("Loading PDFs into memory...");
+        // Before the pages are taken, the numbers tying them to the tree can change
+        if preserve_tags {
+            structure_tree.add(&mut doc);
+        }
 
-    let documents = pdfs
-        .map(|f| {
-            if f.ends_with(".pdf") {
-                (
-                    f.clone(),
-                    Document::load(f)
-                        .with_context(|| format!("File not found: {}", f))
-                        .unwrap(),
-                )
-            } else {
-                let mut s = String::new();
-                s.push_str(f);
-                s.push_str(".pdf");
-                (
-                    s.clone(),
-                    Document::load(s.as_str())
-                        .with_context(|| format!("File not found: {}", f))
-                        .unwrap(),
-                )
+        // Only consecutive PDFs are grouped, the order of the pages stays as it was given
+        let group_name = group_by.and_then(|regex| file_group(regex, &name));
+        let parent = match (group_name, &group) {
+            (Some(group_name), Some((current, parent))) if *current == group_name => Some(*parent),
+            (Some(group_name), _) => {
+                group = doc.page_iter().next().map(|first_page| {
+                    let parent = document.add_bookmark(
+                        Bookmark::new(
+                            group_name.clone(),
+                            bookmark_color,
+                            bookmark_format,
+                            first_page,
+                        ),
+                        None,
+                    );
+                    (group_name, parent)
+                });
+                group.as_ref().map(|(_, parent)| *parent)
             }
-        })
-        .collect::<Vec<_>>();
+            (None, _) => {
+                group = None;
+                None
+            }
+        };
 
-    println!("Merging {} PDFs into {}...", documents.len(), output);
+        // With --keep-bookmarks-from only the chosen PDF keeps its own outline, the PDF that is
+        // appended to always does
+        let kept_outline = match keep_bookmarks_from {
+            _ if is_base => outline::read_outline(&doc),
+            Some(n) if n == index + 1 => outline::read_outline(&doc),
+            _ => Vec::new(),
+        };
+        outline::add_bookmarks(&mut document, &kept_outline, parent);
 
-    let mut max_id = 1;
-    let mut pagenum = 1;
+        if let Ok(root) = doc.trailer.get(b"Root").and_then(Object::as_reference) {
+            catalogs.push((name.clone(), root));
+        }
 
-    let mut documents_pages = BTreeMap::new();
-    let mut documents_objects = BTreeMap::new();
+        // Names only have to be unique within one PDF, prefix them with the number of the input.
+        // Links from elsewhere into the PDF that is appended to keep working with its own names
+        if is_base {
+            named_dests.extend(names::named_destinations(&doc));
+        } else {
+            named_dests.extend(names::prefix_named_destinations(
+                &mut doc,
+                &format!("{}:", index + 1),
+            ));
+        }
 
-    let mut document = Document::with_version("1.5");
+        // Scripts with the same name replace each other, prefix them like the named destinations
+        if merge_javascript {
+            let prefix = if is_base {
+                String::new()
+            } else {
+                format!("{}:", index + 1)
+            };
+            scripts.extend(
+                names::catalog_tree(&doc, b"JavaScript")
+                    .into_iter()
+                    .map(|(name, script)| ([prefix.as_bytes(), &name].concat(), script)),
+            );
+        }
 
-    for (name, mut doc) in documents {
-        let mut first = true;
+        // Fields with the same name would share their value, the ones of the PDF that is appended
+        // to keep their names
+        let number = (index + 1 - base_index.iter().count()).to_string();
+        let renamed = forms::rename_fields(&mut doc, |field| {
+            let renamed = match field_prefix {
+                Some(prefix) if !is_base => {
+                    Some(format!("{}{field}", prefix.replace("{n}", &number)))
+                }
+                _ if field_names.contains(field) => Some(format!("{field}_{number}")),
+                _ => None,
+            };
+            field_names.insert(renamed.clone().unwrap_or_else(|| field.to_owned()));
 
-        doc.renumber_objects_with(max_id);
-        max_id = doc.max_id + 1;
+            renamed
+        });
+        if renamed > 0 && field_prefix.is_none() {
+            println!("Renamed {renamed} form fields of {name} that clash with earlier PDFs");
+        }
+
+        // The bookmark of the whole PDF, with --bookmark-every the bookmarks of its pages go in it
+        let mut file_bookmark = None;
 
         documents_pages.extend(
             doc.get_pages()
-                .into_values()
-                .map(|object_id| {
+                .into_iter()
+                .map(|(page, object_id)| {
                     if first {
-                        document.add_bookmark(
-                            Bookmark::new(
-                                format!("{name}, page {pagenum}"),
-                                [0.0, 0.0, 1.0],
-                                0,
-                                object_id,
-                            ),
-                            None,
-                        );
+                        match keep_bookmarks_from {
+                            _ if is_base => {}
+                            None if bookmark_every.is_none() => {
+                                document.add_bookmark(
+                                    Bookmark::new(
+                                        format!("{name}, page {}", pagenum as i64 + page_offset),
+                                        bookmark_color,
+                                        bookmark_format,
+                                        object_id,
+                                    ),
+                                    parent,
+                                );
+                            }
+                            // The other PDFs get a single bookmark to their first page
+                            _ if kept_outline.is_empty() => {
+                                file_bookmark = Some(document.add_bookmark(
+                                    Bookmark::new(
+                                        title.clone(),
+                                        bookmark_color,
+                                        bookmark_format,
+                                        object_id,
+                                    ),
+                                    parent,
+                                ));
+                            }
+                            _ => {}
+                        }
+                        toc_entries.push((title.clone(), pagenum, object_id));
                         first = false;
-                    } else {
+                    } else if let (Some(every), Some(file_bookmark)) =
+                        (bookmark_every, file_bookmark)
+                    {
+                        if page % every == 0 {
+                            document.add_bookmark(
+                                Bookmark::new(
+                                    format!("Page {page}"),
+                                    bookmark_color,
+                                    bookmark_format,
+                                    object_id,
+                                ),
+                                Some(file_bookmark),
+                            );
+                        }
+                    } else if keep_bookmarks_from.is_none() && bookmark_every.is_none() && !is_base
+                    {
                         document.add_bookmark(
                             Bookmark::new(
-                                format!("{name}, page {pagenum}"),
-                                [0.0, 0.0, 1.0],
-                                0,
+                                format!("{name}, page {}", pagenum as i64 + page_offset),
+                                bookmark_color,
+                                bookmark_format,
                                 object_id,
                             ),
-                            None,
+                            parent,
                         );
                     }
                     pagenum += 1;
@@ -186,9 +4009,74 @@ fn merge_pdfs(data: &ArgMatches) -> Result<()> {
                 })
                 .collect::<BTreeMap<ObjectId, Object>>(),
         );
+
+        let pages = doc.get_pages();
+        provenance.extend(pages.iter().map(|(n, id)| (*id, (name.clone(), *n))));
+
+        if pad_to_even && pages.len() % 2 == 1 {
+            // The id after the range of this PDF keeps the blank page behind its last page
+            let media_box = pages
+                .values()
+                .last()
+                .and_then(|page| pages::inherited(&doc, *page, b"MediaBox"));
+
+            documents_pages.insert((max_id, 0), pages::blank_page(media_box));
+            max_id += 1;
+            pagenum += 1;
+        }
+
         documents_objects.extend(doc.objects);
     }
 
+    let mut replaced = false;
+
+    if let Some(replacements) = data.get_occurrences::<String>("replace-page") {
+        let page_ids = documents_pages.keys().copied().collect::<Vec<_>>();
+
+        for mut replacement in replacements {
+            let (page, f) = (replacement.next().unwrap(), replacement.next().unwrap());
+            let page = page.parse::<usize>()?;
+            if page > page_ids.len() {
+                return Err(anyhow::anyhow!(
+                    "Can't replace page {page}, the merged PDF has {} pages",
+                    page_ids.len()
+                ));
+            }
+
+            let name = input_path(f, data);
+
+            println!("Replacing page {page} with the first page of {name}...");
+
+            let mut fix = load_unencrypted(&name, false, data.get_flag("recover"))?;
+            fix.renumber_objects_with(max_id);
+            max_id = fix.max_id + 1;
+
+            let first = fix
+                .get_pages()
+                .into_values()
+                .next()
+                .with_context(|| format!("{name} does not have any pages"))?;
+
+            // The page moves into the merged page tree, take along what it inherited from its own
+            let mut replacement = fix.get_dictionary(first)?.clone();
+            for key in pages::INHERITABLE {
+                if !replacement.has(key) {
+                    if let Some(value) = pages::inherited(&fix, first, key) {
+                        replacement.set(key, value);
+                    }
+                }
+            }
+
+            // Keeping the id of the replaced page keeps its position, bookmarks and links
+            documents_pages.insert(page_ids[page - 1], Object::Dictionary(replacement));
+            provenance.insert(page_ids[page - 1], (name.clone(), 1));
+            documents_objects.extend(fix.objects.into_iter().filter(|(_, object)| {
+                !matches!(object.type_name(), Ok("Catalog" | "Pages" | "Page"))
+            }));
+            replaced = true;
+        }
+    }
+
     // Catalog and Pages are mandatory
     let mut catalog_object: Option<(ObjectId, Object)> = None;
     let mut pages_object: Option<(ObjectId, Object)> = None;
@@ -233,12 +4121,59 @@ fn merge_pdfs(data: &ArgMatches) -> Result<()> {
             "Page" => {}     // Ignored, processed later and separately
             "Outlines" => {} // Ignored, not supported yet
             "Outline" => {}  // Ignored, not supported yet
+            _ if dropped_infos.contains(object_id) => {}
+            // Annotations, their popups and appearance streams are kept as is, they were
+            // renumbered together with the pages that point at them
             _ => {
                 document.objects.insert(*object_id, object.clone());
             }
         }
     }
 
+    if interactive {
+        let open_actions = catalogs
+            .iter()
+            .map(|(_, id)| {
+                documents_objects
+                    .get(id)
+                    .and_then(|catalog| catalog.as_dict().ok())
+                    .and_then(|catalog| catalog.get(b"OpenAction").ok())
+                    .map(|action| format!("{action:?}"))
+            })
+            .collect::<BTreeSet<_>>();
+
+        if open_actions.len() > 1 {
+            // The catalog of the last PDF is used by default, so that one is listed first
+            let candidates = catalogs.iter().rev().collect::<Vec<_>>();
+            let options = candidates
+                .iter()
+                .map(|(name, _)| name.clone())
+                .collect::<Vec<_>>();
+
+            let choice = prompt_choice(
+                "The PDFs open with different actions, whose document catalog should be kept?",
+                &options,
+                0,
+            )?;
+
+            if let (Some((id, _)), Some(object)) = (
+                catalog_object.as_ref(),
+                documents_objects.get(&candidates[choice].1),
+            ) {
+                catalog_object = Some((*id, object.clone()));
+            }
+        }
+    }
+
+    if let Some(index) = base_index {
+        if let (Some((id, _)), Some(object)) = (
+            catalog_object.as_ref(),
+            documents_objects.get(&catalogs[index].1),
+        ) {
+            catalog_object = Some((*id, object.clone()));
+        }
+    }
+
     // If no "Pages" object found abort
     if pages_object.is_none() {
         println!("Pages root not found.");
@@ -268,19 +4203,46 @@ fn merge_pdfs(data: &ArgMatches) -> Result<()> {
     let catalog_object = catalog_object.unwrap();
     let pages_object = pages_object.unwrap();
 
+    let toc_page = if toc_page {
+        println!("Generating table of contents page...");
+
+        let media_box = documents_pages
+            .values()
+            .next()
+            .and_then(|page| page.as_dict().ok())
+            .and_then(|page| page.get(b"MediaBox").ok())
+            .cloned();
+
+        // All merged objects were inserted directly, continue numbering after the last one
+        document.max_id = max_id - 1;
+
+        Some(add_toc_page(
+            &mut document,
+            pages_object.0,
+            media_box,
+            &toc_entries,
+        )?)
+    } else {
+        None
+    };
+
     // Build a new "Pages" with updated fields
     if let Ok(dictionary) = pages_object.1.as_dict() {
         let mut dictionary = dictionary.clone();
 
         // Set new pages count
-        dictionary.set("Count", documents_pages.len() as u32);
+        dictionary.set(
+            "Count",
+            (documents_pages.len() + toc_page.iter().count()) as u32,
+        );
 
         // Set new "Kids" list (collected from documents pages) for "Pages"
         dictionary.set(
             "Kids",
-            documents_pages
+            toc_page
                 .into_iter()
-                .map(|(object_id, _)| Object::Reference(object_id))
+                .chain(documents_pages.into_keys())
+                .map(Object::Reference)
                 .collect::<Vec<_>>(),
         );
 
@@ -289,24 +4251,217 @@ fn merge_pdfs(data: &ArgMatches) -> Result<()> {
             .insert(pages_object.0, Object::Dictionary(dictionary));
     }
 
+    let catalog_ids = catalogs.iter().map(|(_, id)| *id).collect::<Vec<_>>();
+    let oc_properties = catalog::merge_oc_properties(&documents_objects, &catalog_ids);
+    let acro_form = catalog::merge_acro_forms(&documents_objects, &catalog_ids);
+
     // Build a new "Catalog" with updated fields
     if let Ok(dictionary) = catalog_object.1.as_dict() {
         let mut dictionary = dictionary.clone();
         dictionary.set("Pages", pages_object.0);
         dictionary.remove(b"Outlines"); // Outlines not supported in merged PDFs
 
+        // The catalog of the last PDF is the one that is kept, with its open action
+        if keep_open_action {
+            let first = catalog_ids
+                .first()
+                .and_then(|id| documents_objects.get(id))
+                .and_then(|catalog| catalog.as_dict().ok());
+            for key in [&b"OpenAction"[..], b"AA"] {
+                match first.and_then(|catalog| catalog.get(key).ok()) {
+                    Some(action) => dictionary.set(key, action.clone()),
+                    None => {
+                        dictionary.remove(key);
+                    }
+                }
+            }
+        }
+
+        // Keep the layers of all merged PDFs
+        if let Some(oc_properties) = oc_properties {
+            dictionary.set("OCProperties", oc_properties);
+        }
+
+        // Keep the form fields of all merged PDFs
+        if let Some(acro_form) = acro_form {
+            dictionary.set("AcroForm", acro_form);
+        }
+
+        if preserve_tags {
+            // The table of contents page adds objects without updating max_id
+            let id = (max_id.max(document.max_id + 1), 0);
+            if structure_tree.build(&mut document.objects, id) {
+                dictionary.set("StructTreeRoot", id);
+                dictionary.set("MarkInfo", dictionary! { "Marked" => true });
+            }
+        }
+
+        // Keep the named destinations of all merged PDFs, in a single name tree, and with
+        // --merge-javascript their document level scripts too
+        if !named_dests.is_empty() || merge_javascript {
+            let mut names_dictionary = dictionary
+                .get(b"Names")
+                .map(|names| catalog::resolve(&documents_objects, names))
+                .and_then(Object::as_dict)
+                .cloned()
+                .unwrap_or_default();
+            if !named_dests.is_empty() {
+                names_dictionary.set("Dests", names::name_tree(named_dests));
+                dictionary.remove(b"Dests");
+            }
+            if merge_javascript {
+                println!("Keeping {} document scripts", scripts.len());
+                names_dictionary.remove(b"JavaScript");
+                if !scripts.is_empty() {
+                    names_dictionary.set("JavaScript", names::name_tree(scripts));
+                }
+            }
+
+            dictionary.set("Names", names_dictionary);
+        }
+
         document
             .objects
             .insert(catalog_object.0, Object::Dictionary(dictionary));
     }
 
     document.trailer.set("Root", catalog_object.0);
+    let info = match data.get_one::<String>("metadata").map(String::as_str) {
+        None => {
+            // The Info of the PDF that is appended to is still among the merged objects
+            if let Some(info) = base_info {
+                document.trailer.set("Info", info);
+            }
+            None
+        }
+        Some("first") => infos.into_iter().flatten().next(),
+        Some("last") => infos.into_iter().flatten().last(),
+        Some("merge") => Some(merge_info(&infos.into_iter().flatten().collect::<Vec<_>>())),
+        _ => None,
+    };
+    if let Some(info) = info {
+        let id = (
+            document.objects.keys().map(|id| id.0).max().unwrap_or(0) + 1,
+            0,
+        );
+        document.objects.insert(id, Object::Dictionary(info));
+        document.trailer.set("Info", id);
+    }
+
+    if let Some(mode) = data.get_one::<String>("xmp") {
+        let metadata = match mode.as_str() {
+            "first" => catalog_ids
+                .iter()
+                .find(|id| catalog::catalog_entry(&documents_objects, **id, b"Metadata").is_some())
+                .and_then(|id| {
+                    documents_objects
+                        .get(id)?
+                        .as_dict()
+                        .ok()?
+                        .get(b"Metadata")
+                        .ok()
+                })
+                .cloned(),
+            "merge" => {
+                let packets = catalog_ids
+                    .iter()
+                    .filter_map(|id| xmp::catalog_xmp(&documents_objects, *id))
+                    .collect::<Vec<_>>();
+                (!packets.is_empty()).then(|| {
+                    let id = (
+                        document.objects.keys().map(|id| id.0).max().unwrap_or(0) + 1,
+                        0,
+                    );
+                    document
+                        .objects
+                        .insert(id, Object::Stream(xmp::merge_xmp(&packets)));
+                    Object::Reference(id)
+                })
+            }
+            _ => None,
+        };
+
+        // The packets of the other inputs aren't used anymore, nothing else points at them
+        for id in catalog_ids.iter().filter_map(|id| {
+            documents_objects
+                .get(id)?
+                .as_dict()
+                .ok()?
+                .get(b"Metadata")
+                .ok()?
+                .as_reference()
+                .ok()
+        }) {
+            if metadata.as_ref().and_then(|m| m.as_reference().ok()) != Some(id) {
+                document.objects.remove(&id);
+            }
+        }
+
+        if let Ok(catalog) = document.get_dictionary_mut(catalog_object.0) {
+            match metadata {
+                Some(metadata) => catalog.set("Metadata", metadata),
+                None => {
+                    catalog.remove(b"Metadata");
+                }
+            }
+        }
+    }
+
+    strip_thumbnails(&mut document, data);
+    strip_attachments(&mut document, data);
+    remove_links(&mut document, data);
+
+    if data.get_flag("dedup-pages") {
+        println!("Removing duplicate pages...");
+
+        let removed = pages::remove_duplicate_pages(&mut document);
 
-    // Update the max internal ID as wasn't updated before due to direct objects insertion
-    document.max_id = document.objects.len() as u32;
+        println!(
+            "Removed {} duplicate pages{}",
+            removed.len(),
+            if removed.is_empty() {
+                String::new()
+            } else {
+                format!(": {}", removed.iter().join(", "))
+            }
+        );
+    }
+
+    if let Some(manifest) = data.get_one::<String>("manifest") {
+        println!("Writing manifest {manifest}...");
+
+        let pages = document
+            .get_pages()
+            .into_iter()
+            .map(|(page, id)| {
+                let source = provenance.get(&id);
+                MergedPage {
+                    page,
+                    source: source.map(|(name, _)| name.clone()),
+                    source_page: source.map(|(_, page)| *page),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        std::fs::write(manifest, serde_json::to_string_pretty(&pages)?)
+            .with_context(|| format!("Failed to write manifest {}", manifest))?;
+    }
+
+    // Drop the resources only the replaced pages used
+    if replaced {
+        document.prune_objects();
+    }
+
+    if data.get_flag("no-final-renumber") {
+        // Keep the ids the inputs were renumbered to, new objects go after the highest one
+        document.max_id = document.objects.keys().map(|id| id.0).max().unwrap_or(0);
+    } else {
+        // Update the max internal ID as wasn't updated before due to direct objects insertion
+        document.max_id = document.objects.len() as u32;
 
-    // Reorder all new Document objects
-    document.renumber_objects();
+        // Reorder all new Document objects
+        document.renumber_objects();
+    }
 
     //Set any Bookmarks to the First child if they are not set to a page
     document.adjust_zero_pages();
@@ -318,19 +4473,523 @@ fn merge_pdfs(data: &ArgMatches) -> Result<()> {
         }
     }
 
-    document.compress();
+    add_output_intent(&mut document, data)?;
+    if let Some(view) = data.get_one::<OpenView>("open-fit") {
+        let first_page = document.page_iter().next();
+        if let Some(first_page) = first_page {
+            let destination = match view {
+                OpenView::Width => vec![first_page.into(), "FitH".into(), Object::Null],
+                OpenView::Page => vec![first_page.into(), "Fit".into()],
+                OpenView::Zoom(zoom) => vec![
+                    first_page.into(),
+                    "XYZ".into(),
+                    Object::Null,
+                    Object::Null,
+                    (*zoom).into(),
+                ],
+            };
+            if let Ok(Object::Dictionary(ref mut dict)) = document.get_object_mut(catalog_object.0)
+            {
+                dict.set("OpenAction", destination);
+            }
+        }
+    }
+
+    set_default_title(&mut document, data, &output);
+    resolve_references(&mut document, data);
+    set_file_id(&mut document, data, &output, base_id);
+    verify_page_tree(&document)?;
+    encode_streams(&mut document, data);
+    set_xref_type(&mut document, data);
 
     println!("Writing output file...");
+    progress(data, json!({"phase": "writing"}));
 
     document
         .save(&output)
         .with_context(|| format!("Failed to write output file {}", output))?;
+    finish_output(data, &output)?;
+
+    print_done(data, &[&output]);
+
+    Ok(())
+}
+
+/// Whether merge was given `--deterministic-id`, or `--deterministic` which implies it.
+fn deterministic_id(data: &ArgMatches) -> bool {
+    data.get_flag("deterministic") || data.get_flag("deterministic-id")
+}
+
+/// Give the merged `doc` a file identifier. The first half identifies the file across versions, so
+/// it is kept from the file appended to when there is one, the second half is new for every version.
+fn set_file_id(doc: &mut Document, data: &ArgMatches, output: &str, original: Option<Vec<u8>>) {
+    let seed = if deterministic_id(data) {
+        // Documents with the same pages still differ in their metadata and embedded files
+        let info = doc
+            .trailer
+            .get(b"Info")
+            .and_then(|info| doc.dereference(info))
+            .map(|(_, info)| format!("{info:?}"))
+            .unwrap_or_default();
+        let xmp = doc
+            .trailer
+            .get(b"Root")
+            .and_then(Object::as_reference)
+            .ok()
+            .and_then(|catalog| xmp::catalog_xmp(&doc.objects, catalog))
+            .unwrap_or_default();
+        let files = doc
+            .objects
+            .values()
+            .filter_map(|object| object.as_stream().ok())
+            .filter(|stream| {
+                stream.dict.get(b"Type").and_then(Object::as_name).ok() == Some(b"EmbeddedFile")
+            })
+            .map(|stream| format!("{:x}", md5::compute(&stream.content)))
+            .join("");
+
+        format!("{}{info}{xmp}{files}", pages::content_hash(doc))
+    } else {
+        format!(
+            "{:?}{}{output}{}",
+            std::time::SystemTime::now(),
+            std::process::id(),
+            doc.objects.len()
+        )
+    };
+    let id = md5::compute(seed).0.to_vec();
+    let original = original.unwrap_or_else(|| id.clone());
+
+    doc.trailer.set(
+        "ID",
+        vec![
+            Object::String(original, lopdf::StringFormat::Hexadecimal),
+            Object::String(id, lopdf::StringFormat::Hexadecimal),
+        ],
+    );
+}
+
+/// Check that the flat page tree of a merged `doc` is consistent: the `/Count` of the root has to
+/// match both its `/Kids` and the pages that are found through them. Some viewers trust the count.
+fn verify_page_tree(doc: &Document) -> Result<()> {
+    let pages = doc
+        .catalog()
+        .and_then(|catalog| catalog.get(b"Pages"))
+        .and_then(Object::as_reference)
+        .and_then(|id| doc.get_dictionary(id))
+        .with_context(|| "The merged PDF has no page tree")?;
+
+    let count = pages.get(b"Count").and_then(Object::as_i64).unwrap_or(-1);
+    let kids = pages
+        .get(b"Kids")
+        .and_then(Object::as_array)
+        .map_or(0, Vec::len);
+    let found = doc.get_pages().len();
+
+    if count != kids as i64 || kids != found {
+        return Err(anyhow::anyhow!(
+            "The merged page tree is inconsistent: /Count is {count}, with {kids} /Kids and {found} pages found. This is a bug in pdft"
+        ));
+    }
+
+    Ok(())
+}
+
+/// `merge --portfolio`: embed the PDFs as they are into a portfolio.
+fn merge_into_portfolio<'a>(
+    pdfs: impl Iterator<Item = &'a String>,
+    data: &ArgMatches,
+    output: &str,
+) -> Result<()> {
+    let names = expand_inputs(pdfs, data)?;
+
+    println!("Reading {} PDFs...", names.len());
+
+    let mut files = Vec::new();
+    for (current, name) in names.iter().enumerate() {
+        progress(
+            data,
+            json!({"phase": "loading", "current": current + 1, "total": names.len(), "file": name}),
+        );
+
+        // Gzipped inputs are embedded decompressed, as the PDF they contain
+        let file_name = name
+            .rsplit(['/', '\\'])
+            .next()
+            .unwrap_or(name)
+            .trim_end_matches(".gz");
+        files.push((file_name.to_owned(), read_pdf(name)?));
+    }
+
+    println!(
+        "Building a portfolio of {} files in {output}...",
+        files.len()
+    );
+    progress(data, json!({"phase": "merging"}));
+
+    let mut document = portfolio::portfolio(&files)?;
+    set_file_id(&mut document, data, output, None);
+    encode_streams(&mut document, data);
+    set_xref_type(&mut document, data);
+
+    println!("Writing output file...");
+    progress(data, json!({"phase": "writing"}));
+
+    document
+        .save(output)
+        .with_context(|| format!("Failed to write output file {}", output))?;
+    finish_output(data, output)?;
+
+    print_done(data, &[output]);
+
+    Ok(())
+}
+
+/// Ask the user to pick one of `options` on stdin, returns the index of the chosen option.
+/// Empty input (or a closed stdin) picks `default`.
+fn prompt_choice(question: &str, options: &[String], default: usize) -> Result<usize> {
+    println!("{question}");
+
+    for (i, option) in options.iter().enumerate() {
+        let marker = if i == default { " (default)" } else { "" };
+        println!("  {}) {option}{marker}", i + 1);
+    }
+
+    loop {
+        print!("Choose 1-{} [{}]: ", options.len(), default + 1);
+        io::stdout().flush()?;
+
+        let mut line = String::new();
+        if io::stdin().read_line(&mut line)? == 0 {
+            return Ok(default);
+        }
+
+        match line.trim() {
+            "" => return Ok(default),
+            n => match n.parse::<usize>() {
+                Ok(n) if (1..=options.len()).contains(&n) => return Ok(n - 1),
+                _ => println!("Please enter a number between 1 and {}", options.len()),
+            },
+        }
+    }
+}
 
-    println!("🦀 All done! 🦀");
+/// Read the `/Title` entry from the document information dictionary, if there is one.
+fn document_title(doc: &Document) -> Option<String> {
+    let info = match doc.trailer.get(b"Info").ok()? {
+        Object::Reference(id) => doc.get_dictionary(*id).ok()?,
+        Object::Dictionary(dict) => dict,
+        _ => return None,
+    };
+
+    let title = match info.get(b"Title").ok()? {
+        Object::String(bytes, _) => decode_text_string(bytes),
+        _ => return None,
+    };
+
+    (!title.trim().is_empty()).then_some(title)
+}
+
+/// Combine the document information dictionaries of merged PDFs for `--metadata merge`: the
+/// longest title, every author once and the other entries of the first PDF that has them.
+fn merge_info(infos: &[lopdf::Dictionary]) -> lopdf::Dictionary {
+    let text = |info: &lopdf::Dictionary, key: &[u8]| match info.get(key) {
+        Ok(Object::String(bytes, _)) => {
+            Some(decode_text_string(bytes)).filter(|t| !t.trim().is_empty())
+        }
+        _ => None,
+    };
+
+    let mut merged = lopdf::Dictionary::new();
+    for info in infos.iter().rev() {
+        merged.extend(info);
+    }
+
+    if let Some(title) = infos
+        .iter()
+        .filter_map(|info| text(info, b"Title"))
+        .max_by_key(|title| title.chars().count())
+    {
+        merged.set("Title", encode_text_string(&title));
+    }
+
+    let authors = infos
+        .iter()
+        .filter_map(|info| text(info, b"Author"))
+        .unique()
+        .join("; ");
+    if !authors.is_empty() {
+        merged.set("Author", encode_text_string(&authors));
+    }
+
+    merged
+}
+
+/// Encode `text` as a PDF text string, as-is when it is plain ASCII and as UTF-16 otherwise.
+fn encode_text_string(text: &str) -> Object {
+    if text.is_ascii() {
+        Object::string_literal(text)
+    } else {
+        let bytes = [0xFE, 0xFF]
+            .into_iter()
+            .chain(text.encode_utf16().flat_map(u16::to_be_bytes))
+            .collect();
+        Object::String(bytes, lopdf::StringFormat::Hexadecimal)
+    }
+}
+
+/// Apply `--title-from-filename`: give a document without a title the stem of the file it will
+/// be saved as.
+fn set_default_title(doc: &mut Document, data: &ArgMatches, output: &str) {
+    if !data.get_flag("title-from-filename") || document_title(doc).is_some() {
+        return;
+    }
+    let Some(title) = Path::new(output).file_stem().and_then(|stem| stem.to_str()) else {
+        return;
+    };
+
+    let info_id = match doc.trailer.get(b"Info") {
+        Ok(Object::Reference(id)) => *id,
+        Ok(Object::Dictionary(info)) => {
+            let info = info.clone();
+            doc.add_object(info)
+        }
+        _ => doc.add_object(dictionary! {}),
+    };
+    doc.trailer.set("Info", info_id);
+
+    if let Ok(info) = doc.get_dictionary_mut(info_id) {
+        info.set("Title", encode_text_string(title));
+    }
+}
+
+/// Decode a PDF text string, which is either UTF-16BE with a byte order mark or PDFDocEncoding.
+fn decode_text_string(bytes: &[u8]) -> String {
+    if let Some(utf16) = bytes.strip_prefix(&[0xFE, 0xFF]) {
+        let units = utf16
+            .chunks_exact(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+            .collect::<Vec<_>>();
+        String::from_utf16_lossy(&units)
+    } else {
+        // PDFDocEncoding matches WinAnsiEncoding for all printable characters that matter here
+        Document::decode_text(Some("WinAnsiEncoding"), bytes)
+    }
+}
+
+/// Create a table of contents page listing every `(title, page number, first page)` entry, each
+/// row being a link to the first page of that entry. Returns the id of the new page, which still
+/// has to be added to the "Kids" of `parent`.
+fn add_toc_page(
+    document: &mut Document,
+    parent: ObjectId,
+    media_box: Option<Object>,
+    entries: &[(String, u32, ObjectId)],
+) -> Result<ObjectId> {
+    // A4 is used when the first merged page does not carry its own MediaBox
+    let media_box =
+        media_box.unwrap_or_else(|| vec![0.into(), 0.into(), 595.into(), 842.into()].into());
+    let rect = media_box
+        .as_array()
+        .ok()
+        .and_then(|rect| {
+            rect.iter()
+                .map(|n| n.as_float().ok())
+                .collect::<Option<Vec<_>>>()
+        })
+        .filter(|rect| rect.len() == 4)
+        .with_context(|| "Invalid MediaBox on first page")?;
+    let (left, bottom, right, top) = (rect[0], rect[1], rect[2], rect[3]);
+
+    let margin = 72.0;
+    let heading_size = 18.0;
+    let first_row = top - margin - heading_size * 2.0;
+
+    // Shrink the rows when there are too many entries to fit on a single page
+    let row_height = ((first_row - bottom - margin) / entries.len().max(1) as f32).min(18.0);
+    let font_size = row_height * 2.0 / 3.0;
+
+    let font_id = document.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type1",
+        "BaseFont" => "Helvetica",
+        "Encoding" => "WinAnsiEncoding",
+    });
+    let bold_font_id = document.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type1",
+        "BaseFont" => "Helvetica-Bold",
+        "Encoding" => "WinAnsiEncoding",
+    });
+
+    let text = |font: &str, size: f32, x: f32, y: f32, text: &str| {
+        vec![
+            Operation::new("BT", vec![]),
+            Operation::new("Tf", vec![font.into(), size.into()]),
+            Operation::new("Td", vec![x.into(), y.into()]),
+            Operation::new(
+                "Tj",
+                vec![Object::string_literal(Document::encode_text(
+                    Some("WinAnsiEncoding"),
+                    text,
+                ))],
+            ),
+            Operation::new("ET", vec![]),
+        ]
+    };
+
+    let mut operations = text(
+        "F2",
+        heading_size,
+        left + margin,
+        top - margin - heading_size,
+        "Contents",
+    );
+    let mut annotations = Vec::new();
+
+    for (row, (title, pagenum, target)) in entries.iter().enumerate() {
+        let y = first_row - row as f32 * row_height;
+
+        operations.extend(text("F1", font_size, left + margin, y, title));
+        operations.extend(text(
+            "F1",
+            font_size,
+            right - margin - font_size * 2.0,
+            y,
+            &pagenum.to_string(),
+        ));
+
+        annotations.push(Object::Reference(document.add_object(dictionary! {
+            "Type" => "Annot",
+            "Subtype" => "Link",
+            "Rect" => vec![
+                (left + margin).into(),
+                (y - row_height / 4.0).into(),
+                (right - margin).into(),
+                (y + row_height * 3.0 / 4.0).into(),
+            ],
+            "Border" => vec![0.into(), 0.into(), 0.into()],
+            "Dest" => vec![Object::Reference(*target), "Fit".into()],
+        })));
+    }
+
+    let content = Content { operations }
+        .encode()
+        .with_context(|| "Failed to encode table of contents")?;
+    let content_id = document.add_object(Stream::new(dictionary! {}, content));
+
+    Ok(document.add_object(dictionary! {
+        "Type" => "Page",
+        "Parent" => parent,
+        "MediaBox" => media_box,
+        "Contents" => content_id,
+        "Resources" => dictionary! {
+            "Font" => dictionary! {
+                "F1" => font_id,
+                "F2" => bold_font_id,
+            },
+        },
+        "Annots" => annotations,
+    }))
+}
+
+/// Take the pages and apply the rotation a `--job` gives for the input `name`.
+fn apply_job_input(doc: &mut Document, name: &str, input: &job::Input) -> Result<()> {
+    if let Some(spec) = &input.pages {
+        let page_count = doc.get_pages().len() as u32;
+        let keep = pages::parse_page_spec(spec, page_count)
+            .with_context(|| format!("Invalid pages {spec:?} for {name} in the job"))?
+            .into_iter()
+            .collect::<BTreeSet<_>>();
+        pages::keep_pages(doc, &keep);
+    }
+
+    if let Some(angle) = input.rotate {
+        for (_, page_id) in doc.get_pages() {
+            pages::rotate_page(doc, page_id, angle);
+        }
+    }
 
     Ok(())
 }
 
+/// A `--separator-page`, blank or showing `label`, with its objects added to `objects` from
+/// `max_id` on. The parent still has to be set.
+fn separator_page(
+    objects: &mut BTreeMap<ObjectId, Object>,
+    max_id: &mut u32,
+    media_box: Option<Object>,
+    label: Option<&str>,
+) -> Object {
+    let mut page = pages::blank_page(media_box);
+    let Some(label) = label else {
+        return page;
+    };
+
+    let mut add = |object: Object| {
+        let id = (*max_id, 0);
+        objects.insert(id, object);
+        *max_id += 1;
+        id
+    };
+
+    let Ok(page_dict) = page.as_dict_mut() else {
+        return page;
+    };
+    let rect = page_dict
+        .get(b"MediaBox")
+        .and_then(Object::as_array)
+        .ok()
+        .and_then(|rect| {
+            rect.iter()
+                .map(|n| n.as_float().ok())
+                .collect::<Option<Vec<_>>>()
+        })
+        .filter(|rect| rect.len() == 4)
+        .unwrap_or_else(|| vec![0.0, 0.0, 595.0, 842.0]);
+    let (left, bottom, right, top) = (rect[0], rect[1], rect[2], rect[3]);
+
+    // Helvetica is about half as wide as it is high, shrink long labels to fit between the margins
+    let margin = 72.0;
+    let size = ((right - left - margin * 2.0) / (label.chars().count().max(1) as f32 * 0.55))
+        .clamp(6.0, 24.0);
+
+    let font_id = add(Object::Dictionary(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type1",
+        "BaseFont" => "Helvetica-Bold",
+        "Encoding" => "WinAnsiEncoding",
+    }));
+    let operations = vec![
+        Operation::new("BT", vec![]),
+        Operation::new("Tf", vec!["F1".into(), size.into()]),
+        Operation::new(
+            "Td",
+            vec![(left + margin).into(), ((bottom + top) / 2.0).into()],
+        ),
+        Operation::new(
+            "Tj",
+            vec![Object::string_literal(Document::encode_text(
+                Some("WinAnsiEncoding"),
+                label,
+            ))],
+        ),
+        Operation::new("ET", vec![]),
+    ];
+    let Ok(content) = (Content { operations }).encode() else {
+        return page;
+    };
+    let content_id = add(Object::Stream(Stream::new(dictionary! {}, content)));
+
+    page_dict.set("Contents", content_id);
+    page_dict.set(
+        "Resources",
+        dictionary! { "Font" => dictionary! { "F1" => font_id } },
+    );
+
+    page
+}
+
 // A simple PDF tool to merge files, etc.
 // #[derive(Parser, Debug)]
 // #[command(version)]
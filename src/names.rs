@@ -0,0 +1,154 @@
+//! Named destinations, the targets of links that point to a name instead of a page.
+//!
+//! Names are only unique within one PDF, so when merging every input gets its names prefixed and
+//! all of them are combined into a single name tree.
+
+use std::collections::BTreeSet;
+
+use lopdf::{Dictionary, Document, Object, ObjectId, StringFormat};
+
+/// Name trees are nested through references, don't follow broken ones forever.
+const MAX_DEPTH: usize = 32;
+
+/// Collect the leaves of a name tree into `names`.
+fn collect_tree(
+    doc: &Document,
+    node: &Dictionary,
+    names: &mut Vec<(Vec<u8>, Object)>,
+    visited: &mut BTreeSet<ObjectId>,
+    depth: usize,
+) {
+    if depth > MAX_DEPTH {
+        return;
+    }
+
+    if let Ok(pairs) = node.get(b"Names").and_then(Object::as_array) {
+        for pair in pairs.chunks_exact(2) {
+            if let Ok(name) = doc
+                .dereference(&pair[0])
+                .and_then(|(_, name)| name.as_str())
+            {
+                names.push((name.to_vec(), pair[1].clone()));
+            }
+        }
+    }
+
+    for kid in node
+        .get(b"Kids")
+        .and_then(Object::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+    {
+        if let Ok(id) = kid.as_reference() {
+            if !visited.insert(id) {
+                continue;
+            }
+        }
+        if let Ok((_, Object::Dictionary(kid))) = doc.dereference(kid) {
+            collect_tree(doc, kid, names, visited, depth + 1);
+        }
+    }
+}
+
+/// Every named destination of `doc`, from both the `/Dests` name tree and the older `/Dests`
+/// dictionary of the catalog.
+pub fn named_destinations(doc: &Document) -> Vec<(Vec<u8>, Object)> {
+    let mut names = Vec::new();
+    let Ok(catalog) = doc.catalog() else {
+        return names;
+    };
+
+    if let Ok((_, Object::Dictionary(dests))) = catalog
+        .get(b"Dests")
+        .and_then(|dests| doc.dereference(dests))
+    {
+        names.extend(
+            dests
+                .iter()
+                .map(|(name, dest)| (name.clone(), dest.clone())),
+        );
+    }
+
+    names.extend(catalog_tree(doc, b"Dests"));
+    names
+}
+
+/// The leaves of the name tree `key` of the `/Names` dictionary of the catalog, like `Dests` or
+/// `JavaScript`.
+pub fn catalog_tree(doc: &Document, key: &[u8]) -> Vec<(Vec<u8>, Object)> {
+    let tree = doc
+        .catalog()
+        .and_then(|catalog| catalog.get(b"Names"))
+        .and_then(|names| doc.dereference(names))
+        .and_then(|(_, names)| names.as_dict())
+        .and_then(|names| names.get(key))
+        .and_then(|tree| doc.dereference(tree))
+        .and_then(|(_, tree)| tree.as_dict());
+
+    match tree {
+        Ok(tree) => tree_entries(doc, tree),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// The leaves of the name tree `tree`, in tree order.
+pub fn tree_entries(doc: &Document, tree: &Dictionary) -> Vec<(Vec<u8>, Object)> {
+    let mut names = Vec::new();
+    collect_tree(doc, tree, &mut names, &mut BTreeSet::new(), 0);
+    names
+}
+
+fn prefixed(prefix: &[u8], name: &[u8]) -> Object {
+    Object::String([prefix, name].concat(), StringFormat::Literal)
+}
+
+/// Prefix the named destinations of `doc` with `prefix`, together with every link, bookmark and
+/// action that uses them, and return them.
+pub fn prefix_named_destinations(doc: &mut Document, prefix: &str) -> Vec<(Vec<u8>, Object)> {
+    let prefix = prefix.as_bytes();
+    let names = named_destinations(doc)
+        .into_iter()
+        .map(|(name, dest)| ([prefix, &name].concat(), dest))
+        .collect::<Vec<_>>();
+
+    doc.traverse_objects(|object| {
+        let Object::Dictionary(dict) = object else {
+            return;
+        };
+
+        // Links and bookmarks have a /Dest, go-to actions a /D
+        let key = if dict.has(b"Dest") {
+            &b"Dest"[..]
+        } else if dict.get(b"S").and_then(Object::as_name).ok() == Some(b"GoTo") {
+            b"D"
+        } else {
+            return;
+        };
+
+        let renamed = match dict.get(key) {
+            Ok(Object::String(name, _)) | Ok(Object::Name(name)) => prefixed(prefix, name),
+            _ => return,
+        };
+        dict.set(key, renamed);
+    });
+
+    names
+}
+
+/// Build a name tree holding all `names`, as a single leaf node.
+pub fn name_tree(mut names: Vec<(Vec<u8>, Object)>) -> Dictionary {
+    // The keys of a name tree have to be sorted, the first definition of a name wins
+    names.sort_by(|a, b| a.0.cmp(&b.0));
+    names.dedup_by(|a, b| a.0 == b.0);
+
+    let mut tree = Dictionary::new();
+    tree.set(
+        "Names",
+        names
+            .into_iter()
+            .flat_map(|(name, dest)| [Object::String(name, StringFormat::Literal), dest])
+            .collect::<Vec<_>>(),
+    );
+
+    tree
+}
@@ -0,0 +1,297 @@
+//! Normalization passes for documents that are about to be saved.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use lopdf::{Document, Object, ObjectId};
+
+/// Objects that are cheaper and safer to store directly than behind a reference.
+fn is_trivial(object: &Object) -> bool {
+    matches!(
+        object,
+        Object::Null | Object::Boolean(_) | Object::Integer(_) | Object::Real(_) | Object::Name(_)
+    )
+}
+
+fn replace_references(object: &mut Object, replacements: &BTreeMap<ObjectId, Object>) -> usize {
+    match object {
+        Object::Reference(id) => match replacements.get(id) {
+            Some(replacement) => {
+                *object = replacement.clone();
+                1
+            }
+            None => 0,
+        },
+        Object::Array(array) => array
+            .iter_mut()
+            .map(|o| replace_references(o, replacements))
+            .sum(),
+        Object::Dictionary(dict) => dict
+            .iter_mut()
+            .map(|(_, o)| replace_references(o, replacements))
+            .sum(),
+        Object::Stream(stream) => stream
+            .dict
+            .iter_mut()
+            .map(|(_, o)| replace_references(o, replacements))
+            .sum(),
+        _ => 0,
+    }
+}
+
+/// Point references to a reference straight at the object at the end of the chain, and store
+/// numbers, names, booleans and nulls directly where they are used. Returns how many references
+/// were rewritten.
+///
+/// Not every reader follows a reference that leads to another reference, this makes sure none
+/// of those are left.
+pub fn resolve_references(doc: &mut Document) -> usize {
+    let mut replacements = BTreeMap::new();
+
+    for (id, object) in doc.objects.iter() {
+        if is_trivial(object) {
+            replacements.insert(*id, object.clone());
+            continue;
+        }
+
+        let Object::Reference(mut target) = object else {
+            continue;
+        };
+
+        let mut visited = BTreeSet::from([*id]);
+        let replacement = loop {
+            match doc.objects.get(&target) {
+                Some(Object::Reference(next)) if visited.insert(target) => target = *next,
+                // A reference cycle doesn't lead anywhere, which readers treat as null
+                Some(Object::Reference(_)) => break Object::Null,
+                Some(object) if is_trivial(object) => break object.clone(),
+                Some(_) => break Object::Reference(target),
+                // Keep dangling references as they are, they are not ours to fix
+                None => break Object::Reference(target),
+            }
+        };
+
+        replacements.insert(*id, replacement);
+    }
+
+    let mut replaced = doc
+        .trailer
+        .iter_mut()
+        .map(|(_, object)| replace_references(object, &replacements))
+        .sum::<usize>();
+    replaced += doc
+        .objects
+        .values_mut()
+        .map(|object| replace_references(object, &replacements))
+        .sum::<usize>();
+
+    // Nothing refers to the replaced objects anymore
+    for id in replacements.keys() {
+        doc.objects.remove(id);
+    }
+
+    replaced
+}
+
+/// Encode every stream of `doc` as ASCII85 on top of the filters it already has, so the whole
+/// file is printable text. Returns the number of encoded streams.
+pub fn ascii85_streams(doc: &mut Document) -> usize {
+    let mut encoded = 0;
+
+    for object in doc.objects.values_mut() {
+        let Object::Stream(stream) = object else {
+            continue;
+        };
+
+        // The new filter is undone first, so it goes in front of the existing ones
+        let (filters, params) = match (stream.dict.get(b"Filter"), stream.dict.get(b"DecodeParms"))
+        {
+            (Ok(Object::Array(filters)), params) => {
+                let params = params
+                    .ok()
+                    .and_then(|params| params.as_array().ok())
+                    .map(|params| {
+                        std::iter::once(Object::Null)
+                            .chain(params.iter().cloned())
+                            .collect::<Vec<_>>()
+                    });
+                (
+                    std::iter::once("ASCII85Decode".into())
+                        .chain(filters.iter().cloned())
+                        .collect(),
+                    params,
+                )
+            }
+            (Ok(filter), params) => (
+                vec!["ASCII85Decode".into(), filter.clone()],
+                params.ok().map(|params| vec![Object::Null, params.clone()]),
+            ),
+            (Err(_), _) => (vec!["ASCII85Decode".into()], None),
+        };
+
+        stream.dict.set("Filter", filters);
+        match params {
+            Some(params) => stream.dict.set("DecodeParms", params),
+            None => {
+                stream.dict.remove(b"DecodeParms");
+            }
+        }
+
+        let content = ascii85(&stream.content);
+        stream.set_content(content);
+        encoded += 1;
+    }
+
+    encoded
+}
+
+/// ASCII85 encode `bytes`, with line breaks to keep the lines short.
+fn ascii85(bytes: &[u8]) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(bytes.len() * 5 / 4 + bytes.len() / 64 + 2);
+
+    for (i, chunk) in bytes.chunks(4).enumerate() {
+        if i > 0 && i % 16 == 0 {
+            encoded.push(b'\n');
+        }
+
+        let mut group = [0; 4];
+        group[..chunk.len()].copy_from_slice(chunk);
+        let mut value = u32::from_be_bytes(group);
+
+        // A whole group of zeros has a shorthand, partial groups don't
+        if value == 0 && chunk.len() == 4 {
+            encoded.push(b'z');
+            continue;
+        }
+
+        let mut digits = [0; 5];
+        for digit in digits.iter_mut().rev() {
+            *digit = (value % 85) as u8 + b'!';
+            value /= 85;
+        }
+        encoded.extend_from_slice(&digits[..chunk.len() + 1]);
+    }
+
+    encoded.extend_from_slice(b"~>");
+    encoded
+}
+
+/// The ICC profile streams used by `[/ICCBased profile]` color spaces anywhere in `doc`, with the
+/// device color space each of them can fall back to.
+fn icc_profiles(doc: &Document) -> BTreeMap<ObjectId, &'static str> {
+    fn collect(doc: &Document, object: &Object, profiles: &mut BTreeMap<ObjectId, &'static str>) {
+        match object {
+            Object::Array(array) => {
+                if let [Object::Name(name), Object::Reference(id)] = &array[..] {
+                    if name == b"ICCBased" {
+                        let components = doc
+                            .get_object(*id)
+                            .and_then(Object::as_stream)
+                            .and_then(|profile| profile.dict.get(b"N"))
+                            .and_then(Object::as_i64);
+                        let device = match components {
+                            Ok(1) => "DeviceGray",
+                            Ok(3) => "DeviceRGB",
+                            Ok(4) => "DeviceCMYK",
+                            _ => return,
+                        };
+                        profiles.insert(*id, device);
+                        return;
+                    }
+                }
+                array.iter().for_each(|o| collect(doc, o, profiles));
+            }
+            Object::Dictionary(dict) => dict.iter().for_each(|(_, o)| collect(doc, o, profiles)),
+            Object::Stream(stream) => stream
+                .dict
+                .iter()
+                .for_each(|(_, o)| collect(doc, o, profiles)),
+            _ => {}
+        }
+    }
+
+    let mut profiles = BTreeMap::new();
+    for object in doc.objects.values() {
+        collect(doc, object, &mut profiles);
+    }
+
+    profiles
+}
+
+fn replace_everywhere(doc: &mut Document, replacements: &BTreeMap<ObjectId, Object>) {
+    for object in doc.objects.values_mut() {
+        replace_references(object, replacements);
+    }
+    for (_, object) in doc.trailer.iter_mut() {
+        replace_references(object, replacements);
+    }
+}
+
+/// Store ICC profiles that are embedded more than once, often one copy per image, only once.
+/// Returns how many copies were removed.
+pub fn dedup_icc_profiles(doc: &mut Document) -> usize {
+    let mut first = BTreeMap::new();
+    let mut replacements = BTreeMap::new();
+
+    for id in icc_profiles(doc).into_keys() {
+        let Ok(Object::Stream(profile)) = doc.get_object(id) else {
+            continue;
+        };
+        // The same profile can be compressed differently, compare what's inside
+        let content = profile
+            .decompressed_content()
+            .unwrap_or_else(|_| profile.content.clone());
+
+        match first.get(&content) {
+            Some(original) => {
+                replacements.insert(id, Object::Reference(*original));
+            }
+            None => {
+                first.insert(content, id);
+            }
+        }
+    }
+
+    replace_everywhere(doc, &replacements);
+    for id in replacements.keys() {
+        doc.objects.remove(id);
+    }
+
+    replacements.len()
+}
+
+/// Replace every ICC based color space by the device color space with the same number of
+/// components, and remove the profiles. Colors can shift a little, the profile is what tells a
+/// viewer how they are meant to look. Returns how many profiles were removed.
+pub fn strip_icc_profiles(doc: &mut Document) -> usize {
+    fn replace(object: &mut Object, profiles: &BTreeMap<ObjectId, &'static str>) {
+        match object {
+            Object::Array(array) => {
+                if let [Object::Name(name), Object::Reference(id)] = &array[..] {
+                    if let (b"ICCBased", Some(device)) = (name.as_slice(), profiles.get(id)) {
+                        *object = Object::Name(device.as_bytes().to_vec());
+                        return;
+                    }
+                }
+                array.iter_mut().for_each(|o| replace(o, profiles));
+            }
+            Object::Dictionary(dict) => dict.iter_mut().for_each(|(_, o)| replace(o, profiles)),
+            Object::Stream(stream) => stream
+                .dict
+                .iter_mut()
+                .for_each(|(_, o)| replace(o, profiles)),
+            _ => {}
+        }
+    }
+
+    let profiles = icc_profiles(doc);
+    for object in doc.objects.values_mut() {
+        replace(object, &profiles);
+    }
+
+    // Profiles that are still used elsewhere, like by an output intent, stay
+    doc.prune_objects();
+    profiles
+        .keys()
+        .filter(|id| !doc.objects.contains_key(id))
+        .count()
+}
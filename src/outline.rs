@@ -0,0 +1,181 @@
+use std::collections::BTreeSet;
+
+use lopdf::{Bookmark, Dictionary, Document, Object, ObjectId};
+
+/// Outline trees are linked lists, don't follow broken ones forever.
+const MAX_ITEMS: usize = 100_000;
+
+/// An entry of the outline (bookmarks) of an existing PDF.
+#[derive(Debug, Clone)]
+pub struct OutlineItem {
+    pub title: String,
+    /// Page the entry points to
+    pub page: ObjectId,
+    pub color: [f32; 3],
+    /// 0, 1 for italic, 2 for bold, 3 for italic bold
+    pub format: u32,
+    pub children: Vec<OutlineItem>,
+}
+
+/// Read the outline of `doc`. Entries that don't point to a page of the document itself (like
+/// links to other files) are left out, their children are kept.
+pub fn read_outline(doc: &Document) -> Vec<OutlineItem> {
+    let Some(outlines) = doc
+        .catalog()
+        .ok()
+        .and_then(|catalog| catalog.get(b"Outlines").ok())
+        .and_then(|outlines| dictionary(doc, outlines))
+    else {
+        return Vec::new();
+    };
+
+    let pages = doc.get_pages().into_values().collect::<BTreeSet<_>>();
+    let mut visited = BTreeSet::new();
+
+    read_items(doc, outlines, &pages, &mut visited)
+}
+
+fn dictionary<'a>(doc: &'a Document, object: &'a Object) -> Option<&'a Dictionary> {
+    doc.dereference(object).ok()?.1.as_dict().ok()
+}
+
+fn read_items(
+    doc: &Document,
+    parent: &Dictionary,
+    pages: &BTreeSet<ObjectId>,
+    visited: &mut BTreeSet<ObjectId>,
+) -> Vec<OutlineItem> {
+    let mut items = Vec::new();
+    let mut next = parent.get(b"First").and_then(Object::as_reference).ok();
+
+    while let Some(id) = next {
+        if !visited.insert(id) || visited.len() > MAX_ITEMS {
+            break;
+        }
+        let Ok(node) = doc.get_dictionary(id) else {
+            break;
+        };
+        next = node.get(b"Next").and_then(Object::as_reference).ok();
+
+        let children = read_items(doc, node, pages, visited);
+
+        let page = node
+            .get(b"Dest")
+            .ok()
+            .or_else(|| {
+                let action = dictionary(doc, node.get(b"A").ok()?)?;
+                (action.get(b"S").and_then(Object::as_name).ok()? == b"GoTo")
+                    .then(|| action.get(b"D").ok())?
+            })
+            .and_then(|dest| destination_page(doc, dest))
+            .filter(|page| pages.contains(page));
+
+        let Some(page) = page else {
+            items.extend(children);
+            continue;
+        };
+
+        let title = node
+            .get(b"Title")
+            .ok()
+            .and_then(|title| doc.dereference(title).ok())
+            .and_then(|(_, title)| title.as_str().ok())
+            .map(crate::decode_text_string)
+            .unwrap_or_default();
+
+        let color = node
+            .get(b"C")
+            .and_then(Object::as_array)
+            .ok()
+            .and_then(|c| {
+                c.iter()
+                    .map(|c| c.as_float().ok())
+                    .collect::<Option<Vec<_>>>()
+            })
+            .and_then(|c| c.try_into().ok())
+            .unwrap_or([0.0, 0.0, 0.0]);
+
+        items.push(OutlineItem {
+            title,
+            page,
+            color,
+            format: node.get(b"F").and_then(Object::as_i64).unwrap_or(0) as u32,
+            children,
+        });
+    }
+
+    items
+}
+
+/// The entries of `items` down to `levels` deep (1 is only the top level), in outline order.
+pub fn entries_to_level(items: &[OutlineItem], levels: usize) -> Vec<&OutlineItem> {
+    if levels == 0 {
+        return Vec::new();
+    }
+
+    items
+        .iter()
+        .flat_map(|item| std::iter::once(item).chain(entries_to_level(&item.children, levels - 1)))
+        .collect()
+}
+
+/// Find the page a destination points to. A destination is either an explicit `[page ...]`
+/// array or the name of one in the named destinations of the document.
+pub fn destination_page(doc: &Document, dest: &Object) -> Option<ObjectId> {
+    let (_, dest) = doc.dereference(dest).ok()?;
+
+    match dest {
+        Object::Array(array) => array.first()?.as_reference().ok(),
+        // Named destinations can be wrapped in a dictionary with the actual destination in /D
+        Object::Dictionary(dict) => destination_page(doc, dict.get(b"D").ok()?),
+        Object::Name(name) => {
+            let dests = dictionary(doc, doc.catalog().ok()?.get(b"Dests").ok()?)?;
+            destination_page(doc, dests.get(name).ok()?)
+        }
+        Object::String(name, _) => {
+            let names = dictionary(doc, doc.catalog().ok()?.get(b"Names").ok()?)?;
+            let tree = dictionary(doc, names.get(b"Dests").ok()?)?;
+            destination_page(doc, find_name(doc, tree, name, 0)?)
+        }
+        _ => None,
+    }
+}
+
+/// Look up `name` in a name tree.
+fn find_name<'a>(
+    doc: &'a Document,
+    node: &'a Dictionary,
+    name: &[u8],
+    depth: usize,
+) -> Option<&'a Object> {
+    if depth > 32 {
+        return None;
+    }
+
+    if let Ok(names) = node.get(b"Names").and_then(Object::as_array) {
+        for pair in names.chunks_exact(2) {
+            if pair[0].as_str().ok() == Some(name) {
+                return Some(&pair[1]);
+            }
+        }
+    }
+
+    node.get(b"Kids")
+        .and_then(Object::as_array)
+        .ok()?
+        .iter()
+        .filter_map(|kid| dictionary(doc, kid))
+        .find_map(|kid| find_name(doc, kid, name, depth + 1))
+}
+
+/// Add `items` and their children as bookmarks of `document`, under the bookmark `parent`.
+pub fn add_bookmarks(document: &mut Document, items: &[OutlineItem], parent: Option<u32>) {
+    for item in items {
+        let id = document.add_bookmark(
+            Bookmark::new(item.title.clone(), item.color, item.format, item.page),
+            parent,
+        );
+
+        add_bookmarks(document, &item.children, Some(id));
+    }
+}
@@ -0,0 +1,1446 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use anyhow::{Context, Result};
+use lopdf::content::Content;
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId, Stream};
+
+use crate::images::{self, Matrix};
+
+/// How deep references (and page tree parents) are followed, resources can contain cycles.
+const MAX_DEPTH: usize = 16;
+
+/// Parse a page selection like `1,3,5:7,-2:` against a document with `page_count` pages.
+///
+/// The selection is a comma separated list of items, each item is either:
+/// - a single page `N`, where `1` is the first page and `-1` the last one;
+/// - a range `A:B` of pages `A` up to *and including* `B`, so `-3:-1` are the last three pages.
+///   Either side can be left out, `2:` is page 2 to the end and `:3` the first three pages.
+///
+/// The returned page numbers are 1-based and in the order they were given, which can contain
+/// duplicates when items overlap.
+pub fn parse_page_spec(spec: &str, page_count: u32) -> Result<Vec<u32>> {
+    let mut pages = Vec::new();
+
+    for item in spec
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+    {
+        match item.split_once(':') {
+            Some((start, end)) => {
+                let start = match start.trim() {
+                    "" => 1,
+                    start => resolve_page(start, page_count)?,
+                };
+                let end = match end.trim() {
+                    "" => page_count,
+                    end => resolve_page(end, page_count)?,
+                };
+
+                if start > end {
+                    return Err(anyhow::anyhow!("Page range {item:?} ends before it starts"));
+                }
+
+                pages.extend(start..=end);
+            }
+            None => pages.push(resolve_page(item, page_count)?),
+        }
+    }
+
+    if pages.is_empty() {
+        return Err(anyhow::anyhow!(
+            "Page selection {spec:?} does not contain any pages"
+        ));
+    }
+
+    Ok(pages)
+}
+
+/// Parse a rotation spec like `1-2:90,3:180` against a document with `page_count` pages.
+///
+/// Every item is a page `N` or a range of pages `A-B` (including both ends, negative numbers count
+/// from the end) followed by the angle to turn them clockwise, which has to be a multiple of 90.
+/// Returns every page with its angle, pages that are mentioned more than once are listed more than
+/// once.
+pub fn parse_rotation_spec(spec: &str, page_count: u32) -> Result<Vec<(u32, i64)>> {
+    let mut rotations = Vec::new();
+
+    for item in spec
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+    {
+        let (pages, angle) = item
+            .rsplit_once(':')
+            .with_context(|| format!("Rotation {item:?} has no angle, like \"{item}:90\""))?;
+
+        let angle = angle
+            .trim()
+            .parse::<i64>()
+            .ok()
+            .filter(|angle| angle % 90 == 0)
+            .with_context(|| format!("Invalid angle in {item:?}, expected a multiple of 90"))?;
+
+        let (start, end) = match split_range(pages.trim()) {
+            Some((start, end)) => (start.trim(), end.trim()),
+            None => (pages.trim(), pages.trim()),
+        };
+        let (start, end) = (
+            resolve_page(start, page_count)?,
+            resolve_page(end, page_count)?,
+        );
+
+        if start > end {
+            return Err(anyhow::anyhow!("Page range {item:?} ends before it starts"));
+        }
+
+        rotations.extend((start..=end).map(|page| (page, angle)));
+    }
+
+    if rotations.is_empty() {
+        return Err(anyhow::anyhow!(
+            "Rotation spec {spec:?} does not contain any pages"
+        ));
+    }
+
+    Ok(rotations)
+}
+
+/// Parse a list of page ranges like `1-5,6-10,11-` against a document with `page_count` pages.
+///
+/// Every item is a page `N` or a range `A-B` including both ends, where an open end runs to the
+/// first or last page. Ranges can also be written like `A:B` of [`parse_page_spec`], which allows
+/// negative numbers. Returns the first and last page of every range, in the order they were given.
+pub fn parse_page_ranges(spec: &str, page_count: u32) -> Result<Vec<(u32, u32)>> {
+    let mut ranges = Vec::new();
+
+    for item in spec
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+    {
+        let bounds = item.split_once(':').or_else(|| split_range(item));
+        let (start, end) = match bounds {
+            Some((start, end)) => (
+                match start.trim() {
+                    "" => 1,
+                    start => resolve_page(start, page_count)?,
+                },
+                match end.trim() {
+                    "" => page_count,
+                    end => resolve_page(end, page_count)?,
+                },
+            ),
+            None => {
+                let page = resolve_page(item, page_count)?;
+                (page, page)
+            }
+        };
+
+        if start > end {
+            return Err(anyhow::anyhow!("Page range {item:?} ends before it starts"));
+        }
+
+        ranges.push((start, end));
+    }
+
+    if ranges.is_empty() {
+        return Err(anyhow::anyhow!(
+            "Page ranges {spec:?} do not contain any pages"
+        ));
+    }
+
+    Ok(ranges)
+}
+
+/// Split a range `A-B` at its `-`. A leading `-` is a negative page, not an open start, with
+/// `-3--1` the last three pages.
+fn split_range(range: &str) -> Option<(&str, &str)> {
+    range
+        .get(1..)?
+        .split_once('-')
+        .map(|(start, end)| (&range[..start.len() + 1], end))
+}
+
+/// Resolve a single, possibly negative, page index to a 1-based page number.
+fn resolve_page(index: &str, page_count: u32) -> Result<u32> {
+    let n = index
+        .parse::<i64>()
+        .with_context(|| format!("Invalid page number {index:?}"))?;
+
+    let page = match n {
+        0 => {
+            return Err(anyhow::anyhow!(
+                "Page numbers start at 1, use -1 for the last page"
+            ))
+        }
+        n if n < 0 => page_count as i64 + 1 + n,
+        n => n,
+    };
+
+    if page < 1 || page > page_count as i64 {
+        return Err(anyhow::anyhow!(
+            "Page {index} is out of range, the document has {page_count} pages"
+        ));
+    }
+
+    Ok(page as u32)
+}
+
+/// Remove every page that is not in `keep` (1-based page numbers) from `doc`, together with the
+/// objects only those pages used.
+pub fn keep_pages(doc: &mut Document, keep: &BTreeSet<u32>) {
+    let delete = (1..=doc.get_pages().len() as u32)
+        .filter(|page| !keep.contains(page))
+        .collect::<Vec<_>>();
+
+    doc.delete_pages(&delete);
+    doc.prune_objects();
+    doc.renumber_objects();
+}
+
+/// Find out what is wrong with a page whose objects are damaged, `None` means it looks fine.
+fn page_problem(doc: &Document, page_id: ObjectId) -> Option<String> {
+    let mut content = Vec::new();
+
+    for id in doc.get_page_contents(page_id) {
+        let Ok(stream) = doc.get_object(id).and_then(Object::as_stream) else {
+            return Some(format!("content stream {} {} R is missing", id.0, id.1));
+        };
+
+        let decodable = stream.filters().is_ok_and(|filters| {
+            filters
+                .iter()
+                .all(|filter| matches!(filter.as_str(), "FlateDecode" | "LZWDecode"))
+        });
+        match stream.decompressed_content() {
+            Ok(data) => content.extend(data),
+            Err(_) if decodable && stream.dict.has(b"Filter") => {
+                return Some(format!(
+                    "content stream {} {} R can't be decompressed",
+                    id.0, id.1
+                ))
+            }
+            Err(_) => content.extend(&stream.content),
+        }
+        // Streams are separated by whitespace when they are joined
+        content.push(b'\n');
+    }
+
+    if Content::decode(&content).is_err() {
+        return Some("the content can't be parsed".into());
+    }
+
+    let media_box = inherited(doc, page_id, b"MediaBox");
+    let valid = media_box
+        .as_ref()
+        .and_then(|media_box| media_box.as_array().ok())
+        .is_some_and(|media_box| {
+            media_box.len() == 4 && media_box.iter().all(|n| n.as_float().is_ok())
+        });
+    if !valid {
+        return Some("it has no valid MediaBox".into());
+    }
+
+    if let Ok(Object::Reference(id)) = doc
+        .get_dictionary(page_id)
+        .and_then(|page| page.get(b"Resources"))
+    {
+        if doc.get_dictionary(*id).is_err() {
+            return Some(format!("its resources {} {} R are missing", id.0, id.1));
+        }
+    }
+
+    None
+}
+
+/// Every page of `doc` with a damaged content stream, size or resources, by page number with
+/// what is wrong with it.
+pub fn bad_pages(doc: &Document) -> BTreeMap<u32, String> {
+    doc.get_pages()
+        .into_iter()
+        .filter_map(|(page, page_id)| Some((page, page_problem(doc, page_id)?)))
+        .collect()
+}
+
+/// Attributes a page can inherit from the page tree.
+pub const INHERITABLE: [&[u8]; 4] = [b"Resources", b"MediaBox", b"CropBox", b"Rotate"];
+
+/// Get an attribute of a page, which can be inherited from the page tree.
+pub fn inherited(doc: &Document, page_id: ObjectId, key: &[u8]) -> Option<Object> {
+    inherited_ref(doc, page_id, key).cloned()
+}
+
+/// [`inherited`] without copying the value.
+pub fn inherited_ref<'a>(doc: &'a Document, page_id: ObjectId, key: &[u8]) -> Option<&'a Object> {
+    let mut node = doc.get_dictionary(page_id).ok()?;
+
+    for _ in 0..MAX_DEPTH {
+        if let Ok(value) = node.get(key) {
+            return doc.dereference(value).ok().map(|(_, value)| value);
+        }
+        node = doc
+            .get_dictionary(node.get(b"Parent").and_then(Object::as_reference).ok()?)
+            .ok()?;
+    }
+
+    None
+}
+
+/// The rotation of a page in degrees, normalized to 0, 90, 180 or 270.
+pub fn rotation(doc: &Document, page_id: ObjectId) -> i64 {
+    inherited(doc, page_id, b"Rotate")
+        .and_then(|rotate| rotate.as_i64().ok())
+        .unwrap_or(0)
+        .rem_euclid(360)
+}
+
+/// Turn a page clockwise by `angle` degrees, which has to be a multiple of 90.
+pub fn rotate_page(doc: &mut Document, page_id: ObjectId, angle: i64) {
+    let rotate = (rotation(doc, page_id) + angle).rem_euclid(360);
+
+    if let Ok(page) = doc.get_dictionary_mut(page_id) {
+        page.set("Rotate", rotate);
+    }
+}
+
+/// Collect the ranges of a page label number tree into `ranges`, by the index of their first page.
+fn collect_label_ranges(
+    doc: &Document,
+    node: &Dictionary,
+    ranges: &mut BTreeMap<u32, Dictionary>,
+    depth: usize,
+) {
+    if depth > MAX_DEPTH {
+        return;
+    }
+
+    if let Ok(pairs) = node.get(b"Nums").and_then(Object::as_array) {
+        for pair in pairs.chunks_exact(2) {
+            let first = pair[0]
+                .as_i64()
+                .ok()
+                .and_then(|first| u32::try_from(first).ok());
+            if let (Some(first), Ok((_, Object::Dictionary(range)))) =
+                (first, doc.dereference(&pair[1]))
+            {
+                ranges.insert(first, range.clone());
+            }
+        }
+    }
+
+    for kid in node
+        .get(b"Kids")
+        .and_then(Object::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+    {
+        if let Ok((_, Object::Dictionary(kid))) = doc.dereference(kid) {
+            collect_label_ranges(doc, kid, ranges, depth + 1);
+        }
+    }
+}
+
+/// Write `number` in roman numerals, in lowercase.
+fn roman(mut number: u32) -> String {
+    const NUMERALS: [(u32, &str); 13] = [
+        (1000, "m"),
+        (900, "cm"),
+        (500, "d"),
+        (400, "cd"),
+        (100, "c"),
+        (90, "xc"),
+        (50, "l"),
+        (40, "xl"),
+        (10, "x"),
+        (9, "ix"),
+        (5, "v"),
+        (4, "iv"),
+        (1, "i"),
+    ];
+
+    let mut roman = String::new();
+    for (value, numeral) in NUMERALS {
+        while number >= value {
+            roman.push_str(numeral);
+            number -= value;
+        }
+    }
+    roman
+}
+
+/// Write `number` in letters the way page labels do, in lowercase: a to z, then aa to zz and so on.
+fn letters(number: u32) -> String {
+    let letter = (b'a' + ((number.max(1) - 1) % 26) as u8) as char;
+    letter
+        .to_string()
+        .repeat(((number.max(1) - 1) / 26 + 1) as usize)
+}
+
+/// The page label of every page of `doc` by page number, like `iv` or `A-3`, from the page labels
+/// of the catalog. Empty when the document has no page labels.
+pub fn page_labels(doc: &Document) -> BTreeMap<u32, String> {
+    let mut ranges = BTreeMap::new();
+    if let Ok((_, Object::Dictionary(tree))) = doc
+        .catalog()
+        .and_then(|catalog| catalog.get(b"PageLabels"))
+        .and_then(|tree| doc.dereference(tree))
+    {
+        collect_label_ranges(doc, tree, &mut ranges, 0);
+    }
+    if ranges.is_empty() {
+        return BTreeMap::new();
+    }
+
+    let page_count = doc.get_pages().len() as u32;
+    (1..=page_count)
+        .filter_map(|page| {
+            let index = page - 1;
+            let (first, range) = ranges.range(..=index).next_back()?;
+
+            let prefix = range
+                .get(b"P")
+                .and_then(Object::as_str)
+                .map(crate::decode_text_string)
+                .unwrap_or_default();
+            let start = range
+                .get(b"St")
+                .and_then(Object::as_i64)
+                .ok()
+                .and_then(|start| u32::try_from(start).ok())
+                .unwrap_or(1);
+            let number = start + index - first;
+
+            let number = match range.get(b"S").and_then(Object::as_name) {
+                Ok(b"D") => number.to_string(),
+                Ok(b"R") => roman(number).to_uppercase(),
+                Ok(b"r") => roman(number),
+                Ok(b"A") => letters(number).to_uppercase(),
+                Ok(b"a") => letters(number),
+                // Ranges without a style only have the prefix
+                _ => String::new(),
+            };
+            Some((page, prefix + &number))
+        })
+        .collect()
+}
+
+/// The direction most of the text of a page runs in, in degrees counterclockwise in the
+/// coordinates of the page: 0, 90, 180 or 270. Every piece of text counts with its length. Pages
+/// without text (or with content that can't be read) have no direction.
+pub fn text_direction(doc: &Document, page_id: ObjectId) -> Option<i64> {
+    let content = doc.get_and_decode_page_content(page_id).ok()?;
+
+    let text_length = |operand: &Object| match operand {
+        Object::String(text, _) => text.len(),
+        Object::Array(parts) => parts
+            .iter()
+            .filter_map(|part| part.as_str().ok())
+            .map(<[u8]>::len)
+            .sum(),
+        _ => 0,
+    };
+
+    let mut lengths = [0; 4];
+    let mut stack = Vec::new();
+    let (mut ctm, mut text_matrix) = (images::IDENTITY, images::IDENTITY);
+
+    for operation in &content.operations {
+        match operation.operator.as_str() {
+            "q" => stack.push(ctm),
+            "Q" => ctm = stack.pop().unwrap_or(ctm),
+            "cm" => {
+                if let Some(matrix) = images::to_matrix(&operation.operands) {
+                    ctm = images::multiply(&matrix, &ctm);
+                }
+            }
+            "BT" => text_matrix = images::IDENTITY,
+            // Moving to the next line only translates, which doesn't change the direction
+            "Tm" => {
+                if let Some(matrix) = images::to_matrix(&operation.operands) {
+                    text_matrix = matrix;
+                }
+            }
+            "Tj" | "TJ" | "'" | "\"" => {
+                let matrix = images::multiply(&text_matrix, &ctm);
+                let angle = matrix[1].atan2(matrix[0]).to_degrees();
+                let quarter = ((angle / 90.0).round() as i64).rem_euclid(4) as usize;
+
+                lengths[quarter] += operation.operands.iter().map(text_length).sum::<usize>();
+            }
+            _ => {}
+        }
+    }
+
+    let (quarter, length) = lengths
+        .iter()
+        .enumerate()
+        .max_by_key(|(_, length)| **length)?;
+
+    (*length > 0).then_some(quarter as i64 * 90)
+}
+
+/// Whether a page is shown wider than it is high, taking its rotation into account.
+pub fn is_landscape(doc: &Document, page_id: ObjectId) -> Option<bool> {
+    let page_box =
+        inherited(doc, page_id, b"CropBox").or_else(|| inherited(doc, page_id, b"MediaBox"))?;
+    let page_box = page_box
+        .as_array()
+        .ok()?
+        .iter()
+        .map(|n| n.as_float().ok())
+        .collect::<Option<Vec<_>>>()?;
+    let [x1, y1, x2, y2] = page_box[..] else {
+        return None;
+    };
+
+    let landscape = (x2 - x1).abs() > (y2 - y1).abs();
+    Some(landscape != (rotation(doc, page_id) % 180 == 90))
+}
+
+/// The page boxes, by the name `--box-type` takes.
+pub const PAGE_BOXES: [(&str, &[u8]); 5] = [
+    ("media", b"MediaBox"),
+    ("crop", b"CropBox"),
+    ("bleed", b"BleedBox"),
+    ("trim", b"TrimBox"),
+    ("art", b"ArtBox"),
+];
+
+fn rect(doc: &Document, page_id: ObjectId, key: &[u8]) -> Option<[f32; 4]> {
+    inherited(doc, page_id, key)?
+        .as_array()
+        .ok()?
+        .iter()
+        .map(|n| n.as_float().ok())
+        .collect::<Option<Vec<_>>>()?
+        .try_into()
+        .ok()
+}
+
+/// The box `key` of a page, like `b"TrimBox"`, as it applies: the crop box defaults to the media
+/// box and the other boxes to the crop box, and all of them are clipped to the media box. Pages
+/// with an empty box have none.
+pub fn page_box(doc: &Document, page_id: ObjectId, key: &[u8]) -> Option<[f32; 4]> {
+    let media_box = rect(doc, page_id, b"MediaBox")?;
+    let page_box = match key {
+        b"MediaBox" => return Some(media_box),
+        b"CropBox" => rect(doc, page_id, key),
+        _ => rect(doc, page_id, key).or_else(|| rect(doc, page_id, b"CropBox")),
+    };
+    let Some(page_box) = page_box else {
+        return Some(media_box);
+    };
+
+    let span = |r: &[f32; 4], i: usize| (r[i].min(r[i + 2]), r[i].max(r[i + 2]));
+    let [(x1, x2), (y1, y2)] = [0, 1].map(|i| {
+        let ((box_start, box_end), (media_start, media_end)) =
+            (span(&page_box, i), span(&media_box, i));
+        (box_start.max(media_start), box_end.min(media_end))
+    });
+
+    (x1 < x2 && y1 < y2).then_some([x1, y1, x2, y2])
+}
+
+/// Make the box `key` of every page, its crop box or for print its trim or bleed box, its media
+/// box, so the visible area stays the same in tools that ignore the other boxes. Returns how many
+/// pages were clipped.
+pub fn clip_to_box(doc: &mut Document, key: &[u8]) -> usize {
+    let mut clipped = 0;
+
+    for (_, page_id) in doc.get_pages() {
+        if key != b"MediaBox" && inherited(doc, page_id, key).is_none() {
+            continue;
+        }
+        let Some(clip) = page_box(doc, page_id, key) else {
+            continue;
+        };
+
+        if let Ok(page) = doc.get_dictionary_mut(page_id) {
+            page.set("MediaBox", clip.map(Object::Real).to_vec());
+            if key != b"MediaBox" {
+                page.remove(key);
+            }
+            clipped += 1;
+        }
+    }
+
+    clipped
+}
+
+/// Set the box `key` of a page, like `b"CropBox"`, to `rect` as far as it is inside the media box.
+/// Returns whether the page was changed, not when nothing of `rect` is on the page.
+pub fn set_page_box(doc: &mut Document, page_id: ObjectId, key: &[u8], rect: [f32; 4]) -> bool {
+    let Some(media_box) = page_box(doc, page_id, b"MediaBox") else {
+        return false;
+    };
+    let clipped = [
+        rect[0].min(rect[2]).max(media_box[0]),
+        rect[1].min(rect[3]).max(media_box[1]),
+        rect[0].max(rect[2]).min(media_box[2]),
+        rect[1].max(rect[3]).min(media_box[3]),
+    ];
+    if clipped[0] >= clipped[2] || clipped[1] >= clipped[3] {
+        return false;
+    }
+
+    match doc.get_dictionary_mut(page_id) {
+        Ok(page) => {
+            page.set(key, clipped.map(Object::Real).to_vec());
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+/// The named page sizes `--fit-to` accepts, in points.
+pub const PAGE_SIZES: [(&str, (f32, f32)); 6] = [
+    ("a3", (841.89, 1190.55)),
+    ("a4", (595.28, 841.89)),
+    ("a5", (419.53, 595.28)),
+    ("letter", (612.0, 792.0)),
+    ("legal", (612.0, 1008.0)),
+    ("tabloid", (792.0, 1224.0)),
+];
+
+/// Scale the box `key` of every page, usually the crop box, to fit a `width` by `height` points
+/// page, keeping its aspect ratio and centering it. Pages are turned like the target size, so a
+/// landscape page becomes a landscape page of that size. Returns how many pages were scaled.
+pub fn fit_pages(doc: &mut Document, key: &[u8], width: f32, height: f32) -> usize {
+    let mut fitted = 0;
+
+    for (_, page_id) in doc.get_pages() {
+        let Some(visible) = page_box(doc, page_id, key) else {
+            continue;
+        };
+        let (page_width, page_height) = (
+            (visible[2] - visible[0]).abs(),
+            (visible[3] - visible[1]).abs(),
+        );
+        if page_width == 0.0 || page_height == 0.0 {
+            continue;
+        }
+
+        // The target takes the orientation the page is shown in. The boxes are in the unrotated
+        // space of the page, where a turned page has its sides swapped
+        let turned = rotation(doc, page_id) % 180 == 90;
+        let landscape = (page_width > page_height) != turned;
+        let (long, short) = (width.max(height), width.min(height));
+        let target = if landscape != turned {
+            (long, short)
+        } else {
+            (short, long)
+        };
+
+        let scale = (target.0 / page_width).min(target.1 / page_height);
+        if transform_page(doc, page_id, visible, scale, target) {
+            fitted += 1;
+        }
+    }
+
+    if fitted > 0 {
+        doc.prune_objects();
+    }
+
+    fitted
+}
+
+/// Scale the `pages` (1-based) of `doc` by `factor`, both their content and their size. The box
+/// `key` of the pages becomes the new page, usually the crop box. Returns how many pages were
+/// scaled.
+pub fn scale_pages(doc: &mut Document, pages: &BTreeSet<u32>, key: &[u8], factor: f32) -> usize {
+    let mut scaled = 0;
+
+    for (page, page_id) in doc.get_pages() {
+        if !pages.contains(&page) {
+            continue;
+        }
+        let Some(visible) = page_box(doc, page_id, key) else {
+            continue;
+        };
+        let target = (
+            (visible[2] - visible[0]).abs() * factor,
+            (visible[3] - visible[1]).abs() * factor,
+        );
+
+        if transform_page(doc, page_id, visible, factor, target) {
+            scaled += 1;
+        }
+    }
+
+    if scaled > 0 {
+        doc.prune_objects();
+    }
+
+    scaled
+}
+
+/// Scale the `visible` area of a page by `scale` and center it on a new `target` sized media box,
+/// moving the annotations along. Returns whether the page could be changed.
+fn transform_page(
+    doc: &mut Document,
+    page_id: ObjectId,
+    visible: [f32; 4],
+    scale: f32,
+    (target_width, target_height): (f32, f32),
+) -> bool {
+    let (x, y) = (visible[0].min(visible[2]), visible[1].min(visible[3]));
+    let (page_width, page_height) = (
+        (visible[2] - visible[0]).abs(),
+        (visible[3] - visible[1]).abs(),
+    );
+    let dx = (target_width - page_width * scale) / 2.0 - x * scale;
+    let dy = (target_height - page_height * scale) / 2.0 - y * scale;
+
+    let Ok(content) = doc.get_page_content(page_id) else {
+        return false;
+    };
+    // Clip to the visible area, the rest of the old page would show on the new one
+    let content = [
+        format!("q {scale} 0 0 {scale} {dx} {dy} cm {x} {y} {page_width} {page_height} re W n\n")
+            .into_bytes(),
+        content,
+        b"\nQ".to_vec(),
+    ]
+    .concat();
+
+    let mut stream = Stream::new(dictionary! {}, content);
+    let _ = stream.compress();
+    let content_id = doc.add_object(stream);
+
+    let annots = doc
+        .get_dictionary(page_id)
+        .and_then(|page| page.get(b"Annots"))
+        .and_then(|annots| doc.dereference(annots))
+        .and_then(|(_, annots)| annots.as_array())
+        .map(|annots| {
+            annots
+                .iter()
+                .filter_map(|annot| annot.as_reference().ok())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    // Links and other annotations have to move with the content
+    for annot in annots {
+        if let Ok(annot) = doc.get_dictionary_mut(annot) {
+            let rect = annot
+                .get(b"Rect")
+                .and_then(Object::as_array)
+                .ok()
+                .and_then(|rect| {
+                    rect.iter()
+                        .map(|n| n.as_float().ok())
+                        .collect::<Option<Vec<_>>>()
+                });
+            if let Some([x1, y1, x2, y2]) = rect.as_deref() {
+                annot.set(
+                    "Rect",
+                    vec![
+                        (x1 * scale + dx).into(),
+                        (y1 * scale + dy).into(),
+                        (x2 * scale + dx).into(),
+                        (y2 * scale + dy).into(),
+                    ],
+                );
+            }
+        }
+    }
+
+    let Ok(page) = doc.get_dictionary_mut(page_id) else {
+        return false;
+    };
+    page.set("Contents", content_id);
+    page.set(
+        "MediaBox",
+        vec![
+            0.into(),
+            0.into(),
+            target_width.into(),
+            target_height.into(),
+        ],
+    );
+    for key in [&b"CropBox"[..], b"BleedBox", b"TrimBox", b"ArtBox"] {
+        page.remove(key);
+    }
+
+    true
+}
+
+/// Grow `bounds` to include the point `(x, y)` transformed by `matrix`.
+fn include(bounds: &mut Option<[f32; 4]>, matrix: &Matrix, x: f32, y: f32) {
+    let (x, y) = (
+        x * matrix[0] + y * matrix[2] + matrix[4],
+        x * matrix[1] + y * matrix[3] + matrix[5],
+    );
+
+    *bounds = Some(match *bounds {
+        Some([x1, y1, x2, y2]) => [x1.min(x), y1.min(y), x2.max(x), y2.max(y)],
+        None => [x, y, x, y],
+    });
+}
+
+/// The area of a page something is painted on, roughly: paths that are stroked or filled, text
+/// (with an estimated width, invisible text doesn't count), images and forms. `None` when
+/// nothing is painted at all.
+pub fn content_bounds(doc: &Document, page_id: ObjectId) -> Option<[f32; 4]> {
+    let content = doc.get_and_decode_page_content(page_id).ok()?;
+    let xobjects = images::page_resources(doc, page_id)
+        .and_then(|resources| resources.get(b"XObject").ok())
+        .and_then(|xobjects| doc.dereference(xobjects).ok())
+        .and_then(|(_, xobjects)| xobjects.as_dict().ok());
+
+    let mut bounds = None;
+    let mut ctm = images::IDENTITY;
+    let mut stack = Vec::new();
+    let mut path = Vec::new();
+    // Text matrix, line matrix, font size, leading and whether the text is visible
+    let (mut tm, mut tlm) = (images::IDENTITY, images::IDENTITY);
+    let (mut font_size, mut leading, mut visible) = (12.0, 0.0, true);
+
+    for operation in &content.operations {
+        let numbers = operation
+            .operands
+            .iter()
+            .filter_map(|o| o.as_float().ok())
+            .collect::<Vec<_>>();
+
+        match (operation.operator.as_str(), numbers.as_slice()) {
+            ("q", _) => stack.push(ctm),
+            ("Q", _) => ctm = stack.pop().unwrap_or(ctm),
+            ("cm", _) => {
+                if let Some(matrix) = images::to_matrix(&operation.operands) {
+                    ctm = images::multiply(&matrix, &ctm);
+                }
+            }
+
+            ("m" | "l", [x, y]) => path.push((*x, *y)),
+            ("c", [x1, y1, x2, y2, x3, y3]) => path.extend([(*x1, *y1), (*x2, *y2), (*x3, *y3)]),
+            ("v" | "y", [x1, y1, x2, y2]) => path.extend([(*x1, *y1), (*x2, *y2)]),
+            ("re", [x, y, w, h]) => {
+                path.extend([(*x, *y), (x + w, *y), (*x, y + h), (x + w, y + h)]);
+            }
+            ("S" | "s" | "f" | "F" | "f*" | "B" | "B*" | "b" | "b*", _) => {
+                for (x, y) in path.drain(..) {
+                    include(&mut bounds, &ctm, x, y);
+                }
+            }
+            ("n", _) => path.clear(),
+            // Shadings fill the whole clipping area, count them as the whole page
+            ("sh", _) => {
+                include(&mut bounds, &images::IDENTITY, f32::MIN, f32::MIN);
+                include(&mut bounds, &images::IDENTITY, f32::MAX, f32::MAX);
+            }
+
+            ("BT", _) => (tm, tlm) = (images::IDENTITY, images::IDENTITY),
+            ("Tf", [.., size]) => font_size = *size,
+            ("TL", [l]) => leading = *l,
+            ("Tr", [mode]) => visible = *mode != 3.0 && *mode != 7.0,
+            ("Tm", _) => {
+                if let Some(matrix) = images::to_matrix(&operation.operands) {
+                    (tm, tlm) = (matrix, matrix);
+                }
+            }
+            ("Td" | "TD", [x, y]) => {
+                if operation.operator == "TD" {
+                    leading = -y;
+                }
+                tlm = images::multiply(&[1.0, 0.0, 0.0, 1.0, *x, *y], &tlm);
+                tm = tlm;
+            }
+            ("T*", _) => {
+                tlm = images::multiply(&[1.0, 0.0, 0.0, 1.0, 0.0, -leading], &tlm);
+                tm = tlm;
+            }
+            ("Tj" | "TJ" | "'" | "\"", _) => {
+                if operation.operator == "'" || operation.operator == "\"" {
+                    tlm = images::multiply(&[1.0, 0.0, 0.0, 1.0, 0.0, -leading], &tlm);
+                    tm = tlm;
+                }
+
+                let characters = operation
+                    .operands
+                    .iter()
+                    .flat_map(|o| match o {
+                        Object::Array(parts) => parts.iter().collect::<Vec<_>>(),
+                        o => vec![o],
+                    })
+                    .filter_map(|o| o.as_str().ok())
+                    .map(<[u8]>::len)
+                    .sum::<usize>();
+
+                if visible && characters > 0 {
+                    // Without reading the font, assume the characters are half as wide as high
+                    let width = characters as f32 * font_size * 0.5;
+                    let matrix = images::multiply(&tm, &ctm);
+                    include(&mut bounds, &matrix, 0.0, 0.0);
+                    include(&mut bounds, &matrix, width, font_size);
+                }
+            }
+
+            // Images are drawn into the unit square, forms into their bounding box
+            ("BI" | "EI", _) => {
+                include(&mut bounds, &ctm, 0.0, 0.0);
+                include(&mut bounds, &ctm, 1.0, 1.0);
+            }
+            ("Do", _) => {
+                let Some(xobject) = operation
+                    .operands
+                    .first()
+                    .and_then(|name| name.as_name().ok())
+                    .and_then(|name| xobjects?.get(name).ok())
+                    .and_then(|xobject| doc.dereference(xobject).ok())
+                    .and_then(|(_, xobject)| xobject.as_stream().ok())
+                else {
+                    continue;
+                };
+
+                let number_array = |key: &[u8]| {
+                    xobject
+                        .dict
+                        .get(key)
+                        .and_then(Object::as_array)
+                        .ok()
+                        .and_then(|array| {
+                            array
+                                .iter()
+                                .map(|n| n.as_float().ok())
+                                .collect::<Option<Vec<_>>>()
+                        })
+                };
+
+                match xobject.dict.get(b"Subtype").and_then(Object::as_name) {
+                    Ok(b"Image") => {
+                        include(&mut bounds, &ctm, 0.0, 0.0);
+                        include(&mut bounds, &ctm, 1.0, 1.0);
+                    }
+                    Ok(b"Form") => {
+                        let matrix = number_array(b"Matrix")
+                            .and_then(|m| <[f32; 6]>::try_from(m).ok())
+                            .unwrap_or(images::IDENTITY);
+                        let matrix = images::multiply(&matrix, &ctm);
+
+                        if let Some([x1, y1, x2, y2]) = number_array(b"BBox").as_deref() {
+                            for (x, y) in [(*x1, *y1), (*x2, *y1), (*x1, *y2), (*x2, *y2)] {
+                                include(&mut bounds, &matrix, x, y);
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    bounds
+}
+
+/// The pages of `doc` where less than `threshold` (between 0 and 1) of the visible area has
+/// content on it, by page number with the part that has content.
+pub fn empty_pages(doc: &Document, threshold: f32) -> BTreeMap<u32, f32> {
+    let mut empty = BTreeMap::new();
+
+    for (page, page_id) in doc.get_pages() {
+        let Some(visible) = inherited(doc, page_id, b"CropBox")
+            .or_else(|| inherited(doc, page_id, b"MediaBox"))
+            .and_then(|page_box| {
+                page_box
+                    .as_array()
+                    .ok()?
+                    .iter()
+                    .map(|n| n.as_float().ok())
+                    .collect::<Option<Vec<_>>>()
+            })
+            .filter(|page_box| page_box.len() == 4)
+        else {
+            continue;
+        };
+
+        let (x1, y1) = (visible[0].min(visible[2]), visible[1].min(visible[3]));
+        let (x2, y2) = (visible[0].max(visible[2]), visible[1].max(visible[3]));
+        let area = (x2 - x1) * (y2 - y1);
+        if area <= 0.0 {
+            continue;
+        }
+
+        // Only the content inside the visible area counts
+        let covered = content_bounds(doc, page_id).map_or(0.0, |[bx1, by1, bx2, by2]| {
+            let width = (bx2.min(x2) - bx1.max(x1)).max(0.0);
+            let height = (by2.min(y2) - by1.max(y1)).max(0.0);
+            width * height / area
+        });
+
+        if covered < threshold {
+            empty.insert(page, covered);
+        }
+    }
+
+    empty
+}
+
+/// An empty page, A4 unless a `media_box` is given. The parent still has to be set.
+pub fn blank_page(media_box: Option<Object>) -> Object {
+    let media_box =
+        media_box.unwrap_or_else(|| vec![0.into(), 0.into(), 595.into(), 842.into()].into());
+
+    Object::Dictionary(dictionary! {
+        "Type" => "Page",
+        "MediaBox" => media_box,
+        "Resources" => dictionary! {},
+    })
+}
+
+/// Remove the embedded thumbnail images of all pages. Returns the number of thumbnails and the
+/// number of objects that were removed with them.
+pub fn strip_thumbnails(doc: &mut Document) -> (usize, usize) {
+    let mut thumbnails = 0;
+
+    for (_, page_id) in doc.get_pages() {
+        if let Ok(page) = doc.get_dictionary_mut(page_id) {
+            if page.remove(b"Thumb").is_some() {
+                thumbnails += 1;
+            }
+        }
+    }
+
+    if thumbnails == 0 {
+        return (0, 0);
+    }
+
+    (thumbnails, doc.prune_objects().len())
+}
+
+/// Whether a link annotation leads somewhere inside the document, instead of to a URL, another
+/// file or an application.
+fn is_internal_link(doc: &Document, link: &Dictionary) -> bool {
+    if link.has(b"Dest") {
+        return true;
+    }
+
+    let action = link
+        .get(b"A")
+        .and_then(|action| doc.dereference(action))
+        .and_then(|(_, action)| action.as_dict());
+    match action
+        .and_then(|action| action.get(b"S"))
+        .and_then(Object::as_name)
+    {
+        Ok(b"GoTo" | b"Named") => true,
+        Ok(_) => false,
+        // A link without a destination or action doesn't go anywhere
+        Err(_) => true,
+    }
+}
+
+/// Remove the link annotations of all pages, or with `keep_internal` only those leading out of
+/// the document. Returns the number of links that were removed.
+pub fn remove_links(doc: &mut Document, keep_internal: bool) -> usize {
+    let mut removed = 0;
+
+    for (_, page_id) in doc.get_pages() {
+        let Ok(annots) = doc
+            .get_dictionary(page_id)
+            .and_then(|page| page.get(b"Annots"))
+            .and_then(|annots| doc.dereference(annots))
+            .and_then(|(_, annots)| annots.as_array())
+        else {
+            continue;
+        };
+
+        let kept = annots
+            .iter()
+            .filter(|annot| {
+                let Ok((_, Object::Dictionary(annot))) = doc.dereference(annot) else {
+                    return true;
+                };
+                let is_link = annot.get(b"Subtype").and_then(Object::as_name).ok() == Some(b"Link");
+                !is_link || (keep_internal && is_internal_link(doc, annot))
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+
+        if kept.len() == annots.len() {
+            continue;
+        }
+        removed += annots.len() - kept.len();
+
+        // Set the filtered array on the page itself, the original might be shared
+        if let Ok(page) = doc.get_dictionary_mut(page_id) {
+            if kept.is_empty() {
+                page.remove(b"Annots");
+            } else {
+                page.set("Annots", kept);
+            }
+        }
+    }
+
+    if removed > 0 {
+        doc.prune_objects();
+    }
+
+    removed
+}
+
+/// Feed a canonical form of `object` into `hash`, following references so identical objects
+/// stored under different ids hash the same.
+fn hash_object(doc: &Document, object: &Object, hash: &mut md5::Context, depth: usize) {
+    if depth > MAX_DEPTH {
+        return;
+    }
+
+    match object {
+        Object::Reference(id) => match doc.get_object(*id) {
+            Ok(object) => hash_object(doc, object, hash, depth + 1),
+            Err(_) => hash.consume(b"dangling"),
+        },
+        Object::Array(array) => {
+            hash.consume(format!("[{}", array.len()));
+            for item in array {
+                hash_object(doc, item, hash, depth);
+            }
+        }
+        Object::Dictionary(dict) => {
+            let mut entries = dict.iter().collect::<Vec<_>>();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+
+            hash.consume(format!("<<{}", entries.len()));
+            for (key, value) in entries {
+                hash.consume(key);
+                hash_object(doc, value, hash, depth);
+            }
+        }
+        Object::Stream(stream) => {
+            let mut dict = stream.dict.clone();
+            for key in [&b"Length"[..], b"Filter", b"DecodeParms"] {
+                dict.remove(key);
+            }
+            hash_object(doc, &Object::Dictionary(dict), hash, depth);
+
+            let content = stream
+                .decompressed_content()
+                .unwrap_or_else(|_| stream.content.clone());
+            hash.consume(format!("stream{}", content.len()));
+            hash.consume(content);
+        }
+        Object::String(bytes, _) => {
+            hash.consume(format!("({}", bytes.len()));
+            hash.consume(bytes);
+        }
+        Object::Name(name) => {
+            hash.consume(format!("/{}", name.len()));
+            hash.consume(name);
+        }
+        object => hash.consume(format!("{object:?}")),
+    }
+}
+
+/// Hash what a page looks like: its content, the resources it draws with, its size and rotation.
+/// Attributes inherited from the page tree count the same as ones set on the page.
+fn hash_page(doc: &Document, page_id: ObjectId) -> Option<[u8; 16]> {
+    let mut hash = md5::Context::new();
+
+    // Re-encoding the content makes differences in whitespace and number formatting disappear
+    let content = doc.get_page_content(page_id).ok()?;
+    let content = Content::decode(&content)
+        .and_then(|content| content.encode())
+        .unwrap_or(content);
+    hash.consume(content);
+
+    for key in INHERITABLE {
+        hash.consume(key);
+        if let Some(value) = inherited(doc, page_id, key) {
+            hash_object(doc, &value, &mut hash, 0);
+        }
+    }
+
+    Some(hash.compute().0)
+}
+
+/// Hash the annotations of a page for [`remove_duplicate_pages`], the pages that look the same but
+/// link somewhere else or have other form fields or comments are not duplicates. References are
+/// hashed as they are, links to the same page of the document are the same link.
+fn hash_annotations(doc: &Document, page_id: ObjectId, hash: &mut md5::Context) {
+    let annotations = doc
+        .get_dictionary(page_id)
+        .and_then(|page| page.get(b"Annots"))
+        .and_then(|annotations| doc.dereference(annotations))
+        .and_then(|(_, annotations)| annotations.as_array())
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+
+    hash.consume(format!("annots{}", annotations.len()));
+    for annotation in annotations {
+        let Ok((_, Object::Dictionary(annotation))) = doc.dereference(annotation) else {
+            hash.consume(b"unreadable");
+            continue;
+        };
+
+        for key in [
+            &b"Subtype"[..],
+            b"Rect",
+            b"QuadPoints",
+            b"Contents",
+            b"A",
+            b"Dest",
+            b"Parent",
+            b"T",
+            b"FT",
+            b"V",
+        ] {
+            hash.consume(key);
+            if let Ok(value) = annotation.get(key) {
+                // The parent is the field of a widget, its own object even when it looks the same
+                let value = match key {
+                    b"Parent" => value,
+                    _ => doc.dereference(value).map_or(value, |(_, value)| value),
+                };
+                hash.consume(format!("{value:?}"));
+            }
+        }
+    }
+}
+
+/// A fingerprint of what the pages of `doc` look like, as a hex string. Two PDFs with the same
+/// fingerprint show the same pages in the same order.
+///
+/// Included are, for every page: its content streams after decompressing and re-encoding them,
+/// the resources (fonts, images, forms, ...) with the content of their streams decompressed, the
+/// MediaBox, CropBox and Rotate. Left out are the document information, XMP metadata, bookmarks,
+/// annotations and form fields, object numbers, compression and the file structure.
+pub fn content_hash(doc: &Document) -> String {
+    let pages = doc.get_pages();
+    let mut hash = md5::Context::new();
+
+    hash.consume(format!("pages{}", pages.len()));
+    for page_id in pages.into_values() {
+        match hash_page(doc, page_id) {
+            Some(page) => hash.consume(page),
+            None => hash.consume(b"unreadable"),
+        }
+    }
+
+    format!("{:x}", hash.compute())
+}
+
+/// Remove pages that look exactly like an earlier page of `doc` and have the same annotations.
+/// Links and bookmarks to a removed page are pointed to the page it duplicated. Returns the original numbers of the removed pages.
+pub fn remove_duplicate_pages(doc: &mut Document) -> Vec<u32> {
+    let mut seen = HashMap::new();
+    let mut duplicates = BTreeMap::new();
+    let mut removed = Vec::new();
+
+    for (number, page_id) in doc.get_pages() {
+        let Some(page) = hash_page(doc, page_id) else {
+            continue;
+        };
+        let mut hash = md5::Context::new();
+        hash.consume(page);
+        hash_annotations(doc, page_id, &mut hash);
+        let hash = hash.compute().0;
+
+        match seen.get(&hash) {
+            Some(original) => {
+                duplicates.insert(page_id, *original);
+                removed.push(number);
+            }
+            None => {
+                seen.insert(hash, page_id);
+            }
+        }
+    }
+
+    for duplicate in duplicates.keys() {
+        let Some(page) = doc.objects.remove(duplicate) else {
+            continue;
+        };
+
+        // Take the page out of the page tree, updating the counts of all its ancestors
+        let mut parent = page
+            .as_dict()
+            .and_then(|page| page.get(b"Parent"))
+            .and_then(Object::as_reference)
+            .ok();
+        let mut first = true;
+
+        while let Some(Ok(node)) = parent.map(|id| doc.get_dictionary_mut(id)) {
+            if first {
+                if let Ok(kids) = node.get_mut(b"Kids").and_then(Object::as_array_mut) {
+                    kids.retain(|kid| kid.as_reference().ok() != Some(*duplicate));
+                }
+                first = false;
+            }
+            if let Ok(count) = node.get(b"Count").and_then(Object::as_i64) {
+                node.set("Count", count - 1);
+            }
+            parent = node.get(b"Parent").and_then(Object::as_reference).ok();
+        }
+    }
+
+    doc.traverse_objects(|object| {
+        if let Object::Reference(id) = object {
+            if let Some(original) = duplicates.get(id) {
+                *id = *original;
+            }
+        }
+    });
+
+    for bookmark in doc.bookmark_table.values_mut() {
+        if let Some(original) = duplicates.get(&bookmark.page) {
+            bookmark.page = *original;
+        }
+    }
+
+    doc.prune_objects();
+
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A document with a page for every entry of `links`, all showing the same text, with a link
+    /// to the given URI when there is one.
+    fn linked_pages(links: &[Option<&str>]) -> Document {
+        let mut doc = Document::with_version("1.5");
+        let pages_id = doc.new_object_id();
+        let content = b"BT /F1 12 Tf 72 700 Td (same) Tj ET".to_vec();
+
+        let kids = links
+            .iter()
+            .map(|link| {
+                let content_id = doc.add_object(Stream::new(dictionary! {}, content.clone()));
+                let mut page = dictionary! {
+                    "Type" => "Page",
+                    "Parent" => pages_id,
+                    "MediaBox" => vec![0.into(), 0.into(), 612.into(), 792.into()],
+                    "Contents" => content_id,
+                };
+                if let Some(uri) = link {
+                    let link_id = doc.add_object(dictionary! {
+                        "Type" => "Annot",
+                        "Subtype" => "Link",
+                        "Rect" => vec![72.into(), 690.into(), 172.into(), 710.into()],
+                        "A" => dictionary! { "S" => "URI", "URI" => Object::string_literal(*uri) },
+                    });
+                    page.set("Annots", vec![link_id.into()]);
+                }
+                doc.add_object(page).into()
+            })
+            .collect::<Vec<Object>>();
+        doc.objects.insert(
+            pages_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Count" => kids.len() as i64,
+                "Kids" => kids,
+            }),
+        );
+        let catalog_id = doc.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+        doc.trailer.set("Root", catalog_id);
+
+        doc
+    }
+
+    #[test]
+    fn page_spec_selects_pages_in_order() {
+        assert_eq!(parse_page_spec("1,3,5:7", 10).unwrap(), [1, 3, 5, 6, 7]);
+        assert_eq!(parse_page_spec("-1", 10).unwrap(), [10]);
+        assert_eq!(parse_page_spec("-3:", 10).unwrap(), [8, 9, 10]);
+        assert_eq!(parse_page_spec(":2, 9:", 10).unwrap(), [1, 2, 9, 10]);
+        assert_eq!(parse_page_spec("2:-8", 10).unwrap(), [2, 3]);
+        assert_eq!(parse_page_spec("3,1:2,3", 10).unwrap(), [3, 1, 2, 3]);
+        assert_eq!(parse_page_spec(":", 3).unwrap(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn page_spec_rejects_invalid_pages() {
+        for spec in [
+            "5:3", "-1:-3", "0", "0:2", "11", "-11", "1:11", "a", "1-3", "", " , ",
+        ] {
+            assert!(
+                parse_page_spec(spec, 10).is_err(),
+                "{spec:?} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn rotation_spec_lists_every_page_with_its_angle() {
+        assert_eq!(
+            parse_rotation_spec("1-2:90,3:180", 5).unwrap(),
+            [(1, 90), (2, 90), (3, 180)]
+        );
+        assert_eq!(
+            parse_rotation_spec("-1:-90, 2 - 3 : 270", 5).unwrap(),
+            [(5, -90), (2, 270), (3, 270)]
+        );
+        assert_eq!(
+            parse_rotation_spec("1:0,1:360", 5).unwrap(),
+            [(1, 0), (1, 360)]
+        );
+        // Overlapping items list a page more than once, the angles add up
+        assert_eq!(
+            parse_rotation_spec("1-3:90,2:90", 5).unwrap(),
+            [(1, 90), (2, 90), (3, 90), (2, 90)]
+        );
+    }
+
+    #[test]
+    fn rotation_spec_rejects_invalid_angles_and_pages() {
+        for spec in [
+            "1:45", "1:-45", "1:90.0", "1:", "1", "1:a", "3-1:90", "0:90", "6:90", "", ",",
+        ] {
+            assert!(
+                parse_rotation_spec(spec, 5).is_err(),
+                "{spec:?} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn page_ranges_keep_their_order_and_overlaps() {
+        assert_eq!(
+            parse_page_ranges("1-5,6-10,11-", 12).unwrap(),
+            [(1, 5), (6, 10), (11, 12)]
+        );
+        assert_eq!(
+            parse_page_ranges("7-9, 1-3, 2-8", 10).unwrap(),
+            [(7, 9), (1, 3), (2, 8)]
+        );
+        assert_eq!(parse_page_ranges("4", 10).unwrap(), [(4, 4)]);
+        assert_eq!(parse_page_ranges("-2", 10).unwrap(), [(9, 9)]);
+        assert_eq!(
+            parse_page_ranges("-3--1,:2", 10).unwrap(),
+            [(8, 10), (1, 2)]
+        );
+        assert_eq!(
+            parse_page_ranges("5:,-10:2", 10).unwrap(),
+            [(5, 10), (1, 2)]
+        );
+    }
+
+    #[test]
+    fn page_ranges_reject_invalid_ranges() {
+        for spec in ["5-3", "0-2", "1-11", "11-", "12", "-11", "1-a", "", ","] {
+            assert!(
+                parse_page_ranges(spec, 10).is_err(),
+                "{spec:?} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn duplicate_pages_need_the_same_links() {
+        let mut doc = linked_pages(&[
+            Some("https://example.com/a"),
+            Some("https://example.com/b"),
+            Some("https://example.com/a"),
+            None,
+        ]);
+
+        assert_eq!(remove_duplicate_pages(&mut doc), vec![3]);
+        assert_eq!(doc.get_pages().len(), 3);
+    }
+}
@@ -0,0 +1,161 @@
+//! PDF portfolios, which keep every file separate as an attachment of one container PDF.
+//!
+//! Viewers that support portfolios show the list of files instead of the pages of the container,
+//! other viewers show its cover page, which lists the files and where to find them.
+
+use lopdf::content::{Content, Operation};
+use lopdf::{dictionary, Document, Object, Stream, StringFormat};
+
+use crate::names;
+
+/// Build a portfolio holding all `files`, which are `(file name, content)` pairs. The files are
+/// kept in the given order.
+pub fn portfolio(files: &[(String, Vec<u8>)]) -> lopdf::Result<Document> {
+    let mut doc = Document::with_version("1.7");
+    let pages_id = doc.new_object_id();
+
+    let mut embedded = Vec::new();
+    for (order, (name, content)) in files.iter().enumerate() {
+        let mut stream = Stream::new(
+            dictionary! {
+                "Type" => "EmbeddedFile",
+                "Subtype" => "application/pdf",
+                "Params" => dictionary! { "Size" => content.len() as i64 },
+            },
+            content.clone(),
+        );
+        let _ = stream.compress();
+        let stream_id = doc.add_object(stream);
+
+        let file_spec = doc.add_object(dictionary! {
+            "Type" => "Filespec",
+            "F" => Object::string_literal(name.as_str()),
+            "UF" => crate::encode_text_string(name),
+            "EF" => dictionary! { "F" => stream_id },
+            "CI" => dictionary! { "Order" => order as i64 },
+        });
+
+        // The keys of the name tree are sorted, a number in front keeps the given order
+        embedded.push((
+            format!("{order:04} {name}").into_bytes(),
+            Object::Reference(file_spec),
+        ));
+    }
+
+    let first = embedded
+        .first()
+        .map(|(key, _)| Object::String(key.clone(), StringFormat::Literal));
+    let mut collection = dictionary! {
+        "Type" => "Collection",
+        // Details view, a list of the files
+        "View" => "D",
+        "Schema" => dictionary! {
+            "Type" => "CollectionSchema",
+            "Order" => dictionary! {
+                "Type" => "CollectionField",
+                "Subtype" => "N",
+                "N" => Object::string_literal("Order"),
+                "O" => 0,
+                "V" => false,
+            },
+            "Name" => dictionary! {
+                "Type" => "CollectionField",
+                "Subtype" => "F",
+                "N" => Object::string_literal("Name"),
+                "O" => 1,
+            },
+        },
+        "Sort" => dictionary! { "S" => "Order" },
+    };
+    if let Some(first) = first {
+        collection.set("D", first);
+    }
+
+    let page_id = cover_page(&mut doc, pages_id, files)?;
+    doc.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => vec![page_id.into()],
+            "Count" => 1,
+        }),
+    );
+
+    let catalog_id = doc.add_object(dictionary! {
+        "Type" => "Catalog",
+        "Pages" => pages_id,
+        "Names" => dictionary! { "EmbeddedFiles" => names::name_tree(embedded) },
+        "Collection" => collection,
+        "PageMode" => "UseAttachments",
+    });
+    doc.trailer.set("Root", catalog_id);
+
+    Ok(doc)
+}
+
+/// The page shown by viewers without portfolio support, listing the files.
+fn cover_page(
+    doc: &mut Document,
+    parent: lopdf::ObjectId,
+    files: &[(String, Vec<u8>)],
+) -> lopdf::Result<lopdf::ObjectId> {
+    // A4, with the rows shrinking when there are many files
+    let (width, height, margin) = (595.0, 842.0, 72.0);
+    let row_height = ((height - margin * 3.0) / files.len().max(1) as f32).min(16.0);
+
+    let font_id = doc.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type1",
+        "BaseFont" => "Helvetica",
+        "Encoding" => "WinAnsiEncoding",
+    });
+
+    let lines = [
+        (
+            14.0,
+            format!("This PDF is a portfolio of {} files:", files.len()),
+        ),
+        (
+            10.0,
+            "Open the attachments panel of your viewer to see them.".into(),
+        ),
+    ]
+    .into_iter()
+    .chain(
+        files
+            .iter()
+            .map(|(name, _)| (row_height * 2.0 / 3.0, format!("  {name}"))),
+    );
+
+    let mut operations = Vec::new();
+    let mut y = height - margin;
+    for (size, line) in lines {
+        operations.extend([
+            Operation::new("BT", vec![]),
+            Operation::new("Tf", vec!["F1".into(), size.into()]),
+            Operation::new("Td", vec![margin.into(), y.into()]),
+            Operation::new(
+                "Tj",
+                vec![Object::string_literal(Document::encode_text(
+                    Some("WinAnsiEncoding"),
+                    &line,
+                ))],
+            ),
+            Operation::new("ET", vec![]),
+        ]);
+        y -= size * 1.5;
+    }
+
+    let content_id = doc.add_object(Stream::new(
+        dictionary! {},
+        Content { operations }.encode()?,
+    ));
+
+    Ok(doc.add_object(dictionary! {
+        "Type" => "Page",
+        "Parent" => parent,
+        "MediaBox" => vec![0.into(), 0.into(), width.into(), height.into()],
+        "Contents" => content_id,
+        "Resources" => dictionary! { "Font" => dictionary! { "F1" => font_id } },
+    }))
+}
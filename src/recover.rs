@@ -0,0 +1,146 @@
+//! Recovering PDFs with a damaged or missing cross-reference table, like files that were cut off
+//! or edited by hand. The objects are found by scanning the file for `N G obj`, the way readers
+//! repair files, and a new table is appended for them.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use regex::bytes::Regex;
+
+/// The largest object number PDF readers are required to support, the limit of the spec.
+const MAX_OBJECT_NUMBER: u32 = 8_388_607;
+
+/// The offset of every object in `bytes` by object number, with its generation. Objects that are
+/// in the file more than once, from incremental updates, are the last copy. Numbers no real
+/// object can have are skipped, the table has a line for every number up to the largest.
+fn scan_objects(bytes: &[u8]) -> BTreeMap<u32, (u16, usize)> {
+    let object =
+        Regex::new(r"(?-u)(\d{1,10})[\x00\t\n\x0c\r ]+(\d{1,5})[\x00\t\n\x0c\r ]+obj\b").unwrap();
+    let end = Regex::new(r"(?-u)\bendobj\b").unwrap();
+
+    let mut objects = BTreeMap::new();
+    let mut start = 0;
+    while let Some(found) = object.captures_at(bytes, start) {
+        let whole = found.get(0).unwrap();
+        let number = std::str::from_utf8(&found[1])
+            .ok()
+            .and_then(|n| n.parse::<u32>().ok())
+            .filter(|n| *n <= MAX_OBJECT_NUMBER && (*n as usize) <= bytes.len());
+        let generation = std::str::from_utf8(&found[2])
+            .ok()
+            .and_then(|g| g.parse().ok());
+        if let (Some(number), Some(generation)) = (number, generation) {
+            objects.insert(number, (generation, whole.start()));
+        }
+
+        // Skip the body, streams can contain anything that looks like an object
+        match end.find_at(bytes, whole.end()) {
+            Some(end) => start = end.end(),
+            None => break,
+        }
+    }
+
+    objects
+}
+
+/// The last reference to an object stored under `key` in `bytes`, like the `/Root` of the
+/// trailer.
+fn last_reference(bytes: &[u8], key: &str) -> Option<(u32, u16)> {
+    let reference = Regex::new(&format!(
+        r"/{key}[\x00\t\n\x0c\r ]*(\d+)[\x00\t\n\x0c\r ]+(\d+)[\x00\t\n\x0c\r ]+R"
+    ))
+    .unwrap();
+    let found = reference.captures_iter(bytes).last()?;
+
+    Some((
+        std::str::from_utf8(&found[1]).ok()?.parse().ok()?,
+        std::str::from_utf8(&found[2]).ok()?.parse().ok()?,
+    ))
+}
+
+/// `bytes` with a new cross-reference table and trailer for the objects that are in it, which lopdf
+/// can load when the original table is broken. Nothing when no catalog can be found.
+pub fn rebuild_xref(bytes: &[u8]) -> Option<Vec<u8>> {
+    let objects = scan_objects(bytes);
+
+    // The trailer can be lost with the table, then the catalog is the object that says it is one
+    let catalog = Regex::new(r"(?-u)/Type[\x00\t\n\x0c\r ]*/Catalog\b").unwrap();
+    let root = last_reference(bytes, "Root")
+        .filter(|(number, _)| objects.contains_key(number))
+        .or_else(|| {
+            // In file order, object numbers don't have to be, so a body ends at the next offset
+            let mut offsets = objects
+                .values()
+                .map(|(_, offset)| *offset)
+                .collect::<Vec<_>>();
+            offsets.sort_unstable();
+            objects
+                .iter()
+                .rev()
+                .find_map(|(number, (generation, offset))| {
+                    let next = offsets.get(offsets.partition_point(|o| o <= offset));
+                    let body = &bytes[*offset..next.copied().unwrap_or(bytes.len())];
+                    catalog.is_match(body).then_some((*number, *generation))
+                })
+        })?;
+    let info = last_reference(bytes, "Info").filter(|(number, _)| objects.contains_key(number));
+
+    let size = objects
+        .keys()
+        .max()
+        .map_or(Some(1), |max| max.checked_add(1))?;
+    let mut rebuilt = bytes.to_vec();
+    let xref_start = rebuilt.len() + 1;
+
+    let _ = write!(rebuilt, "\nxref\n0 {size}\n");
+    for number in 0..size {
+        let _ = match objects.get(&number) {
+            Some((generation, offset)) => writeln!(rebuilt, "{offset:010} {generation:05} n "),
+            None if number == 0 => writeln!(rebuilt, "0000000000 65535 f "),
+            None => writeln!(rebuilt, "0000000000 00000 f "),
+        };
+    }
+    let _ = write!(
+        rebuilt,
+        "trailer\n<< /Size {size} /Root {} {} R{} >>\nstartxref\n{xref_start}\n%%EOF\n",
+        root.0,
+        root.1,
+        info.map(|(number, generation)| format!(" /Info {number} {generation} R"))
+            .unwrap_or_default()
+    );
+
+    Some(rebuilt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn catalog_is_found_when_objects_are_out_of_order() {
+        // The page comes first and the pages last, so the next object by number after the page
+        // is past the catalog in the file
+        let bytes = b"%PDF-1.4\n\
+            3 0 obj << /Type /Page /Parent 1 0 R /MediaBox [0 0 612 792] >> endobj\n\
+            2 0 obj << /Type /Catalog /Pages 1 0 R >> endobj\n\
+            1 0 obj << /Type /Pages /Kids [3 0 R] /Count 1 >> endobj\n";
+
+        let rebuilt = rebuild_xref(bytes).unwrap();
+        let trailer = String::from_utf8_lossy(&rebuilt[bytes.len()..]).into_owned();
+        assert!(trailer.contains("/Root 2 0 R"), "{trailer}");
+    }
+
+    #[test]
+    fn implausible_object_numbers_are_skipped() {
+        let bytes = b"%PDF-1.4\n\
+            1 0 obj << /Type /Catalog /Pages 2 0 R >> endobj\n\
+            2 0 obj << /Type /Pages /Kids [] /Count 0 >> endobj\n\
+            4294967295 0 obj << >> endobj\n\
+            8388608 0 obj << >> endobj\n";
+
+        let rebuilt = rebuild_xref(bytes).unwrap();
+        let trailer = String::from_utf8_lossy(&rebuilt[bytes.len()..]).into_owned();
+        assert!(trailer.contains("xref\n0 3\n"), "{trailer}");
+        assert!(trailer.contains("/Size 3 "), "{trailer}");
+    }
+}
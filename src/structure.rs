@@ -0,0 +1,189 @@
+//! Structure trees of tagged PDFs, which screen readers use to read a document in order.
+//!
+//! Content is tied to the tree by numbers: pages have a `/StructParents` key and annotations and
+//! XObjects a `/StructParent` key into the parent tree of the document. Those numbers are only
+//! unique within one PDF, so when merging the numbers of every input are moved behind the ones of
+//! the inputs before it.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId};
+
+/// Number trees are nested through references, don't follow broken ones forever.
+const MAX_DEPTH: usize = 32;
+
+/// The structure trees of the merged inputs, combined under a single root.
+#[derive(Debug, Default)]
+pub struct StructureTree {
+    kids: Vec<Object>,
+    parent_tree: Vec<(i64, Object)>,
+    role_map: Dictionary,
+    class_map: Dictionary,
+    next_key: i64,
+    /// The roots and parent trees of the inputs, which the combined root replaces
+    replaced: Vec<ObjectId>,
+}
+
+/// Collect the leaves of a number tree into `numbers`.
+fn collect_tree(
+    doc: &Document,
+    node: &Dictionary,
+    numbers: &mut Vec<(i64, Object)>,
+    visited: &mut BTreeSet<ObjectId>,
+    depth: usize,
+) {
+    if depth > MAX_DEPTH {
+        return;
+    }
+
+    if let Ok(pairs) = node.get(b"Nums").and_then(Object::as_array) {
+        for pair in pairs.chunks_exact(2) {
+            if let Ok(key) = pair[0].as_i64() {
+                numbers.push((key, pair[1].clone()));
+            }
+        }
+    }
+
+    for kid in node
+        .get(b"Kids")
+        .and_then(Object::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+    {
+        if let Ok(id) = kid.as_reference() {
+            if !visited.insert(id) {
+                continue;
+            }
+        }
+        if let Ok((_, Object::Dictionary(kid))) = doc.dereference(kid) {
+            collect_tree(doc, kid, numbers, visited, depth + 1);
+        }
+    }
+}
+
+fn dictionary(doc: &Document, dict: &Dictionary, key: &[u8]) -> Option<Dictionary> {
+    let (_, object) = doc.dereference(dict.get(key).ok()?).ok()?;
+    object.as_dict().ok().cloned()
+}
+
+impl StructureTree {
+    /// Add the structure tree of `doc`, which has to be renumbered into its own range of ids of
+    /// the merge already. Its parent tree numbers are moved behind the ones added before.
+    pub fn add(&mut self, doc: &mut Document) {
+        let Ok(catalog) = doc.catalog() else {
+            return;
+        };
+        let Some(root) = dictionary(doc, catalog, b"StructTreeRoot") else {
+            return;
+        };
+        if let Ok(id) = catalog
+            .get(b"StructTreeRoot")
+            .and_then(Object::as_reference)
+        {
+            self.replaced.push(id);
+        }
+        if let Ok(id) = root.get(b"ParentTree").and_then(Object::as_reference) {
+            self.replaced.push(id);
+        }
+
+        let offset = self.next_key;
+        let mut next_key = root
+            .get(b"ParentTreeNextKey")
+            .and_then(Object::as_i64)
+            .unwrap_or(0);
+
+        if let Some(parent_tree) = dictionary(doc, &root, b"ParentTree") {
+            let mut numbers = Vec::new();
+            collect_tree(doc, &parent_tree, &mut numbers, &mut BTreeSet::new(), 0);
+
+            for (key, value) in numbers {
+                next_key = next_key.max(key + 1);
+                self.parent_tree.push((key + offset, value));
+            }
+        }
+
+        for (name, map) in [
+            (&b"RoleMap"[..], &mut self.role_map),
+            (b"ClassMap", &mut self.class_map),
+        ] {
+            // The first input to define a role or class wins
+            for (key, value) in dictionary(doc, &root, name).unwrap_or_default().iter() {
+                if !map.has(key) {
+                    map.set(key.clone(), value.clone());
+                }
+            }
+        }
+
+        match root.get(b"K") {
+            Ok(Object::Array(kids)) => self.kids.extend(kids.iter().cloned()),
+            Ok(kid) => self.kids.push(kid.clone()),
+            Err(_) => {}
+        }
+
+        for object in doc.objects.values_mut() {
+            let dict = match object {
+                Object::Dictionary(dict) => dict,
+                Object::Stream(stream) => &mut stream.dict,
+                _ => continue,
+            };
+
+            for key in [&b"StructParents"[..], b"StructParent"] {
+                if let Ok(number) = dict.get(key).and_then(Object::as_i64) {
+                    next_key = next_key.max(number + 1);
+                    dict.set(key, number + offset);
+                }
+            }
+        }
+
+        self.next_key = offset + next_key;
+    }
+
+    /// Store the combined structure tree root in `objects` under `id`, in place of the roots of
+    /// the inputs. Returns `false` when none of the inputs was tagged.
+    pub fn build(mut self, objects: &mut BTreeMap<ObjectId, Object>, id: ObjectId) -> bool {
+        if self.kids.is_empty() && self.replaced.is_empty() {
+            return false;
+        }
+
+        for id in &self.replaced {
+            objects.remove(id);
+        }
+
+        // The top level elements now have the combined root as their parent
+        for kid in self.kids.iter_mut() {
+            let kid = match kid {
+                Object::Reference(kid_id) => match objects.get_mut(kid_id) {
+                    Some(Object::Dictionary(kid)) => kid,
+                    _ => continue,
+                },
+                Object::Dictionary(kid) => kid,
+                _ => continue,
+            };
+            kid.set("P", id);
+        }
+
+        self.parent_tree.sort_by_key(|(key, _)| *key);
+        let numbers = self
+            .parent_tree
+            .into_iter()
+            .flat_map(|(key, value)| [Object::Integer(key), value])
+            .collect::<Vec<_>>();
+
+        let mut root = dictionary! {
+            "Type" => "StructTreeRoot",
+            "K" => self.kids,
+            "ParentTree" => dictionary! { "Nums" => numbers },
+            "ParentTreeNextKey" => self.next_key,
+        };
+        if !self.role_map.is_empty() {
+            root.set("RoleMap", self.role_map);
+        }
+        if !self.class_map.is_empty() {
+            root.set("ClassMap", self.class_map);
+        }
+
+        objects.insert(id, Object::Dictionary(root));
+
+        true
+    }
+}
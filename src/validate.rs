@@ -0,0 +1,208 @@
+//! Structural checks for the most common reasons a PDF is not PDF/A.
+//!
+//! This is not a full PDF/A validator, it only catches the usual disqualifiers: missing fonts,
+//! encryption, JavaScript, device dependent color without an output intent and missing or
+//! incomplete XMP metadata.
+
+use std::collections::BTreeSet;
+
+use lopdf::{Dictionary, Document, Object};
+
+/// The result of a single rule, it passed when there are no problems.
+#[derive(Debug)]
+pub struct Check {
+    pub rule: &'static str,
+    pub problems: Vec<String>,
+}
+
+impl Check {
+    pub fn passed(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Run all checks on `doc`.
+pub fn check(doc: &Document) -> Vec<Check> {
+    vec![
+        Check {
+            rule: "Not encrypted",
+            problems: encryption(doc),
+        },
+        Check {
+            rule: "All fonts embedded",
+            problems: fonts(doc),
+        },
+        Check {
+            rule: "No JavaScript",
+            problems: javascript(doc),
+        },
+        Check {
+            rule: "Device independent color or an output intent",
+            problems: color(doc),
+        },
+        Check {
+            rule: "XMP metadata with a PDF/A identification",
+            problems: metadata(doc),
+        },
+    ]
+}
+
+fn dictionaries(doc: &Document) -> impl Iterator<Item = &Dictionary> {
+    doc.objects.values().filter_map(|object| match object {
+        Object::Dictionary(dict) => Some(dict),
+        Object::Stream(stream) => Some(&stream.dict),
+        _ => None,
+    })
+}
+
+fn dictionary<'a>(doc: &'a Document, dict: &'a Dictionary, key: &[u8]) -> Option<&'a Dictionary> {
+    let (_, object) = doc.dereference(dict.get(key).ok()?).ok()?;
+    object.as_dict().ok()
+}
+
+fn encryption(doc: &Document) -> Vec<String> {
+    if doc.trailer.has(b"Encrypt") {
+        vec!["the document is encrypted".into()]
+    } else {
+        Vec::new()
+    }
+}
+
+fn fonts(doc: &Document) -> Vec<String> {
+    let mut missing = BTreeSet::new();
+
+    for font in dictionaries(doc).filter(|dict| dict.type_is(b"Font")) {
+        let subtype = font
+            .get(b"Subtype")
+            .and_then(Object::as_name)
+            .unwrap_or_default();
+
+        // Type 3 fonts are drawn by content streams in the file, composite fonts embed their
+        // descendant, which is checked on its own
+        if subtype == b"Type3" || subtype == b"Type0" {
+            continue;
+        }
+
+        let embedded = dictionary(doc, font, b"FontDescriptor").is_some_and(|descriptor| {
+            [&b"FontFile"[..], b"FontFile2", b"FontFile3"]
+                .iter()
+                .any(|key| descriptor.has(key))
+        });
+
+        if !embedded {
+            let name = font
+                .get(b"BaseFont")
+                .and_then(Object::as_name_str)
+                .unwrap_or("unnamed font");
+            missing.insert(format!("{name} is not embedded"));
+        }
+    }
+
+    missing.into_iter().collect()
+}
+
+fn javascript(doc: &Document) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    let names = doc
+        .catalog()
+        .ok()
+        .and_then(|catalog| dictionary(doc, catalog, b"Names"));
+    if names.is_some_and(|names| names.has(b"JavaScript")) {
+        problems.push("the document has document level scripts".into());
+    }
+
+    let actions = dictionaries(doc)
+        .filter(|dict| {
+            dict.get(b"S").and_then(Object::as_name).ok() == Some(b"JavaScript") || dict.has(b"JS")
+        })
+        .count();
+    if actions > 0 {
+        problems.push(format!("{actions} JavaScript actions"));
+    }
+
+    problems
+}
+
+fn color(doc: &Document) -> Vec<String> {
+    let has_output_intent = doc
+        .catalog()
+        .ok()
+        .and_then(|catalog| catalog.get(b"OutputIntents").ok())
+        .and_then(|intents| doc.dereference(intents).ok())
+        .and_then(|(_, intents)| intents.as_array().ok())
+        .is_some_and(|intents| !intents.is_empty());
+
+    if has_output_intent {
+        return Vec::new();
+    }
+
+    let mut used = BTreeSet::new();
+
+    // Color spaces of images and of the resources
+    for dict in dictionaries(doc) {
+        let spaces = dict
+            .get(b"ColorSpace")
+            .ok()
+            .map(|space| match doc.dereference(space) {
+                Ok((_, Object::Dictionary(spaces))) => spaces.iter().map(|(_, s)| s).collect(),
+                // The base of indexed and other derived color spaces
+                Ok((_, Object::Array(space))) => space.iter().collect(),
+                Ok((_, space)) => vec![space],
+                Err(_) => Vec::new(),
+            })
+            .unwrap_or_default();
+
+        for space in spaces {
+            if let Ok(name @ (b"DeviceRGB" | b"DeviceCMYK")) = space.as_name() {
+                used.insert(String::from_utf8_lossy(name).into_owned());
+            }
+        }
+    }
+
+    // Colors set directly in the content of the pages
+    for (_, page_id) in doc.get_pages() {
+        let Ok(content) = doc.get_and_decode_page_content(page_id) else {
+            continue;
+        };
+
+        for operation in content.operations {
+            match operation.operator.as_str() {
+                "rg" | "RG" => used.insert("DeviceRGB".into()),
+                "k" | "K" => used.insert("DeviceCMYK".into()),
+                _ => false,
+            };
+        }
+    }
+
+    used.into_iter()
+        .map(|space| format!("{space} is used without an output intent"))
+        .collect()
+}
+
+fn metadata(doc: &Document) -> Vec<String> {
+    let stream = doc
+        .catalog()
+        .ok()
+        .and_then(|catalog| catalog.get(b"Metadata").ok())
+        .and_then(|metadata| doc.dereference(metadata).ok())
+        .and_then(|(_, metadata)| metadata.as_stream().ok());
+
+    let Some(stream) = stream else {
+        return vec!["the document has no XMP metadata".into()];
+    };
+
+    let xmp = stream
+        .decompressed_content()
+        .unwrap_or_else(|_| stream.content.clone());
+    let xmp = String::from_utf8_lossy(&xmp);
+
+    let mut problems = Vec::new();
+    if !xmp.contains("<rdf:RDF") || !xmp.contains("</rdf:RDF>") {
+        problems.push("the metadata is not an XMP packet".into());
+    } else if !xmp.contains("pdfaid:part") {
+        problems.push("the metadata does not say which PDF/A part the file conforms to".into());
+    }
+
+    problems
+}
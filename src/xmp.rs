@@ -0,0 +1,110 @@
+//! XMP metadata, the XML packet in the `/Metadata` stream of the catalog that modern readers use
+//! instead of the document information dictionary.
+//!
+//! Packets are not parsed as XML, only the few properties that are combined when merging are
+//! looked up, both in their element and their attribute form.
+
+use std::collections::BTreeMap;
+
+use itertools::Itertools;
+use lopdf::{dictionary, Object, ObjectId, Stream};
+use regex::Regex;
+
+/// The XMP packet of the catalog `catalog`, if it has one.
+pub fn catalog_xmp(objects: &BTreeMap<ObjectId, Object>, catalog: ObjectId) -> Option<String> {
+    let stream = crate::catalog::catalog_entry(objects, catalog, b"Metadata")?
+        .as_stream()
+        .ok()?;
+    let content = stream
+        .decompressed_content()
+        .unwrap_or_else(|_| stream.content.clone());
+
+    Some(String::from_utf8_lossy(&content).into_owned())
+}
+
+/// The text of the `rdf:li` items of the array property `name`, like every `dc:creator`.
+fn items(xmp: &str, name: &str) -> Vec<String> {
+    let property = Regex::new(&format!(r"(?s)<{name}>(.*?)</{name}>")).unwrap();
+    let item = Regex::new(r"(?s)<rdf:li(?:\s[^>]*)?>(.*?)</rdf:li>").unwrap();
+
+    property
+        .captures_iter(xmp)
+        .flat_map(|property| {
+            item.captures_iter(property.get(1).unwrap().as_str())
+                .map(|item| item[1].trim().to_owned())
+                .collect::<Vec<_>>()
+        })
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+/// The value of the simple property `name`, like `xmp:CreateDate`.
+fn value(xmp: &str, name: &str) -> Option<String> {
+    let element = Regex::new(&format!(r"(?s)<{name}>(.*?)</{name}>")).unwrap();
+    let attribute = Regex::new(&format!(r#"{name}\s*=\s*["']([^"']*)["']"#)).unwrap();
+
+    element
+        .captures(xmp)
+        .or_else(|| attribute.captures(xmp))
+        .map(|value| value[1].trim().to_owned())
+        .filter(|value| !value.is_empty())
+}
+
+/// Combine the XMP packets of the merged PDFs into a new one, with the longest title, every
+/// creator once and the earliest creation date. Dates are compared as written, which is the order
+/// they were created in unless they are in different time zones.
+pub fn merge_xmp(packets: &[String]) -> Stream {
+    let title = packets
+        .iter()
+        .flat_map(|xmp| items(xmp, "dc:title").into_iter().take(1))
+        .max_by_key(|title| title.chars().count());
+    let creators = packets
+        .iter()
+        .flat_map(|xmp| items(xmp, "dc:creator"))
+        .unique()
+        .collect::<Vec<_>>();
+    let created = packets
+        .iter()
+        .filter_map(|xmp| value(xmp, "xmp:CreateDate"))
+        .min();
+
+    // The values are copied as they are, so they stay escaped
+    let mut properties = String::new();
+    if let Some(title) = title {
+        properties.push_str(&format!(
+            "   <dc:title><rdf:Alt><rdf:li xml:lang=\"x-default\">{title}</rdf:li></rdf:Alt></dc:title>\n"
+        ));
+    }
+    if !creators.is_empty() {
+        properties.push_str(&format!(
+            "   <dc:creator><rdf:Seq>{}</rdf:Seq></dc:creator>\n",
+            creators
+                .iter()
+                .map(|creator| format!("<rdf:li>{creator}</rdf:li>"))
+                .join("")
+        ));
+    }
+    if let Some(created) = created {
+        properties.push_str(&format!("   <xmp:CreateDate>{created}</xmp:CreateDate>\n"));
+    }
+
+    let xmp = format!(
+        "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n\
+         <x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n \
+         <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n  \
+         <rdf:Description rdf:about=\"\" xmlns:dc=\"http://purl.org/dc/elements/1.1/\" \
+         xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\">\n\
+         {properties}  \
+         </rdf:Description>\n \
+         </rdf:RDF>\n\
+         </x:xmpmeta>\n\
+         <?xpacket end=\"w\"?>"
+    );
+
+    // Metadata stays uncompressed, so tools that don't understand PDF can still find it
+    Stream::new(
+        dictionary! { "Type" => "Metadata", "Subtype" => "XML" },
+        xmp.into_bytes(),
+    )
+    .with_compression(false)
+}
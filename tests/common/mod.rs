@@ -0,0 +1,74 @@
+//! Helpers shared by the end to end tests, which run the binary on generated PDFs.
+
+// Every test file uses a different part of this module
+#![allow(dead_code)]
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+use lopdf::{dictionary, Document, Object, Stream};
+
+/// A directory of its own for every test, so they can run at the same time. `name` has to be
+/// unique across all test files.
+pub fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("pdft-{name}-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// A PDF with a page for every content stream in `contents`, which can draw text with the
+/// Helvetica font `/F1`.
+pub fn pdf_with_pages(path: &Path, contents: &[String]) {
+    let mut doc = Document::with_version("1.5");
+    let pages_id = doc.new_object_id();
+    let font_id = doc.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type1",
+        "BaseFont" => "Helvetica",
+    });
+
+    let kids = contents
+        .iter()
+        .map(|content| {
+            let content_id = doc.add_object(Stream::new(dictionary! {}, content.clone().into()));
+            doc.add_object(dictionary! {
+                "Type" => "Page",
+                "Parent" => pages_id,
+                "MediaBox" => vec![0.into(), 0.into(), 612.into(), 792.into()],
+                "Contents" => content_id,
+                "Resources" => dictionary! { "Font" => dictionary! { "F1" => font_id } },
+            })
+            .into()
+        })
+        .collect::<Vec<Object>>();
+    doc.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Count" => kids.len() as i64,
+            "Kids" => kids,
+        }),
+    );
+
+    let catalog_id = doc.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+    doc.trailer.set("Root", catalog_id);
+    doc.save(path).unwrap();
+}
+
+/// Run pdft with `args`, whether it succeeds or not.
+pub fn run_pdft(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_pdft"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// Run pdft with `args`, which has to succeed.
+pub fn pdft(args: &[&str]) {
+    let output = run_pdft(args);
+    assert!(
+        output.status.success(),
+        "pdft {args:?} failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
@@ -0,0 +1,58 @@
+//! End to end tests of `pdft crop`, running the binary on generated PDFs.
+
+mod common;
+
+use std::path::Path;
+
+use lopdf::{Document, Object};
+
+use common::{pdf_with_pages, pdft, test_dir};
+
+/// A PDF with a filled square on every page, at the given bottom left corners.
+fn squares_pdf(path: &Path, corners: &[(i64, i64)]) {
+    let contents = corners
+        .iter()
+        .map(|(x, y)| format!("0 0 0 rg {x} {y} 100 100 re f"))
+        .collect::<Vec<_>>();
+    pdf_with_pages(path, &contents);
+}
+
+#[test]
+fn crop_removes_pages_left_empty() {
+    let dir = test_dir("crop-empty");
+    let (input, output) = (dir.join("in.pdf"), dir.join("out.pdf"));
+    // The first and last square are inside the crop area, the one in the middle isn't
+    squares_pdf(&input, &[(100, 100), (400, 600), (120, 80)]);
+
+    pdft(&[
+        "crop",
+        input.to_str().unwrap(),
+        "--box",
+        "50,50,300,300",
+        "--remove-empty-pages",
+        "-o",
+        output.to_str().unwrap(),
+    ]);
+
+    let doc = Document::load(&output).unwrap();
+    let pages = doc.get_pages();
+    assert_eq!(pages.len(), 2);
+
+    for page_id in pages.values() {
+        let crop_box = doc
+            .get_dictionary(*page_id)
+            .unwrap()
+            .get(b"CropBox")
+            .and_then(Object::as_array)
+            .unwrap()
+            .iter()
+            .map(|n| n.as_float().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(crop_box, [50.0, 50.0, 300.0, 300.0]);
+
+        let content = doc.get_page_content(*page_id).unwrap();
+        assert!(!String::from_utf8_lossy(&content).contains("400 600"));
+    }
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,79 @@
+//! End to end tests of `pdft encrypt`, running the binary on generated PDFs.
+
+mod common;
+
+use common::{pdf_with_pages, pdft, run_pdft, test_dir};
+
+#[test]
+fn deterministic_doesnt_fix_the_encryption_key() {
+    let dir = test_dir("encrypt-deterministic");
+    let input = dir.join("in.pdf");
+    // Without an identifier of its own, encrypt has to make one
+    pdf_with_pages(
+        &input,
+        &["BT /F1 24 Tf 72 700 Td (secret) Tj ET".to_owned()],
+    );
+
+    let encrypt = |output: &str, flag: &str| {
+        let output = dir.join(output);
+        pdft(&[
+            flag,
+            "encrypt",
+            input.to_str().unwrap(),
+            "--password",
+            "x",
+            "-o",
+            output.to_str().unwrap(),
+        ]);
+        std::fs::read(output).unwrap()
+    };
+
+    assert_ne!(
+        encrypt("first.pdf", "--deterministic"),
+        encrypt("second.pdf", "--deterministic")
+    );
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn commands_refuse_encrypted_inputs() {
+    let dir = test_dir("encrypt-refused");
+    let plain = dir.join("plain.pdf");
+    let encrypted = dir.join("encrypted.pdf");
+    pdf_with_pages(
+        &plain,
+        &["BT /F1 24 Tf 72 700 Td (secret) Tj ET".to_owned()],
+    );
+    pdft(&[
+        "encrypt",
+        plain.to_str().unwrap(),
+        "--password",
+        "x",
+        "-o",
+        encrypted.to_str().unwrap(),
+    ]);
+
+    let encrypted = encrypted.to_str().unwrap();
+    let output = dir.join("out.pdf");
+    let output = output.to_str().unwrap();
+    for args in [
+        &["merge", encrypted, plain.to_str().unwrap(), "-o", output][..],
+        &["compress", encrypted],
+        &["rotate", encrypted, "--angle", "90", "-o", output],
+        &["extract", encrypted, "-p", "1", "-o", output],
+    ] {
+        let result = run_pdft(&[&["--errors-json"], args].concat());
+        assert!(!result.status.success(), "{args:?} succeeded");
+        let stderr = String::from_utf8_lossy(&result.stderr);
+        assert!(
+            stderr.contains(r#""kind":"encrypted""#),
+            "{args:?}: {stderr}"
+        );
+    }
+
+    // The commands for encrypted files still read it
+    pdft(&["info", encrypted]);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,272 @@
+//! End to end tests of `pdft merge`, running the binary on generated PDFs.
+
+mod common;
+
+use std::path::Path;
+
+use lopdf::{dictionary, Document, Object, ObjectId, Stream};
+
+use common::{pdft, test_dir};
+
+/// A one page PDF with a text note that has a popup, and a link that has an appearance stream.
+fn annotated_pdf(path: &Path, label: &str) {
+    let mut doc = Document::with_version("1.5");
+    let pages_id = doc.new_object_id();
+
+    let content = format!("BT /F1 24 Tf 72 700 Td ({label}) Tj ET");
+    let content_id = doc.add_object(Stream::new(dictionary! {}, content.into_bytes()));
+    let font_id = doc.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type1",
+        "BaseFont" => "Helvetica",
+    });
+    let page_id = doc.new_object_id();
+
+    let note_id = doc.new_object_id();
+    let popup_id = doc.add_object(dictionary! {
+        "Type" => "Annot",
+        "Subtype" => "Popup",
+        "Rect" => vec![200.into(), 600.into(), 400.into(), 700.into()],
+        "Parent" => note_id,
+        "P" => page_id,
+    });
+    doc.objects.insert(
+        note_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Annot",
+            "Subtype" => "Text",
+            "Rect" => vec![100.into(), 600.into(), 120.into(), 620.into()],
+            "Contents" => Object::string_literal(format!("note on {label}")),
+            "Popup" => popup_id,
+            "P" => page_id,
+        }),
+    );
+
+    let appearance_id = doc.add_object(Stream::new(
+        dictionary! {
+            "Type" => "XObject",
+            "Subtype" => "Form",
+            "BBox" => vec![0.into(), 0.into(), 200.into(), 20.into()],
+        },
+        b"0 0 1 RG 0 0 200 20 re S".to_vec(),
+    ));
+    let link_id = doc.add_object(dictionary! {
+        "Type" => "Annot",
+        "Subtype" => "Link",
+        "Rect" => vec![72.into(), 690.into(), 272.into(), 710.into()],
+        "A" => dictionary! { "S" => "URI", "URI" => Object::string_literal("https://example.com") },
+        "AP" => dictionary! { "N" => appearance_id },
+        "P" => page_id,
+    });
+
+    doc.objects.insert(
+        page_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "MediaBox" => vec![0.into(), 0.into(), 612.into(), 792.into()],
+            "Contents" => content_id,
+            "Resources" => dictionary! { "Font" => dictionary! { "F1" => font_id } },
+            "Annots" => vec![note_id.into(), popup_id.into(), link_id.into()],
+        }),
+    );
+    doc.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => vec![page_id.into()],
+            "Count" => 1,
+        }),
+    );
+
+    let catalog_id = doc.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+    doc.trailer.set("Root", catalog_id);
+    doc.save(path).unwrap();
+}
+
+fn annotations(doc: &Document, page_id: ObjectId) -> Vec<(ObjectId, &lopdf::Dictionary)> {
+    doc.get_dictionary(page_id)
+        .unwrap()
+        .get(b"Annots")
+        .and_then(Object::as_array)
+        .unwrap()
+        .iter()
+        .map(|annot| {
+            let id = annot.as_reference().unwrap();
+            (id, doc.get_dictionary(id).unwrap())
+        })
+        .collect()
+}
+
+#[test]
+fn merge_keeps_annotations() {
+    let dir = test_dir("merge-annotations");
+    let (a, b, merged) = (dir.join("a.pdf"), dir.join("b.pdf"), dir.join("merged.pdf"));
+    annotated_pdf(&a, "first");
+    annotated_pdf(&b, "second");
+
+    pdft(&[
+        "merge",
+        a.to_str().unwrap(),
+        b.to_str().unwrap(),
+        "-o",
+        merged.to_str().unwrap(),
+    ]);
+
+    let doc = Document::load(&merged).unwrap();
+    let pages = doc.get_pages();
+    assert_eq!(pages.len(), 2);
+
+    for (page, page_id) in pages {
+        let annots = annotations(&doc, page_id);
+        assert_eq!(annots.len(), 3, "page {page} lost annotations");
+
+        for (_, annot) in &annots {
+            assert_eq!(
+                annot.get(b"P").and_then(Object::as_reference).unwrap(),
+                page_id
+            );
+        }
+
+        let subtype =
+            |annot: &lopdf::Dictionary| annot.get(b"Subtype").unwrap().as_name().unwrap().to_vec();
+        let (note_id, note) = annots.iter().find(|(_, a)| subtype(a) == b"Text").unwrap();
+        let expected = if page == 1 {
+            "note on first"
+        } else {
+            "note on second"
+        };
+        assert_eq!(
+            note.get(b"Contents").unwrap().as_str().unwrap(),
+            expected.as_bytes()
+        );
+
+        // The note and its popup still point at each other
+        let popup_id = note.get(b"Popup").and_then(Object::as_reference).unwrap();
+        let popup = doc.get_dictionary(popup_id).unwrap();
+        assert_eq!(
+            popup.get(b"Parent").and_then(Object::as_reference).unwrap(),
+            *note_id
+        );
+        assert!(annots.iter().any(|(id, _)| *id == popup_id));
+
+        // The appearance of the link is carried along
+        let (_, link) = annots.iter().find(|(_, a)| subtype(a) == b"Link").unwrap();
+        let appearance = link
+            .get(b"AP")
+            .and_then(Object::as_dict)
+            .and_then(|ap| ap.get(b"N"))
+            .and_then(Object::as_reference)
+            .unwrap();
+        let appearance = doc
+            .get_object(appearance)
+            .and_then(Object::as_stream)
+            .unwrap();
+        assert!(appearance.dict.has(b"BBox"));
+    }
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn merge_deterministic_is_reproducible() {
+    let dir = test_dir("merge-deterministic");
+    let (a, b) = (dir.join("a.pdf"), dir.join("b.pdf"));
+    annotated_pdf(&a, "first");
+    annotated_pdf(&b, "second");
+
+    let merge = |output: &str| {
+        let output = dir.join(output);
+        pdft(&[
+            "merge",
+            a.to_str().unwrap(),
+            b.to_str().unwrap(),
+            "-o",
+            output.to_str().unwrap(),
+            "--deterministic",
+        ]);
+        std::fs::read(output).unwrap()
+    };
+
+    assert_eq!(merge("first.pdf"), merge("second.pdf"));
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn merge_deterministic_id_depends_on_metadata() {
+    let dir = test_dir("merge-deterministic-metadata");
+    let (a, b) = (dir.join("a.pdf"), dir.join("b.pdf"));
+    annotated_pdf(&a, "first");
+    annotated_pdf(&b, "second");
+
+    let merge = |inputs: [&Path; 2], output: &str| {
+        let output = dir.join(output);
+        pdft(&[
+            "merge",
+            inputs[0].to_str().unwrap(),
+            inputs[1].to_str().unwrap(),
+            "-o",
+            output.to_str().unwrap(),
+            "--deterministic",
+            "--metadata",
+            "first",
+        ]);
+        Document::load(output)
+            .unwrap()
+            .trailer
+            .get(b"ID")
+            .unwrap()
+            .clone()
+    };
+    let plain = merge([&a, &b], "plain.pdf");
+
+    // The same pages, with a title
+    for input in [&a, &b] {
+        let mut doc = Document::load(input).unwrap();
+        let info_id = doc.add_object(dictionary! { "Title" => Object::string_literal("Report") });
+        doc.trailer.set("Info", info_id);
+        doc.save(input).unwrap();
+    }
+    let titled = merge([&a, &b], "titled.pdf");
+
+    assert_ne!(format!("{plain:?}"), format!("{titled:?}"));
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn merge_drops_the_info_of_other_inputs() {
+    let dir = test_dir("merge-metadata");
+    let (a, b) = (dir.join("a.pdf"), dir.join("b.pdf"));
+    for (input, title) in [(&a, "Alpha report"), (&b, "Beta report")] {
+        annotated_pdf(input, "page");
+        let mut doc = Document::load(input).unwrap();
+        let info_id = doc.add_object(dictionary! { "Title" => Object::string_literal(title) });
+        doc.trailer.set("Info", info_id);
+        doc.save(input).unwrap();
+    }
+
+    let merge = |extra: &[&str], output: &str| {
+        let output = dir.join(output);
+        let mut args = vec![
+            "merge",
+            a.to_str().unwrap(),
+            b.to_str().unwrap(),
+            "-o",
+            output.to_str().unwrap(),
+        ];
+        args.extend_from_slice(extra);
+        pdft(&args);
+        String::from_utf8_lossy(&std::fs::read(output).unwrap()).into_owned()
+    };
+
+    let first = merge(&["--metadata", "first"], "first.pdf");
+    assert!(first.contains("(Alpha report)"));
+    assert!(!first.contains("(Beta report)"));
+
+    let none = merge(&[], "none.pdf");
+    assert!(!none.contains("Alpha report") && !none.contains("Beta report"));
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
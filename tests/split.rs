@@ -0,0 +1,58 @@
+//! End to end tests of `pdft split`, running the binary on generated PDFs.
+
+mod common;
+
+use std::path::Path;
+
+use lopdf::Document;
+
+use common::{pdf_with_pages, pdft, test_dir};
+
+/// A PDF with `pages` pages that show their own page number.
+fn numbered_pdf(path: &Path, pages: u32) {
+    let contents = (1..=pages)
+        .map(|page| format!("BT /F1 24 Tf 72 700 Td (page {page}) Tj ET"))
+        .collect::<Vec<_>>();
+    pdf_with_pages(path, &contents);
+}
+
+/// The page numbers the pages of the PDF `path` show.
+fn shown_pages(path: &Path) -> Vec<u32> {
+    let doc = Document::load(path).unwrap();
+    doc.get_pages()
+        .into_values()
+        .map(|page_id| {
+            let content = doc.get_page_content(page_id).unwrap();
+            let content = String::from_utf8_lossy(&content);
+            let start = content.find("(page ").unwrap() + "(page ".len();
+            let end = start + content[start..].find(')').unwrap();
+            content[start..end].parse().unwrap()
+        })
+        .collect()
+}
+
+#[test]
+fn split_writes_a_part_per_range() {
+    let dir = test_dir("split-ranges");
+    let input = dir.join("book.pdf");
+    let out = dir.join("parts");
+    numbered_pdf(&input, 6);
+
+    // Unsorted and overlapping ranges are written as they are given
+    pdft(&[
+        "split",
+        input.to_str().unwrap(),
+        "--ranges",
+        "4-,1-2,2-3",
+        "--out",
+        out.to_str().unwrap(),
+        "--mkdir",
+    ]);
+
+    assert_eq!(shown_pages(&out.join("book_1.pdf")), [4, 5, 6]);
+    assert_eq!(shown_pages(&out.join("book_2.pdf")), [1, 2]);
+    assert_eq!(shown_pages(&out.join("book_3.pdf")), [2, 3]);
+    assert_eq!(std::fs::read_dir(&out).unwrap().count(), 3);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}